@@ -0,0 +1,673 @@
+//! A derive macro generating the boilerplate that makes an entity reposable in a `MongoRepo`.
+
+use proc_macro::TokenStream;
+use proc_macro2::{Span, TokenStream as TokenStream2};
+use quote::{format_ident, quote};
+use syn::{
+    parse_macro_input, Data, DeriveInput, Error, Field, Fields, GenericArgument, Ident, LitStr,
+    Path, PathArguments, Type, Visibility,
+};
+
+/// Derives `Entity`, `Reposable` and `MongoReposable` for an entity struct, along with a spec, a
+/// patch and a filter type named after the entity, e.g. `ItemSpec`, `ItemPatch` and `ItemFilter`
+/// for `Item`.
+///
+/// The entity is configured with a `#[reposable(...)]` attribute:
+/// * `db = "..."` - the default name of the database the entity is stored in (required)
+/// * `collection = "..."` - the default name of the collection the entity is stored in (required)
+/// * `key = "..."` - the type of the entity's key; `ObjectId` by default
+/// * `json_schema = "..."` - the path of a function returning the entity's `$jsonSchema`
+/// * `id_strategy = "..."` - the `IdStrategy` variant used to generate IDs, which must generate
+///   keys of the entity's key type; `Server` by default, which generates `ObjectId`s
+///
+/// and its fields with `#[reposable(...)]` attributes:
+/// * `id` - marks the entity's ID; otherwise the field named `id` is used
+/// * `skip_spec` - leaves the field out of the spec, e.g. because it is only ever set by updates
+/// * `filter` - allows entities to be filtered by the field's value
+/// * `filter = "..."` - allows entities to be filtered by the field with a condition of the given
+///   type, e.g. `ArrayFilter<Tag>`
+/// * `patch = "..."` - changes the field with a patch of the given type, e.g. `ArrayPatch<Tag>`,
+///   rather than by setting it
+/// * `geo` - indexes the field, a GeoJSON geometry, for geospatial queries
+///
+/// Every other field is in the spec, and every field but the ID is in the patch. In the patch,
+/// optional fields can be cleared. Optional fields, and those deserialized with
+/// `#[serde(default)]`, can be left out when creating a spec.
+#[proc_macro_derive(Reposable, attributes(reposable))]
+pub fn derive_reposable(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand(input)
+        .unwrap_or_else(Error::into_compile_error)
+        .into()
+}
+
+/// The configuration of the entity.
+struct EntityOptions {
+    db: LitStr,
+    collection: LitStr,
+    key: Type,
+    json_schema: Option<Path>,
+    id_strategy: Option<Ident>,
+}
+
+/// A field of the entity, other than its ID.
+struct EntityField {
+    ident: Ident,
+    ty: Type,
+    rename: Option<LitStr>,
+    default: bool,
+    skip_spec: bool,
+    filter: bool,
+    filter_type: Option<Type>,
+    patch_type: Option<Type>,
+    geo: bool,
+}
+
+impl EntityField {
+    /// Returns the type wrapped by the field's type, if it is an `Option`.
+    fn optional_type(&self) -> Option<&Type> {
+        option_inner_type(&self.ty)
+    }
+
+    fn serde_rename(&self) -> TokenStream2 {
+        match self.rename {
+            Some(ref rename) => quote! { #[serde(rename = #rename)] },
+            None => quote! {},
+        }
+    }
+
+    /// Returns the name the field is stored with.
+    fn stored_name(&self) -> String {
+        match self.rename {
+            Some(ref rename) => rename.value(),
+            None => self.ident.to_string(),
+        }
+    }
+}
+
+fn expand(input: DeriveInput) -> syn::Result<TokenStream2> {
+    if !input.generics.params.is_empty() {
+        return Err(Error::new_spanned(
+            &input.generics,
+            "reposable entities cannot be generic",
+        ));
+    }
+    let options = entity_options(&input)?;
+    let named_fields = match input.data {
+        Data::Struct(ref data) => match data.fields {
+            Fields::Named(ref fields) => &fields.named,
+            _ => return Err(Error::new_spanned(&input, "expected named fields")),
+        },
+        _ => return Err(Error::new_spanned(&input, "expected a struct")),
+    };
+
+    let mut id = None;
+    let mut fields = vec![];
+    for field in named_fields {
+        let (is_id, field) = entity_field(field)?;
+        match is_id {
+            true if id.is_some() => {
+                return Err(Error::new_spanned(
+                    &field.ident,
+                    "only one field can be the ID",
+                ))
+            }
+            true => id = Some(field.ident),
+            false => fields.push(field),
+        }
+    }
+    let id = match id {
+        Some(id) => id,
+        None => {
+            let position = fields
+                .iter()
+                .position(|field| field.ident == "id")
+                .ok_or_else(|| Error::new_spanned(&input.ident, "expected an `id` field"))?;
+            fields.remove(position).ident
+        }
+    };
+
+    let entity = &input.ident;
+    let vis = &input.vis;
+    let spec = format_ident!("{}Spec", entity);
+    let patch = format_ident!("{}Patch", entity);
+    let filter = format_ident!("{}Filter", entity);
+
+    let impls = expand_impls(entity, &id, &spec, &patch, &filter, &options, &fields);
+    let spec_type = expand_spec(vis, entity, &spec, &fields);
+    let patch_type = expand_patch(vis, entity, &patch, &fields);
+    let filter_type = expand_filter(vis, entity, &filter, &fields);
+
+    Ok(quote! {
+        #impls
+        #spec_type
+        #patch_type
+        #filter_type
+    })
+}
+
+fn expand_impls(
+    entity: &Ident,
+    id: &Ident,
+    spec: &Ident,
+    patch: &Ident,
+    filter: &Ident,
+    options: &EntityOptions,
+    fields: &[EntityField],
+) -> TokenStream2 {
+    let EntityOptions {
+        db,
+        collection,
+        key,
+        json_schema,
+        id_strategy,
+    } = options;
+    let json_schema = json_schema.as_ref().map(|json_schema| {
+        quote! {
+            fn json_schema() -> ::std::option::Option<::mongodb::bson::Document> {
+                ::std::option::Option::Some(#json_schema())
+            }
+        }
+    });
+    // an entity whose ID strategy generates keys of another type would fail on every creation
+    let strategy = match id_strategy {
+        Some(id_strategy) => id_strategy.clone(),
+        None => Ident::new("Server", Span::call_site()),
+    };
+    let key_check = quote! {
+        const _: fn() = ::mongo_repo::storage::mongo_repo::assert_generated_key::<
+            #key,
+            ::mongo_repo::storage::mongo_repo::id_strategies::#strategy,
+        >;
+    };
+    let id_strategy = id_strategy.as_ref().map(|id_strategy| {
+        quote! {
+            fn id_strategy() -> ::mongo_repo::storage::mongo_repo::IdStrategy {
+                ::mongo_repo::storage::mongo_repo::IdStrategy::#id_strategy
+            }
+        }
+    });
+
+    let geo_fields: Vec<_> = fields
+        .iter()
+        .filter(|field| field.geo)
+        .map(EntityField::stored_name)
+        .collect();
+    let geo_fields = (!geo_fields.is_empty()).then(|| {
+        quote! {
+            fn geo_fields() -> &'static [&'static str] {
+                &[#(#geo_fields),*]
+            }
+        }
+    });
+
+    quote! {
+        impl ::mongo_repo::common::entity::Entity for #entity {
+            type Key = #key;
+
+            fn id(&self) -> &::mongo_repo::common::id::Id<#entity> {
+                &self.#id
+            }
+        }
+
+        impl ::mongo_repo::storage::repo::Reposable for #entity {
+            type Spec = #spec;
+            type Patch = #patch;
+            type Filter = #filter;
+        }
+
+        impl ::mongo_repo::storage::mongo_repo::MongoReposable for #entity {
+            fn db_name() -> &'static str {
+                #db
+            }
+
+            fn collection_name() -> &'static str {
+                #collection
+            }
+
+            #json_schema
+            #id_strategy
+            #geo_fields
+        }
+
+        #key_check
+    }
+}
+
+fn expand_spec(
+    vis: &Visibility,
+    entity: &Ident,
+    spec: &Ident,
+    fields: &[EntityField],
+) -> TokenStream2 {
+    let fields = fields
+        .iter()
+        .filter(|field| !field.skip_spec)
+        .collect::<Vec<_>>();
+    let doc = format!("A specification for creating a new [`{entity}`].");
+
+    let declarations = fields.iter().map(|field| {
+        let EntityField { ident, ty, .. } = field;
+        let rename = field.serde_rename();
+        let skip = field.optional_type().map(|_| {
+            quote! { #[serde(skip_serializing_if = "::std::option::Option::is_none")] }
+        });
+        quote! { #rename #skip #ident: #ty }
+    });
+    let required = fields
+        .iter()
+        .filter(|field| field.optional_type().is_none() && !field.default)
+        .collect::<Vec<_>>();
+    let parameters = required.iter().map(|field| {
+        let EntityField { ident, ty, .. } = field;
+        quote! { #ident: #ty }
+    });
+    let initializers = fields.iter().map(|field| {
+        let ident = &field.ident;
+        match field.optional_type() {
+            Some(_) => quote! { #ident: ::std::option::Option::None },
+            None if field.default => quote! { #ident: ::std::default::Default::default() },
+            None => quote! { #ident },
+        }
+    });
+    let accessors = fields
+        .iter()
+        .map(|field| accessors(&field.ident, &field.ty));
+
+    quote! {
+        #[doc = #doc]
+        #[derive(::serde::Serialize)]
+        #vis struct #spec {
+            #(#declarations,)*
+        }
+
+        impl #spec {
+            pub fn new(#(#parameters),*) -> Self {
+                Self {
+                    #(#initializers,)*
+                }
+            }
+
+            #(#accessors)*
+        }
+    }
+}
+
+fn expand_patch(
+    vis: &Visibility,
+    entity: &Ident,
+    patch: &Ident,
+    fields: &[EntityField],
+) -> TokenStream2 {
+    let doc = format!("An update to an existing [`{entity}`].");
+    let patched_types = fields
+        .iter()
+        .map(|field| match (&field.patch_type, field.optional_type()) {
+            (Some(ty), _) => quote! { ::std::option::Option<#ty> },
+            (None, Some(ty)) => quote! { ::mongo_repo::storage::patch::FieldPatch<#ty> },
+            (None, None) => {
+                let ty = &field.ty;
+                quote! { ::std::option::Option<#ty> }
+            }
+        })
+        .collect::<Vec<_>>();
+
+    let declarations = fields.iter().zip(&patched_types).map(|(field, ty)| {
+        let ident = &field.ident;
+        let rename = field.serde_rename();
+        let skip = match field.optional_type().filter(|_| field.patch_type.is_none()) {
+            Some(_) => quote! {
+                #[serde(skip_serializing_if = "::mongo_repo::storage::patch::FieldPatch::is_unchanged")]
+            },
+            None => quote! { #[serde(skip_serializing_if = "::std::option::Option::is_none")] },
+        };
+        quote! { #rename #skip #ident: #ty }
+    });
+    let initializers = fields.iter().map(|field| {
+        let ident = &field.ident;
+        quote! { #ident: ::std::default::Default::default() }
+    });
+    // only custom patch types can make changes that are not idempotent
+    let idempotence_checks = fields
+        .iter()
+        .filter(|field| field.patch_type.is_some())
+        .map(|field| {
+            let ident = &field.ident;
+            quote! {
+                self.#ident.as_ref().map_or(true, ::mongo_repo::storage::patch::FieldUpdate::is_idempotent)
+            }
+        });
+    let accessors = fields.iter().zip(&patched_types).map(|(field, ty)| {
+        let ty = syn::parse2::<Type>(ty.clone()).expect("patched types are valid types");
+        accessors(&field.ident, &ty)
+    });
+
+    quote! {
+        #[doc = #doc]
+        #[derive(::serde::Serialize)]
+        #vis struct #patch {
+            #[serde(skip)]
+            id: ::mongo_repo::common::id::Id<#entity>,
+            #(#declarations,)*
+        }
+
+        impl #patch {
+            pub fn new(id: ::mongo_repo::common::id::Id<#entity>) -> Self {
+                Self {
+                    id,
+                    #(#initializers,)*
+                }
+            }
+
+            #(#accessors)*
+        }
+
+        impl ::mongo_repo::storage::repo::Patch<#entity> for #patch {
+            fn id(&self) -> &::mongo_repo::common::id::Id<#entity> {
+                &self.id
+            }
+
+            fn is_idempotent(&self) -> bool {
+                true #(&& #idempotence_checks)*
+            }
+        }
+    }
+}
+
+fn expand_filter(
+    vis: &Visibility,
+    entity: &Ident,
+    filter: &Ident,
+    fields: &[EntityField],
+) -> TokenStream2 {
+    let fields = fields
+        .iter()
+        .filter(|field| field.filter)
+        .collect::<Vec<_>>();
+    let doc = format!("Selects the [`{entity}`]s that match every field that is present.");
+    let filtered_types = fields
+        .iter()
+        .map(|field| {
+            let ty = field
+                .filter_type
+                .as_ref()
+                .or(field.optional_type())
+                .unwrap_or(&field.ty);
+            syn::parse_quote! { ::std::option::Option<#ty> }
+        })
+        .collect::<Vec<Type>>();
+
+    let declarations = fields.iter().zip(&filtered_types).map(|(field, ty)| {
+        let ident = &field.ident;
+        let rename = field.serde_rename();
+        quote! {
+            #rename
+            #[serde(skip_serializing_if = "::std::option::Option::is_none")]
+            #ident: #ty
+        }
+    });
+    let accessors = fields
+        .iter()
+        .zip(&filtered_types)
+        .map(|(field, ty)| accessors(&field.ident, ty));
+
+    quote! {
+        #[doc = #doc]
+        #[derive(::std::default::Default, ::serde::Serialize)]
+        #vis struct #filter {
+            #[serde(rename = "_id", skip_serializing_if = "::std::option::Option::is_none")]
+            id: ::std::option::Option<::mongo_repo::common::id::Id<#entity>>,
+            #(#declarations,)*
+        }
+
+        impl #filter {
+            pub fn id(&self) -> &::std::option::Option<::mongo_repo::common::id::Id<#entity>> {
+                &self.id
+            }
+
+            #(#accessors)*
+        }
+
+        impl ::mongo_repo::storage::repo::Filter<#entity> for #filter {
+            fn id_mut(
+                &mut self,
+            ) -> &mut ::std::option::Option<::mongo_repo::common::id::Id<#entity>> {
+                &mut self.id
+            }
+        }
+    }
+}
+
+/// Generates a getter and a mutable getter for a field.
+fn accessors(ident: &Ident, ty: &Type) -> TokenStream2 {
+    let ident_mut = format_ident!("{}_mut", ident);
+    quote! {
+        pub fn #ident(&self) -> &#ty {
+            &self.#ident
+        }
+
+        pub fn #ident_mut(&mut self) -> &mut #ty {
+            &mut self.#ident
+        }
+    }
+}
+
+fn entity_options(input: &DeriveInput) -> syn::Result<EntityOptions> {
+    let mut db = None;
+    let mut collection = None;
+    let mut key = None;
+    let mut json_schema = None;
+    let mut id_strategy = None;
+    for attr in input
+        .attrs
+        .iter()
+        .filter(|attr| attr.path().is_ident("reposable"))
+    {
+        attr.parse_nested_meta(|meta| {
+            let value = meta.value()?.parse::<LitStr>()?;
+            if meta.path.is_ident("db") {
+                db = Some(value);
+            } else if meta.path.is_ident("collection") {
+                collection = Some(value);
+            } else if meta.path.is_ident("key") {
+                key = Some(value.parse()?);
+            } else if meta.path.is_ident("json_schema") {
+                json_schema = Some(value.parse()?);
+            } else if meta.path.is_ident("id_strategy") {
+                id_strategy = Some(value.parse()?);
+            } else {
+                return Err(meta.error("unknown reposable option"));
+            }
+            Ok(())
+        })?;
+    }
+    let missing = |name: &str| {
+        Error::new(
+            Span::call_site(),
+            format!("expected #[reposable({name} = \"...\")]"),
+        )
+    };
+    Ok(EntityOptions {
+        db: db.ok_or_else(|| missing("db"))?,
+        collection: collection.ok_or_else(|| missing("collection"))?,
+        key: key.unwrap_or_else(|| syn::parse_quote! { ::mongodb::bson::oid::ObjectId }),
+        json_schema,
+        id_strategy,
+    })
+}
+
+/// Reads a field and its options, returning whether it is the entity's ID.
+fn entity_field(field: &Field) -> syn::Result<(bool, EntityField)> {
+    let mut is_id = false;
+    let mut entity_field = EntityField {
+        ident: field.ident.clone().expect("fields are named"),
+        ty: field.ty.clone(),
+        rename: None,
+        default: false,
+        skip_spec: false,
+        filter: false,
+        filter_type: None,
+        patch_type: None,
+        geo: false,
+    };
+    for attr in &field.attrs {
+        if attr.path().is_ident("reposable") {
+            attr.parse_nested_meta(|meta| {
+                if meta.path.is_ident("id") {
+                    is_id = true;
+                } else if meta.path.is_ident("skip_spec") {
+                    entity_field.skip_spec = true;
+                } else if meta.path.is_ident("filter") {
+                    entity_field.filter = true;
+                    if meta.input.peek(syn::Token![=]) {
+                        entity_field.filter_type = Some(meta.value()?.parse::<LitStr>()?.parse()?);
+                    }
+                } else if meta.path.is_ident("patch") {
+                    entity_field.patch_type = Some(meta.value()?.parse::<LitStr>()?.parse()?);
+                } else if meta.path.is_ident("geo") {
+                    entity_field.geo = true;
+                } else {
+                    return Err(meta.error("unknown reposable field option"));
+                }
+                Ok(())
+            })?;
+        } else if attr.path().is_ident("serde") {
+            // only the name a field is serialized with carries over to the generated types, and
+            // whether it can be left out
+            attr.parse_nested_meta(|meta| {
+                if meta.path.is_ident("rename") {
+                    entity_field.rename = Some(meta.value()?.parse()?);
+                } else if meta.path.is_ident("default") {
+                    entity_field.default = true;
+                    if meta.input.peek(syn::Token![=]) {
+                        meta.value()?.parse::<syn::Expr>()?;
+                    }
+                } else if meta.input.peek(syn::Token![=]) {
+                    meta.value()?.parse::<syn::Expr>()?;
+                }
+                Ok(())
+            })?;
+        }
+    }
+    Ok((is_id, entity_field))
+}
+
+fn option_inner_type(ty: &Type) -> Option<&Type> {
+    let Type::Path(path) = ty else {
+        return None;
+    };
+    let segment = path.path.segments.last()?;
+    if segment.ident != "Option" {
+        return None;
+    }
+    match segment.arguments {
+        PathArguments::AngleBracketed(ref arguments) if arguments.args.len() == 1 => {
+            match arguments.args.first()? {
+                GenericArgument::Type(ty) => Some(ty),
+                _ => None,
+            }
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Returns the type wrapped by an `Option` as a string, if the provided type is one.
+    fn unwrapped(ty: Type) -> Option<String> {
+        option_inner_type(&ty).map(|ty| quote!(#ty).to_string())
+    }
+
+    #[test]
+    fn optional_types_are_unwrapped() {
+        let ty = syn::parse_quote! { Option<String> };
+        assert_eq!(unwrapped(ty), Some("String".to_string()));
+
+        let ty = syn::parse_quote! { ::std::option::Option<Vec<u8>> };
+        assert_eq!(unwrapped(ty), Some("Vec < u8 >".to_string()));
+    }
+
+    #[test]
+    fn required_types_are_not_unwrapped() {
+        assert_eq!(unwrapped(syn::parse_quote! { String }), None);
+        assert_eq!(unwrapped(syn::parse_quote! { Vec<Option<String>> }), None);
+    }
+
+    #[test]
+    fn entity_without_db_is_rejected() {
+        let input: DeriveInput = syn::parse_quote! {
+            #[reposable(collection = "things")]
+            struct Thing {
+                id: Id<Thing>,
+            }
+        };
+        assert!(expand(input).is_err());
+    }
+
+    #[test]
+    fn serialized_names_carry_over() {
+        let input: DeriveInput = syn::parse_quote! {
+            #[reposable(db = "test", collection = "things")]
+            struct Thing {
+                #[serde(rename = "_id")]
+                id: Id<Thing>,
+                #[serde(rename = "n", default)]
+                #[reposable(filter)]
+                name: String,
+            }
+        };
+        let expanded = expand(input).unwrap().to_string();
+        assert!(expanded.contains("rename = \"n\""));
+        assert!(expanded.contains("struct ThingFilter"));
+    }
+
+    #[test]
+    fn custom_patch_and_filter_types_are_used() {
+        let input: DeriveInput = syn::parse_quote! {
+            #[reposable(db = "test", collection = "things")]
+            struct Thing {
+                id: Id<Thing>,
+                #[serde(default)]
+                #[reposable(patch = "ArrayPatch<String>", filter = "ArrayFilter<String>")]
+                labels: Vec<String>,
+            }
+        };
+        let expanded = expand(input).unwrap().to_string();
+        assert!(expanded.contains("labels : :: std :: option :: Option < ArrayPatch < String > >"));
+        assert!(expanded.contains("labels : :: std :: option :: Option < ArrayFilter < String > >"));
+        assert!(expanded.contains("pub fn new () -> Self"));
+    }
+
+    #[test]
+    fn only_custom_patch_types_are_checked_for_idempotence() {
+        let input: DeriveInput = syn::parse_quote! {
+            #[reposable(db = "test", collection = "things")]
+            struct Thing {
+                id: Id<Thing>,
+                name: String,
+                #[reposable(patch = "Increment<i64>")]
+                count: i64,
+            }
+        };
+        let expanded = expand(input).unwrap().to_string();
+        assert!(expanded.contains("self . count . as_ref ()"));
+        assert!(!expanded.contains("self . name . as_ref ()"));
+    }
+
+    #[test]
+    fn geo_fields_are_listed_by_stored_name() {
+        let input: DeriveInput = syn::parse_quote! {
+            #[reposable(db = "test", collection = "things")]
+            struct Thing {
+                id: Id<Thing>,
+                #[serde(rename = "loc")]
+                #[reposable(geo)]
+                location: Option<GeoPoint>,
+            }
+        };
+        let expanded = expand(input).unwrap().to_string();
+        assert!(expanded.contains("fn geo_fields () -> & 'static [& 'static str] { & [\"loc\"] }"));
+    }
+}
@@ -0,0 +1,134 @@
+use crate::common::tenant::{InvalidTenantIdError, TenantId};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+use std::sync::Arc;
+
+/// The shortest API key that is accepted; shorter keys are too easily guessed.
+pub const MIN_API_KEY_LEN: usize = 16;
+
+/// The API keys issued to tenants, each of which authenticates its bearer as the tenant it was
+/// issued to.
+///
+/// Only the SHA-256 digests of the keys are kept, so looking up a key takes no longer for keys
+/// that share a prefix with one that was issued than for any other.
+#[derive(Clone, Debug, Default)]
+pub struct TenantKeys(Arc<HashMap<[u8; 32], TenantId>>);
+
+/// An error indicating tenant API keys could not be read.
+#[derive(Debug, Clone)]
+pub enum InvalidTenantKeysError {
+    /// A line is not of the form `<tenant ID>:<API key>`.
+    Malformed { line: usize },
+    /// The tenant ID on a line is not valid.
+    TenantId {
+        line: usize,
+        e: InvalidTenantIdError,
+    },
+    /// The API key on a line is shorter than `MIN_API_KEY_LEN`.
+    ShortKey { line: usize },
+    /// The API key on a line was already issued on an earlier line.
+    DuplicateKey { line: usize },
+}
+
+impl TenantKeys {
+    /// Returns the tenant the provided API key was issued to, if it was issued at all.
+    pub fn tenant_of(&self, api_key: &str) -> Option<&TenantId> {
+        self.0.get(&digest(api_key))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+/// Reads API keys from lines of the form `<tenant ID>:<API key>`; blank lines and lines starting
+/// with `#` are ignored.
+impl FromStr for TenantKeys {
+    type Err = InvalidTenantKeysError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut keys = HashMap::new();
+        for (i, line) in s.lines().enumerate() {
+            let line_number = i + 1;
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (tenant_id, api_key) = line
+                .split_once(':')
+                .ok_or(InvalidTenantKeysError::Malformed { line: line_number })?;
+            let tenant_id = tenant_id.trim().parse::<TenantId>().map_err(|e| {
+                InvalidTenantKeysError::TenantId {
+                    line: line_number,
+                    e,
+                }
+            })?;
+            let api_key = api_key.trim();
+            if api_key.len() < MIN_API_KEY_LEN {
+                return Err(InvalidTenantKeysError::ShortKey { line: line_number });
+            }
+            if keys.insert(digest(api_key), tenant_id).is_some() {
+                return Err(InvalidTenantKeysError::DuplicateKey { line: line_number });
+            }
+        }
+        Ok(Self(Arc::new(keys)))
+    }
+}
+
+fn digest(api_key: &str) -> [u8; 32] {
+    Sha256::digest(api_key.as_bytes()).into()
+}
+
+impl Error for InvalidTenantKeysError {}
+
+impl Display for InvalidTenantKeysError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Malformed { line } => {
+                write!(f, "line {line}: expected <tenant ID>:<API key>")
+            }
+            Self::TenantId { line, e } => write!(f, "line {line}: {e}"),
+            Self::ShortKey { line } => write!(
+                f,
+                "line {line}: an API key must be at least {MIN_API_KEY_LEN} characters long"
+            ),
+            Self::DuplicateKey { line } => {
+                write!(f, "line {line}: the API key was already issued")
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn keys_authenticate_the_tenant_they_were_issued_to() {
+        let keys = "# comment\nacme:0123456789abcdef\n\nglobex: fedcba9876543210\n"
+            .parse::<TenantKeys>()
+            .unwrap();
+        assert_eq!(
+            keys.tenant_of("0123456789abcdef"),
+            Some(&"acme".parse().unwrap())
+        );
+        assert_eq!(
+            keys.tenant_of("fedcba9876543210"),
+            Some(&"globex".parse().unwrap())
+        );
+        assert_eq!(keys.tenant_of("0123456789abcdeF"), None);
+    }
+
+    #[test]
+    fn invalid_keys_are_rejected() {
+        assert!("acme".parse::<TenantKeys>().is_err());
+        assert!("acme:short".parse::<TenantKeys>().is_err());
+        assert!("ac.me:0123456789abcdef".parse::<TenantKeys>().is_err());
+        assert!("acme:0123456789abcdef\nglobex:0123456789abcdef"
+            .parse::<TenantKeys>()
+            .is_err());
+    }
+}
@@ -1,34 +1,434 @@
-use crate::domain::{Domain, DomainImpl, MongoDomainContext};
+use super::{
+    auth::TenantKeys,
+    loader::{BatchCancelledError, Loader},
+};
+use crate::{
+    common::{
+        deadline::Deadline,
+        id::Id,
+        idempotency::{IdempotencyKey, InvalidIdempotencyKeyError},
+        tenant::{InvalidTenantIdError, TenantId},
+    },
+    domain::{
+        models::{items::Item, owners::Owner},
+        Domain, DomainImpl, MongoDomainContext,
+    },
+    migrations::{MigrationError, TenantMigrator},
+    storage::mongo_repo::MongoRepoError,
+};
+use hyper::{header, HeaderMap};
+use juniper::{graphql_value, FieldError};
+use std::{
+    borrow::Borrow,
+    error::Error,
+    fmt::{Display, Formatter},
+    sync::Arc,
+    time::Duration,
+};
+use tracing::warn;
+
+/// The request header identifying the tenant a request is made on behalf of. It is only trusted
+/// on its own if a trusted proxy sets it; otherwise it must agree with the tenant the request is
+/// authenticated as.
+pub const TENANT_ID_HEADER: &str = "x-tenant-id";
+
+/// The scheme with which a request is authenticated as a tenant, in its `Authorization` header.
+const BEARER_SCHEME: &str = "Bearer ";
+
+/// The request header with which a client can shorten the time allowed for its request, in
+/// milliseconds.
+pub const REQUEST_TIMEOUT_HEADER: &str = "x-request-timeout-ms";
+
+/// The request header with which a client can make the mutations in its request idempotent; it
+/// applies to every mutation in the request that is not given a `clientMutationId` of its own.
+pub const IDEMPOTENCY_KEY_HEADER: &str = "idempotency-key";
+
+/// The time allowed for a request unless configured otherwise.
+pub const DEFAULT_REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// How requests are mapped to tenants.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Tenancy {
+    /// All requests share one set of data and tenant headers are ignored.
+    Single,
+    /// Every request must be authenticated as a tenant, and each tenant's data is kept in its own
+    /// database.
+    Multi,
+}
 
 #[derive(Clone)]
 pub struct ContextFactory {
-    mongo_client: mongodb::Client,
+    domain_ctx: MongoDomainContext,
+    tenancy: Tenancy,
+    tenant_keys: TenantKeys,
+    trust_tenant_header: bool,
+    tenant_migrator: Option<TenantMigrator>,
+    request_timeout: Duration,
 }
 
 impl ContextFactory {
-    pub fn new(mongo_client: mongodb::Client) -> Self {
-        Self { mongo_client }
+    pub fn new(domain_ctx: MongoDomainContext, tenancy: Tenancy) -> Self {
+        Self {
+            domain_ctx,
+            tenancy,
+            tenant_keys: TenantKeys::default(),
+            trust_tenant_header: false,
+            tenant_migrator: None,
+            request_timeout: DEFAULT_REQUEST_TIMEOUT,
+        }
+    }
+
+    /// Returns this factory configured to authenticate requests as tenants by the API keys issued
+    /// to them, presented as `Authorization: Bearer <API key>`.
+    pub fn with_tenant_keys(mut self, tenant_keys: TenantKeys) -> Self {
+        self.tenant_keys = tenant_keys;
+        self
+    }
+
+    /// Returns this factory configured to trust the tenant header of requests that present no API
+    /// key; this is only safe behind a proxy that authenticates requests and sets the header
+    /// itself.
+    pub fn with_trusted_tenant_header(mut self, trust_tenant_header: bool) -> Self {
+        self.trust_tenant_header = trust_tenant_header;
+        self
+    }
+
+    /// Returns this factory configured to make sure each tenant's database is migrated to the
+    /// latest version before the tenant is first served.
+    pub fn with_tenant_migrator(mut self, tenant_migrator: TenantMigrator) -> Self {
+        self.tenant_migrator = Some(tenant_migrator);
+        self
+    }
+
+    /// Returns this factory configured to allow requests the provided time to complete; clients
+    /// can only shorten this.
+    pub fn with_request_timeout(mut self, request_timeout: Duration) -> Self {
+        self.request_timeout = request_timeout;
+        self
     }
 
-    pub fn create_context(&self) -> Context {
-        Context::new(self.mongo_client.clone())
+    /// Creates a context for handling a request, scoped to the request's tenant if required, and
+    /// with a deadline starting from now.
+    ///
+    /// # Arguments
+    /// * `headers` - the headers of the request to create a context for
+    pub async fn create_context(&self, headers: &HeaderMap) -> Result<Context, ContextError> {
+        let timeout = match request_timeout_from_headers(headers)? {
+            Some(timeout) => timeout.min(self.request_timeout),
+            None => self.request_timeout,
+        };
+        let deadline = Deadline::after(timeout);
+        let idempotency_key = idempotency_key_from_headers(headers)?;
+        let domain_ctx = match self.tenancy {
+            Tenancy::Single => self.domain_ctx.with_deadline(deadline),
+            Tenancy::Multi => {
+                let tenant_id =
+                    tenant_from_headers(headers, &self.tenant_keys, self.trust_tenant_header)?;
+                if let Some(tenant_migrator) = &self.tenant_migrator {
+                    tenant_migrator
+                        .ensure_migrated(&tenant_id)
+                        .await
+                        .map_err(|e| {
+                            warn!("tenant {} is not migrated: {}", tenant_id, e);
+                            ContextError::TenantNotMigrated(e)
+                        })?;
+                }
+                self.domain_ctx
+                    .for_tenant(tenant_id)
+                    .with_deadline(deadline)
+            }
+        };
+        Ok(Context::new(domain_ctx, deadline, idempotency_key))
+    }
+}
+
+/// Determines the tenant a request is made on behalf of: the tenant its API key was issued to,
+/// which its tenant header must agree with if it has one, or else the tenant in its header if
+/// that is trusted.
+fn tenant_from_headers(
+    headers: &HeaderMap,
+    tenant_keys: &TenantKeys,
+    trust_tenant_header: bool,
+) -> Result<TenantId, ContextError> {
+    let claimed = tenant_id_from_headers(headers)?;
+    match api_key_from_headers(headers)? {
+        Some(api_key) => {
+            let tenant_id = tenant_keys
+                .tenant_of(api_key)
+                .ok_or(ContextError::InvalidCredentials)?;
+            match claimed {
+                Some(claimed) if claimed != *tenant_id => Err(ContextError::TenantMismatch),
+                _ => Ok(tenant_id.clone()),
+            }
+        }
+        None if trust_tenant_header => claimed.ok_or(ContextError::MissingTenantId),
+        None => Err(ContextError::MissingCredentials),
+    }
+}
+
+fn idempotency_key_from_headers(
+    headers: &HeaderMap,
+) -> Result<Option<IdempotencyKey>, ContextError> {
+    headers
+        .get(IDEMPOTENCY_KEY_HEADER)
+        .map(|value| {
+            value
+                .to_str()
+                .map_err(|_| InvalidIdempotencyKeyError)
+                .and_then(|value| value.parse())
+                .map_err(ContextError::InvalidIdempotencyKey)
+        })
+        .transpose()
+}
+
+fn request_timeout_from_headers(headers: &HeaderMap) -> Result<Option<Duration>, ContextError> {
+    headers
+        .get(REQUEST_TIMEOUT_HEADER)
+        .map(|value| {
+            value
+                .to_str()
+                .ok()
+                .and_then(|value| value.parse::<u64>().ok())
+                .map(Duration::from_millis)
+                .ok_or(ContextError::InvalidRequestTimeout)
+        })
+        .transpose()
+}
+
+fn tenant_id_from_headers(headers: &HeaderMap) -> Result<Option<TenantId>, ContextError> {
+    headers
+        .get(TENANT_ID_HEADER)
+        .map(|value| {
+            value
+                .to_str()
+                .map_err(|_| InvalidTenantIdError)
+                .and_then(|value| value.parse())
+                .map_err(ContextError::InvalidTenantId)
+        })
+        .transpose()
+}
+
+fn api_key_from_headers(headers: &HeaderMap) -> Result<Option<&str>, ContextError> {
+    headers
+        .get(header::AUTHORIZATION)
+        .map(|value| {
+            value
+                .to_str()
+                .ok()
+                .and_then(|value| value.strip_prefix(BEARER_SCHEME))
+                .map(str::trim)
+                .ok_or(ContextError::InvalidCredentials)
+        })
+        .transpose()
+}
+
+pub type DomainError = <DomainImpl<MongoDomainContext> as Domain>::DomainError;
+
+impl From<BatchCancelledError> for MongoRepoError {
+    fn from(_: BatchCancelledError) -> Self {
+        MongoRepoError::Cancelled
     }
 }
 
 #[derive(Clone)]
 pub struct Context {
     domain: DomainImpl<MongoDomainContext>,
+    deadline: Deadline,
+    idempotency_key: Option<IdempotencyKey>,
+    items_loader: Loader<Item, DomainError>,
+    owners_loader: Loader<Owner, DomainError>,
 }
 
 impl juniper::Context for Context {}
 
 impl Context {
-    fn new(mongo_client: mongodb::Client) -> Self {
-        let domain = DomainImpl::new(MongoDomainContext::new(mongo_client));
-        Context { domain }
+    fn new(
+        domain_ctx: MongoDomainContext,
+        deadline: Deadline,
+        idempotency_key: Option<IdempotencyKey>,
+    ) -> Self {
+        let domain = DomainImpl::new(domain_ctx);
+        Context {
+            domain,
+            deadline,
+            idempotency_key,
+            items_loader: Loader::new(),
+            owners_loader: Loader::new(),
+        }
     }
 
-    pub fn domain(&self) -> &impl Domain {
+    pub fn domain(&self) -> &impl Domain<DomainError = DomainError> {
         &self.domain
     }
+
+    /// Loads an item, in one batch with the other items loaded while resolving the same level of
+    /// the query; each item is only retrieved once per request.
+    pub async fn load_item(&self, id: &Id<Item>) -> Result<Option<Item>, Arc<DomainError>> {
+        self.items_loader
+            .load(id, |ids| async move { self.domain.items(&ids).await })
+            .await
+    }
+
+    /// Loads an owner, in one batch with the other owners loaded while resolving the same level of
+    /// the query; each owner is only retrieved once per request.
+    pub async fn load_owner(&self, id: &Id<Owner>) -> Result<Option<Owner>, Arc<DomainError>> {
+        self.owners_loader
+            .load(id, |ids| async move { self.domain.owners(&ids).await })
+            .await
+    }
+
+    /// Returns the moment by which the request must be handled.
+    pub fn deadline(&self) -> Deadline {
+        self.deadline
+    }
+
+    /// Returns the idempotency key to make a mutation with: the mutation's own `clientMutationId`
+    /// if it has one, otherwise the key supplied for the whole request, if any.
+    pub fn idempotency_key(
+        &self,
+        client_mutation_id: Option<&str>,
+    ) -> Result<Option<IdempotencyKey>, InvalidIdempotencyKeyError> {
+        match client_mutation_id {
+            Some(client_mutation_id) => client_mutation_id.parse().map(Some),
+            None => Ok(self.idempotency_key.clone()),
+        }
+    }
+
+    /// Converts an error from the domain into a GraphQL error; an operation abandoned because the
+    /// request's deadline passed is reported with the code `TIMEOUT`, a change that would break an
+    /// entity's invariants with the code `INVALID_INPUT`, and a mutation whose idempotency key was
+    /// used by a concurrent request with the code `CONFLICT`, while any other error keeps its
+    /// cause, even if the deadline has since passed.
+    pub fn field_error(&self, e: impl Borrow<DomainError>) -> FieldError {
+        match e.borrow() {
+            MongoRepoError::Timeout => FieldError::new(
+                "the request did not complete before its deadline",
+                graphql_value!({ "code": "TIMEOUT" }),
+            ),
+            MongoRepoError::InvalidEntity(e) => {
+                FieldError::new(e.to_string(), graphql_value!({ "code": "INVALID_INPUT" }))
+            }
+            MongoRepoError::IdempotencyConflict => FieldError::new(
+                "a concurrent request with the same idempotency key completed first; retry to \
+                 replay its response",
+                graphql_value!({ "code": "CONFLICT" }),
+            ),
+            e => FieldError::from(e),
+        }
+    }
+}
+
+/// An error indicating a context could not be created for a request.
+#[derive(Debug)]
+pub enum ContextError {
+    MissingTenantId,
+    InvalidTenantId(InvalidTenantIdError),
+    MissingCredentials,
+    InvalidCredentials,
+    TenantMismatch,
+    InvalidRequestTimeout,
+    InvalidIdempotencyKey(InvalidIdempotencyKeyError),
+    /// The tenant's database is not at the latest version, and could not be migrated to it.
+    TenantNotMigrated(MigrationError),
+}
+
+impl Error for ContextError {}
+
+impl Display for ContextError {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        match self {
+            Self::MissingTenantId => write!(f, "the {TENANT_ID_HEADER} header is required"),
+            Self::InvalidTenantId(e) => write!(f, "invalid {TENANT_ID_HEADER} header: {e}"),
+            Self::MissingCredentials => write!(
+                f,
+                "the {} header is required, with an API key",
+                header::AUTHORIZATION
+            ),
+            Self::InvalidCredentials => write!(
+                f,
+                "invalid {} header: must be a bearer API key issued to a tenant",
+                header::AUTHORIZATION
+            ),
+            Self::TenantMismatch => write!(
+                f,
+                "the {TENANT_ID_HEADER} header does not match the tenant the API key was issued to"
+            ),
+            Self::InvalidRequestTimeout => write!(
+                f,
+                "invalid {REQUEST_TIMEOUT_HEADER} header: must be a number of milliseconds"
+            ),
+            Self::InvalidIdempotencyKey(e) => {
+                write!(f, "invalid {IDEMPOTENCY_KEY_HEADER} header: {e}")
+            }
+            Self::TenantNotMigrated(_) => write!(f, "the tenant is not available, try again later"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const ACME_KEY: &str = "0123456789abcdef";
+
+    fn tenant_keys() -> TenantKeys {
+        format!("acme:{ACME_KEY}").parse().unwrap()
+    }
+
+    fn headers(api_key: Option<&str>, tenant_id: Option<&str>) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        if let Some(api_key) = api_key {
+            let value = format!("{BEARER_SCHEME}{api_key}");
+            headers.insert(header::AUTHORIZATION, value.parse().unwrap());
+        }
+        if let Some(tenant_id) = tenant_id {
+            headers.insert(TENANT_ID_HEADER, tenant_id.parse().unwrap());
+        }
+        headers
+    }
+
+    fn tenant(headers: &HeaderMap, trust_tenant_header: bool) -> Result<String, ContextError> {
+        tenant_from_headers(headers, &tenant_keys(), trust_tenant_header)
+            .map(|tenant_id| tenant_id.to_string())
+    }
+
+    #[test]
+    fn tenant_is_taken_from_api_key() {
+        assert_eq!(
+            tenant(&headers(Some(ACME_KEY), None), false).unwrap(),
+            "acme"
+        );
+        assert_eq!(
+            tenant(&headers(Some(ACME_KEY), Some("acme")), false).unwrap(),
+            "acme"
+        );
+        assert!(matches!(
+            tenant(&headers(Some("not-an-issued-key"), None), true),
+            Err(ContextError::InvalidCredentials)
+        ));
+    }
+
+    #[test]
+    fn tenant_header_must_agree_with_api_key() {
+        assert!(matches!(
+            tenant(&headers(Some(ACME_KEY), Some("globex")), true),
+            Err(ContextError::TenantMismatch)
+        ));
+    }
+
+    #[test]
+    fn tenant_header_alone_is_only_accepted_if_trusted() {
+        assert!(matches!(
+            tenant(&headers(None, Some("globex")), false),
+            Err(ContextError::MissingCredentials)
+        ));
+        assert_eq!(
+            tenant(&headers(None, Some("globex")), true).unwrap(),
+            "globex"
+        );
+        assert!(matches!(
+            tenant(&headers(None, None), true),
+            Err(ContextError::MissingTenantId)
+        ));
+    }
 }
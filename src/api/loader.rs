@@ -0,0 +1,321 @@
+use crate::common::{entity::Entity, id::Id};
+use futures::Future;
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+use tokio::sync::oneshot;
+
+type LoadResult<T, E> = Result<Option<T>, Arc<E>>;
+
+/// An error indicating a batch was abandoned before it was fetched, because the load fetching it
+/// was dropped; the loads waiting for it are given this error instead of waiting forever.
+#[derive(Debug, Clone)]
+pub struct BatchCancelledError;
+
+/// Loads entities by ID on behalf of a single request, batching the loads made while resolving one
+/// level of a query into a single fetch, and remembering what it has loaded so each entity is
+/// fetched at most once per request.
+pub struct Loader<T: Entity, E> {
+    state: Arc<Mutex<LoaderState<T, E>>>,
+}
+
+struct LoaderState<T: Entity, E> {
+    /// Entities that have been fetched, or `None` for those found not to exist.
+    loaded: HashMap<Id<T>, Option<T>>,
+    /// IDs to fetch in the next batch.
+    queued: Vec<Id<T>>,
+    /// Those waiting for entities that are queued or being fetched.
+    waiting: HashMap<Id<T>, Vec<oneshot::Sender<LoadResult<T, E>>>>,
+}
+
+impl<T: Entity + Clone, E: From<BatchCancelledError>> Loader<T, E> {
+    pub fn new() -> Self {
+        Self {
+            state: Arc::new(Mutex::new(LoaderState {
+                loaded: HashMap::new(),
+                queued: vec![],
+                waiting: HashMap::new(),
+            })),
+        }
+    }
+
+    /// Loads an entity. The first load of a batch yields before fetching, so that the loads made
+    /// alongside it can join the batch; it then fetches the whole batch, on behalf of every load in
+    /// it. If that load is dropped before the batch is fetched, every load in the batch fails with
+    /// `BatchCancelledError`.
+    ///
+    /// # Arguments
+    /// * `id` - the ID of the entity to load
+    /// * `fetch` - fetches the entities with the provided IDs, if this load fetches a batch
+    ///
+    /// # Returns
+    /// `Some()` of the entity if it existed, `None` otherwise
+    pub async fn load<F, Fut>(&self, id: &Id<T>, fetch: F) -> LoadResult<T, E>
+    where
+        F: FnOnce(Vec<Id<T>>) -> Fut,
+        Fut: Future<Output = Result<Vec<T>, E>>,
+    {
+        let (tx, rx) = oneshot::channel();
+        let dispatch = {
+            let mut state = self.state.lock().unwrap();
+            if let Some(entity) = state.loaded.get(id) {
+                return Ok(entity.clone());
+            }
+            let dispatch = match state.waiting.contains_key(id) {
+                true => false,
+                false => {
+                    state.queued.push(id.clone());
+                    state.queued.len() == 1
+                }
+            };
+            state.waiting.entry(id.clone()).or_default().push(tx);
+            dispatch
+        };
+
+        if dispatch {
+            let mut batch = Batch::new(self);
+            tokio::task::yield_now().await;
+            let ids = batch.take_queued();
+            let result = fetch(ids).await;
+            batch.complete(result);
+        }
+
+        // every batch is completed, even if the load fetching it is dropped
+        rx.await
+            .expect("batch completed without a result for a load")
+    }
+
+    /// Delivers the result of fetching a batch to everything waiting for it.
+    fn complete(&self, ids: Vec<Id<T>>, result: Result<Vec<T>, E>) {
+        let mut state = self.state.lock().unwrap();
+        match result {
+            Ok(entities) => {
+                let mut fetched = entities
+                    .into_iter()
+                    .map(|entity| (entity.id().clone(), entity))
+                    .collect::<HashMap<_, _>>();
+                for id in ids {
+                    let entity = fetched.remove(&id);
+                    for waiter in state.waiting.remove(&id).unwrap_or_default() {
+                        waiter.send(Ok(entity.clone())).ok();
+                    }
+                    state.loaded.insert(id, entity);
+                }
+            }
+            Err(e) => {
+                let e = Arc::new(e);
+                for id in ids {
+                    for waiter in state.waiting.remove(&id).unwrap_or_default() {
+                        waiter.send(Err(Arc::clone(&e))).ok();
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// A batch being fetched by a load. If the load is dropped before it completes the batch, the
+/// batch is completed with `BatchCancelledError`, so the loads waiting for it do not wait forever.
+struct Batch<'a, T: Entity + Clone, E: From<BatchCancelledError>> {
+    loader: &'a Loader<T, E>,
+    /// The IDs in the batch, once they have been taken from the queue.
+    ids: Option<Vec<Id<T>>>,
+    completed: bool,
+}
+
+impl<'a, T: Entity + Clone, E: From<BatchCancelledError>> Batch<'a, T, E> {
+    fn new(loader: &'a Loader<T, E>) -> Self {
+        Self {
+            loader,
+            ids: None,
+            completed: false,
+        }
+    }
+
+    /// Takes the queued IDs into the batch, so that later loads start a new batch.
+    fn take_queued(&mut self) -> Vec<Id<T>> {
+        let ids = std::mem::take(&mut self.loader.state.lock().unwrap().queued);
+        self.ids = Some(ids.clone());
+        ids
+    }
+
+    /// Delivers the result of fetching the batch to everything waiting for it.
+    fn complete(mut self, result: Result<Vec<T>, E>) {
+        self.completed = true;
+        let ids = self.ids.take().unwrap_or_default();
+        self.loader.complete(ids, result);
+    }
+}
+
+impl<'a, T: Entity + Clone, E: From<BatchCancelledError>> Drop for Batch<'a, T, E> {
+    fn drop(&mut self) {
+        if self.completed {
+            return;
+        }
+        let ids = match self.ids.take() {
+            Some(ids) => ids,
+            None => self.take_queued(),
+        };
+        self.loader.complete(ids, Err(BatchCancelledError.into()));
+    }
+}
+
+impl<T: Entity + Clone, E: From<BatchCancelledError>> Default for Loader<T, E> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: Entity, E> Clone for Loader<T, E> {
+    fn clone(&self) -> Self {
+        Self {
+            state: Arc::clone(&self.state),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use futures::executor::block_on;
+    use mongodb::bson::oid::ObjectId;
+
+    #[derive(Clone, Debug, PartialEq)]
+    struct Thing {
+        id: Id<Thing>,
+    }
+
+    impl Entity for Thing {
+        type Key = ObjectId;
+
+        fn id(&self) -> &Id<Thing> {
+            &self.id
+        }
+    }
+
+    #[derive(Debug, PartialEq)]
+    enum FetchError {
+        Unavailable,
+        Cancelled,
+    }
+
+    impl From<BatchCancelledError> for FetchError {
+        fn from(_: BatchCancelledError) -> Self {
+            FetchError::Cancelled
+        }
+    }
+
+    fn new_id() -> Id<Thing> {
+        ObjectId::new().into()
+    }
+
+    /// Returns a fetch that finds every requested thing except `missing`, recording each batch.
+    fn fetch<'a>(
+        batches: &'a Mutex<Vec<Vec<Id<Thing>>>>,
+        missing: &'a Id<Thing>,
+    ) -> impl Fn(Vec<Id<Thing>>) -> futures::future::Ready<Result<Vec<Thing>, FetchError>> + 'a
+    {
+        move |ids| {
+            batches.lock().unwrap().push(ids.clone());
+            let things = ids
+                .into_iter()
+                .filter(|id| id != missing)
+                .map(|id| Thing { id })
+                .collect();
+            futures::future::ready(Ok(things))
+        }
+    }
+
+    #[test]
+    fn concurrent_loads_are_fetched_in_one_batch() {
+        let loader = Loader::<Thing, FetchError>::new();
+        let batches = Mutex::new(vec![]);
+        let (id1, id2, missing) = (new_id(), new_id(), new_id());
+        let fetch = fetch(&batches, &missing);
+
+        let (thing1, thing2, thing3) = block_on(async {
+            futures::join!(
+                loader.load(&id1, &fetch),
+                loader.load(&id2, &fetch),
+                loader.load(&missing, &fetch),
+            )
+        });
+
+        assert_eq!(thing1.unwrap().map(|thing| thing.id), Some(id1.clone()));
+        assert_eq!(thing2.unwrap().map(|thing| thing.id), Some(id2.clone()));
+        assert_eq!(thing3.unwrap(), None);
+        assert_eq!(
+            *batches.lock().unwrap(),
+            vec![vec![id1, id2, missing.clone()]]
+        );
+    }
+
+    #[test]
+    fn repeated_loads_are_fetched_once() {
+        let loader = Loader::<Thing, FetchError>::new();
+        let batches = Mutex::new(vec![]);
+        let (id, missing) = (new_id(), new_id());
+        let fetch = fetch(&batches, &missing);
+
+        block_on(async {
+            let (thing1, thing2) =
+                futures::join!(loader.load(&id, &fetch), loader.load(&id, &fetch));
+            assert_eq!(thing1.unwrap(), thing2.unwrap());
+            loader.load(&id, &fetch).await.unwrap();
+            loader.load(&missing, &fetch).await.unwrap();
+            loader.load(&missing, &fetch).await.unwrap();
+        });
+
+        assert_eq!(
+            *batches.lock().unwrap(),
+            vec![vec![id], vec![missing.clone()]]
+        );
+    }
+
+    #[test]
+    fn fetch_errors_are_delivered_to_every_load() {
+        let loader = Loader::<Thing, FetchError>::new();
+        let fail = |_| futures::future::ready(Err(FetchError::Unavailable));
+        let (id1, id2) = (new_id(), new_id());
+
+        let (thing1, thing2) =
+            block_on(async { futures::join!(loader.load(&id1, fail), loader.load(&id2, fail)) });
+
+        assert_eq!(*thing1.unwrap_err(), FetchError::Unavailable);
+        assert_eq!(*thing2.unwrap_err(), FetchError::Unavailable);
+    }
+
+    #[test]
+    fn loads_in_a_batch_fail_if_the_load_fetching_it_is_dropped() {
+        let loader = Loader::<Thing, FetchError>::new();
+        let never = |_| futures::future::pending();
+        let (id1, id2) = (new_id(), new_id());
+
+        block_on(async {
+            let mut fetching = Box::pin(loader.load(&id1, never));
+            let mut waiting = Box::pin(loader.load(&id2, never));
+            assert!(futures::poll!(fetching.as_mut()).is_pending());
+            assert!(futures::poll!(waiting.as_mut()).is_pending());
+            assert!(futures::poll!(fetching.as_mut()).is_pending());
+            drop(fetching);
+            assert_eq!(*waiting.await.unwrap_err(), FetchError::Cancelled);
+        });
+    }
+
+    #[test]
+    fn loads_queued_for_a_batch_fail_if_the_load_that_would_fetch_it_is_dropped() {
+        let loader = Loader::<Thing, FetchError>::new();
+        let never = |_| futures::future::pending();
+        let (id1, id2) = (new_id(), new_id());
+
+        block_on(async {
+            let mut fetching = Box::pin(loader.load(&id1, never));
+            let mut waiting = Box::pin(loader.load(&id2, never));
+            assert!(futures::poll!(fetching.as_mut()).is_pending());
+            assert!(futures::poll!(waiting.as_mut()).is_pending());
+            drop(fetching);
+            assert_eq!(*waiting.await.unwrap_err(), FetchError::Cancelled);
+        });
+    }
+}
@@ -1,3 +1,5 @@
+pub mod auth;
 pub mod context;
+pub mod loader;
 pub mod schema;
 pub mod server;
@@ -0,0 +1,204 @@
+use super::json::Json;
+use crate::{
+    common::attribute::{AttributeKey, AttributeValue, Attributes, AttributesPatch},
+    storage::filter::{MapFilter, RangeFilter, ValueFilter},
+};
+use mongodb::bson::DateTime;
+use std::collections::{BTreeMap, BTreeSet};
+
+#[derive(juniper::GraphQLInputObject)]
+#[graphql(description = "The value of a custom attribute; exactly one of its fields must be set")]
+pub struct AttributeValueInput {
+    pub string: Option<String>,
+    pub number: Option<f64>,
+    pub bool: Option<bool>,
+    #[graphql(description = "A date, as an RFC 3339 timestamp")]
+    pub date: Option<String>,
+}
+
+impl TryFrom<AttributeValueInput> for AttributeValue {
+    type Error = String;
+
+    fn try_from(input: AttributeValueInput) -> Result<Self, Self::Error> {
+        match (input.string, input.number, input.bool, input.date) {
+            (Some(s), None, None, None) => Ok(AttributeValue::String(s)),
+            (None, Some(n), None, None) => Ok(AttributeValue::Number(n)),
+            (None, None, Some(b), None) => Ok(AttributeValue::Bool(b)),
+            (None, None, None, Some(date)) => DateTime::parse_rfc3339_str(&date)
+                .map(AttributeValue::Date)
+                .map_err(|_| format!("\"{date}\" is not an RFC 3339 timestamp")),
+            _ => Err("an attribute value must have exactly one of its fields set".into()),
+        }
+    }
+}
+
+#[derive(juniper::GraphQLInputObject)]
+#[graphql(description = "A custom attribute to set")]
+pub struct AttributeInput {
+    pub key: String,
+    pub value: AttributeValueInput,
+}
+
+#[derive(juniper::GraphQLInputObject)]
+#[graphql(
+    description = "A condition on a custom attribute: equal to a value, or within an inclusive \
+                   range of values of the same type"
+)]
+pub struct AttributeFilterInput {
+    pub key: String,
+    pub eq: Option<AttributeValueInput>,
+    pub min: Option<AttributeValueInput>,
+    pub max: Option<AttributeValueInput>,
+}
+
+/// Converts custom attributes to JSON; dates become RFC 3339 timestamps.
+pub fn to_json(attributes: &Attributes) -> Json {
+    let fields = attributes
+        .iter()
+        .map(|(key, value)| {
+            let value = match value {
+                AttributeValue::String(s) => s.clone().into(),
+                AttributeValue::Number(n) => serde_json::Number::from_f64(*n)
+                    .map_or(serde_json::Value::Null, serde_json::Value::Number),
+                AttributeValue::Bool(b) => (*b).into(),
+                AttributeValue::Date(d) => d.try_to_rfc3339_string().unwrap_or_default().into(),
+            };
+            (key.to_string(), value)
+        })
+        .collect();
+    Json(serde_json::Value::Object(fields))
+}
+
+/// Parses the custom attributes provided as input.
+pub fn parse_attributes(inputs: Vec<AttributeInput>) -> Result<Attributes, String> {
+    Attributes::new(parse_values(inputs)?).map_err(|e| e.to_string())
+}
+
+/// Parses the changes to custom attributes provided as input.
+pub fn parse_attributes_patch(
+    set: Vec<AttributeInput>,
+    remove: Vec<String>,
+) -> Result<AttributesPatch, String> {
+    let remove = remove
+        .iter()
+        .map(|key| parse_key(key))
+        .collect::<Result<BTreeSet<_>, _>>()?;
+    AttributesPatch::new(parse_values(set)?, remove).map_err(|e| e.to_string())
+}
+
+/// Parses the conditions on custom attributes provided as input; there can be at most one
+/// condition per attribute.
+pub fn parse_attribute_filters(
+    inputs: Vec<AttributeFilterInput>,
+) -> Result<MapFilter<AttributeKey, ValueFilter<AttributeValue>>, String> {
+    let mut conditions = BTreeMap::new();
+    for input in inputs {
+        let key = parse_key(&input.key)?;
+        let condition = match (input.eq, input.min, input.max) {
+            (Some(eq), None, None) => ValueFilter::Eq(eq.try_into()?),
+            (None, min, max) if min.is_some() || max.is_some() => ValueFilter::Range(RangeFilter {
+                min: min.map(TryInto::try_into).transpose()?,
+                max: max.map(TryInto::try_into).transpose()?,
+            }),
+            _ => {
+                return Err(format!(
+                    "the condition on attribute {key} must have either eq, or min and/or max"
+                ))
+            }
+        };
+        if conditions.insert(key.clone(), condition).is_some() {
+            return Err(format!(
+                "there can only be one condition on attribute {key}"
+            ));
+        }
+    }
+    Ok(MapFilter(conditions))
+}
+
+fn parse_values(
+    inputs: Vec<AttributeInput>,
+) -> Result<BTreeMap<AttributeKey, AttributeValue>, String> {
+    let mut values = BTreeMap::new();
+    for input in inputs {
+        let key = parse_key(&input.key)?;
+        if values
+            .insert(key.clone(), input.value.try_into()?)
+            .is_some()
+        {
+            return Err(format!("attribute {key} can only be set once"));
+        }
+    }
+    Ok(values)
+}
+
+fn parse_key(key: &str) -> Result<AttributeKey, String> {
+    key.parse().map_err(|e| format!("\"{key}\": {e}"))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use mongodb::bson::{doc, to_bson, Bson};
+
+    fn value(number: f64) -> AttributeValueInput {
+        AttributeValueInput {
+            string: None,
+            number: Some(number),
+            bool: None,
+            date: None,
+        }
+    }
+
+    #[test]
+    fn value_must_have_exactly_one_field() {
+        let empty = AttributeValueInput {
+            string: None,
+            number: None,
+            bool: None,
+            date: None,
+        };
+        assert!(AttributeValue::try_from(empty).is_err());
+        let both = AttributeValueInput {
+            string: Some("red".into()),
+            ..value(1.0)
+        };
+        assert!(AttributeValue::try_from(both).is_err());
+        assert_eq!(
+            AttributeValue::try_from(value(1.5)),
+            Ok(AttributeValue::Number(1.5))
+        );
+    }
+
+    #[test]
+    fn range_filter_is_parsed() {
+        let filter = parse_attribute_filters(vec![AttributeFilterInput {
+            key: "weight".into(),
+            eq: None,
+            min: Some(value(1.0)),
+            max: None,
+        }])
+        .unwrap();
+        assert_eq!(
+            to_bson(&filter).unwrap(),
+            Bson::Document(doc! { "$fields": { "weight": { "$gte": 1.0 } } })
+        );
+    }
+
+    #[test]
+    fn filter_cannot_mix_eq_and_range_or_repeat_a_key() {
+        let mixed = AttributeFilterInput {
+            key: "weight".into(),
+            eq: Some(value(1.0)),
+            min: Some(value(1.0)),
+            max: None,
+        };
+        assert!(parse_attribute_filters(vec![mixed]).is_err());
+        let eq = || AttributeFilterInput {
+            key: "weight".into(),
+            eq: Some(value(1.0)),
+            min: None,
+            max: None,
+        };
+        assert!(parse_attribute_filters(vec![eq(), eq()]).is_err());
+    }
+}
@@ -0,0 +1,41 @@
+use crate::common::geo::GeoPoint;
+
+#[derive(juniper::GraphQLInputObject)]
+#[graphql(description = "A point on the earth, in degrees")]
+pub struct GeoPointInput {
+    #[graphql(description = "The latitude of the point, between -90 and 90")]
+    pub latitude: f64,
+    #[graphql(description = "The longitude of the point, between -180 and 180")]
+    pub longitude: f64,
+}
+
+impl TryFrom<GeoPointInput> for GeoPoint {
+    type Error = String;
+
+    fn try_from(input: GeoPointInput) -> Result<Self, Self::Error> {
+        parse_point(input.latitude, input.longitude)
+    }
+}
+
+#[derive(juniper::GraphQLObject)]
+#[graphql(name = "GeoPoint", description = "A point on the earth, in degrees")]
+pub struct GeoPointNode {
+    #[graphql(description = "The latitude of the point")]
+    pub latitude: f64,
+    #[graphql(description = "The longitude of the point")]
+    pub longitude: f64,
+}
+
+impl From<&GeoPoint> for GeoPointNode {
+    fn from(point: &GeoPoint) -> Self {
+        Self {
+            latitude: point.latitude(),
+            longitude: point.longitude(),
+        }
+    }
+}
+
+/// Parses a point provided as input as its latitude and longitude.
+pub fn parse_point(latitude: f64, longitude: f64) -> Result<GeoPoint, String> {
+    GeoPoint::new(latitude, longitude).map_err(|e| e.to_string())
+}
@@ -1,79 +1,106 @@
-pub use controller::*;
 pub use create::*;
 pub use find::*;
+pub use near::*;
 pub use node::*;
+pub use tags::*;
 pub use update::*;
 
-mod controller {
+mod resource {
     use super::*;
     use crate::{
-        api::context::Context,
-        common::id::Id,
-        domain::models::items::{ItemFilter, ItemPatch, ItemSpec},
+        api::{
+            context::{Context, DomainError},
+            schema::resource::{MutationInput, Resource},
+        },
+        common::{id::Id, idempotency::IdempotencyKey},
+        domain::models::items::{Item, ItemFilter, ItemPatch, ItemSpec},
         domain::Domain,
     };
-    use juniper::FieldResult;
-    use std::str::FromStr;
+    use async_trait::async_trait;
+    use std::sync::Arc;
 
-    pub async fn create_item_from_input(
-        ctx: &Context,
-        input: CreateItemInput,
-    ) -> FieldResult<ItemNode> {
-        let spec = ItemSpec::try_from(input)?;
-        Ok(ItemNode::from(ctx.domain().create_item(&spec).await?))
-    }
+    #[async_trait]
+    impl Resource for Item {
+        const NAME: &'static str = "item";
 
-    pub async fn update_item_from_input(
-        ctx: &Context,
-        input: UpdateItemInput,
-    ) -> FieldResult<ItemNode> {
-        let patch = ItemPatch::try_from(input)?;
-        if let Some(item) = ctx.domain().update_item(&patch).await? {
-            Ok(ItemNode::from(item))
-        } else {
-            Err(String::from("no item exists with the provided ID").into())
+        type Node = ItemNode;
+        type CreateInput = CreateItemInput;
+        type UpdateInput = UpdateItemInput;
+        type FilterInput = ItemFilterInput;
+
+        async fn load(ctx: &Context, id: &Id<Item>) -> Result<Option<Item>, Arc<DomainError>> {
+            ctx.load_item(id).await
+        }
+
+        async fn find_all(ctx: &Context, filter: &ItemFilter) -> Result<Vec<Item>, DomainError> {
+            ctx.domain().find_items(filter).await
+        }
+
+        async fn find_page(
+            ctx: &Context,
+            filter: &ItemFilter,
+            offset: usize,
+            limit: usize,
+        ) -> Result<Vec<Item>, DomainError> {
+            ctx.domain().find_items_page(filter, offset, limit).await
         }
-    }
 
-    pub async fn delete_item_by_id(ctx: &Context, id: &str) -> FieldResult<()> {
-        let id = id
-            .parse::<Id>()
-            .map_err(|_| String::from("the provided ID was invalid"))?;
-        match ctx.domain().delete_item(&id).await? {
-            true => Ok(()),
-            false => Err(String::from("no item exists with the provided ID").into()),
+        async fn create(
+            ctx: &Context,
+            spec: &ItemSpec,
+            idempotency_key: Option<&IdempotencyKey>,
+        ) -> Result<Item, DomainError> {
+            ctx.domain().create_item(spec, idempotency_key).await
+        }
+
+        async fn update(
+            ctx: &Context,
+            patch: &ItemPatch,
+            idempotency_key: Option<&IdempotencyKey>,
+        ) -> Result<Option<Item>, DomainError> {
+            ctx.domain().update_item(patch, idempotency_key).await
+        }
+
+        async fn delete(
+            ctx: &Context,
+            id: &Id<Item>,
+            idempotency_key: Option<&IdempotencyKey>,
+        ) -> Result<Option<Item>, DomainError> {
+            ctx.domain().delete_item(id, idempotency_key).await
         }
     }
 
-    pub async fn item_nodes(
-        ctx: &Context,
-        filter: Option<ItemFilterInput>,
-    ) -> FieldResult<Vec<ItemNode>> {
-        let items = if let Some(filter) = filter {
-            ctx.domain()
-                .find_items(&ItemFilter::try_from(filter)?)
-                .await?
-        } else {
-            ctx.domain().all_items().await?
-        };
-        let item_nodes = items.into_iter().map(ItemNode::from).collect();
-        Ok(item_nodes)
+    impl MutationInput for CreateItemInput {
+        fn client_mutation_id(&self) -> Option<&str> {
+            self.client_mutation_id.as_deref()
+        }
     }
 
-    pub async fn item_node(ctx: &Context, id: String) -> FieldResult<Option<ItemNode>> {
-        let id = Id::from_str(&id)?;
-        let item = ctx.domain().item(&id).await?;
-        Ok(item.map(ItemNode::from))
+    impl MutationInput for UpdateItemInput {
+        fn client_mutation_id(&self) -> Option<&str> {
+            self.client_mutation_id.as_deref()
+        }
     }
 }
 
 mod node {
     use crate::{
-        api::context::Context,
-        common::entity::Entity,
-        domain::models::items::{self, Item},
+        api::{
+            context::Context,
+            schema::{
+                attributes, geo::GeoPointNode, json::Json, owners::OwnerNode, stock::StockLevelNode,
+            },
+        },
+        common::{entity::Entity, tag::Tag},
+        domain::{
+            models::{
+                items::{self, Item},
+                stock::StockLevelFilter,
+            },
+            Domain,
+        },
     };
-    use juniper::graphql_object;
+    use juniper::{graphql_object, FieldResult};
 
     pub struct ItemNode(Item);
 
@@ -105,6 +132,52 @@ mod node {
         pub fn size(&self) -> ItemSize {
             self.0.size().into()
         }
+
+        #[graphql(description = "A description of the item, if it has one")]
+        pub fn description(&self) -> Option<&str> {
+            self.0.description()
+        }
+
+        #[graphql(description = "The tags of the item")]
+        pub fn tags(&self) -> Vec<&str> {
+            self.0.tags().iter().map(Tag::as_ref).collect()
+        }
+
+        #[graphql(
+            description = "The custom attributes of the item, as an object of their values; dates are RFC 3339 timestamps"
+        )]
+        pub fn attributes(&self) -> Json {
+            attributes::to_json(self.0.attributes())
+        }
+
+        #[graphql(description = "Where the item is located, if it has a location")]
+        pub fn location(&self) -> Option<GeoPointNode> {
+            self.0.location().map(GeoPointNode::from)
+        }
+
+        #[graphql(description = "The owner of the item, if it has one")]
+        pub async fn owner(&self, ctx: &Context) -> FieldResult<Option<OwnerNode>> {
+            let owner = match self.0.owner_id() {
+                Some(owner_id) => ctx
+                    .load_owner(owner_id)
+                    .await
+                    .map_err(|e| ctx.field_error(e))?,
+                None => None,
+            };
+            Ok(owner.map(OwnerNode::from))
+        }
+
+        #[graphql(description = "The stock of the item at each location it is stocked at")]
+        pub async fn stock_levels(&self, ctx: &Context) -> FieldResult<Vec<StockLevelNode>> {
+            let mut filter = StockLevelFilter::default();
+            *filter.item_id_mut() = Some(self.0.id().clone());
+            let stock_levels = ctx
+                .domain()
+                .stock_levels(&filter)
+                .await
+                .map_err(|e| ctx.field_error(e))?;
+            Ok(stock_levels.into_iter().map(StockLevelNode::from).collect())
+        }
     }
 
     impl From<Item> for ItemNode {
@@ -135,10 +208,17 @@ mod node {
 }
 
 mod create {
-    use super::ItemSize;
+    use super::{parse_tags, ItemSize};
     use crate::{
-        common::name::Name,
-        domain::models::items::{self, ItemSpec},
+        api::schema::{
+            attributes::{parse_attributes, AttributeInput},
+            geo::GeoPointInput,
+        },
+        common::{id::Id, name::Name},
+        domain::models::{
+            items::{self, ItemSpec},
+            owners::Owner,
+        },
     };
 
     #[derive(juniper::GraphQLInputObject)]
@@ -148,6 +228,18 @@ mod create {
         pub name: String,
         #[graphql(description = "The size of the item to create")]
         pub size: ItemSize,
+        #[graphql(description = "A description of the item to create")]
+        pub description: Option<String>,
+        #[graphql(description = "The ID of the owner of the item to create")]
+        pub owner_id: Option<String>,
+        #[graphql(description = "The tags of the item to create")]
+        pub tags: Option<Vec<String>>,
+        #[graphql(description = "The custom attributes of the item to create")]
+        pub attributes: Option<Vec<AttributeInput>>,
+        #[graphql(description = "Where the item to create is located")]
+        pub location: Option<GeoPointInput>,
+        #[graphql(description = "A key making the creation idempotent, if it is retried")]
+        pub client_mutation_id: Option<String>,
     }
 
     impl TryFrom<CreateItemInput> for ItemSpec {
@@ -155,7 +247,20 @@ mod create {
 
         fn try_from(input: CreateItemInput) -> Result<Self, Self::Error> {
             match input.name.parse::<Name>() {
-                Ok(name) => Ok(ItemSpec::new(name, items::ItemSize::from(&input.size))),
+                Ok(name) => {
+                    let mut spec = ItemSpec::new(name, items::ItemSize::from(&input.size));
+                    *spec.description_mut() = input.description;
+                    *spec.owner_id_mut() = input
+                        .owner_id
+                        .map(|owner_id| owner_id.parse::<Id<Owner>>())
+                        .transpose()
+                        .map_err(|_| String::from("the provided owner ID was invalid"))?;
+                    *spec.tags_mut() = parse_tags(input.tags.unwrap_or_default())?;
+                    *spec.attributes_mut() =
+                        parse_attributes(input.attributes.unwrap_or_default())?;
+                    *spec.location_mut() = input.location.map(TryInto::try_into).transpose()?;
+                    Ok(spec)
+                }
                 Err(_) => Err("name cannot be empty".into()),
             }
         }
@@ -164,11 +269,20 @@ mod create {
 
 mod update {
     use crate::{
+        api::schema::{
+            attributes::{parse_attributes_patch, AttributeInput},
+            geo::GeoPointInput,
+        },
         common::{id::Id, name::Name},
-        domain::models::items::ItemPatch,
+        domain::models::{
+            items::{Item, ItemPatch},
+            owners::Owner,
+        },
+        storage::patch::{ArrayPatch, FieldPatch},
     };
+    use juniper::Nullable;
 
-    use super::ItemSize;
+    use super::{parse_tags, ItemSize};
 
     #[derive(juniper::GraphQLInputObject)]
     #[graphql(description = "Input for updating an item")]
@@ -176,6 +290,22 @@ mod update {
         pub id: String,
         pub name: Option<String>,
         pub size: Option<ItemSize>,
+        #[graphql(description = "A new description of the item; null removes its description")]
+        pub description: Nullable<String>,
+        #[graphql(description = "The ID of the new owner of the item; null removes its owner")]
+        pub owner_id: Nullable<String>,
+        #[graphql(description = "Tags to add to the item, unless it already has them")]
+        pub add_tags: Option<Vec<String>>,
+        #[graphql(description = "Tags to remove from the item")]
+        pub remove_tags: Option<Vec<String>>,
+        #[graphql(description = "Custom attributes to set, replacing any existing values")]
+        pub set_attributes: Option<Vec<AttributeInput>>,
+        #[graphql(description = "The keys of custom attributes to remove")]
+        pub remove_attributes: Option<Vec<String>>,
+        #[graphql(description = "Where the item is now located; null removes its location")]
+        pub location: Nullable<GeoPointInput>,
+        #[graphql(description = "A key making the update idempotent, if it is retried")]
+        pub client_mutation_id: Option<String>,
     }
 
     impl TryFrom<UpdateItemInput> for ItemPatch {
@@ -184,7 +314,7 @@ mod update {
         fn try_from(input: UpdateItemInput) -> Result<Self, Self::Error> {
             let id = input
                 .id
-                .parse::<Id>()
+                .parse::<Id<Item>>()
                 .map_err(|_| String::from("the provided ID was invalid"))?;
             let mut patch = ItemPatch::new(id);
 
@@ -199,17 +329,59 @@ mod update {
                 *patch.size_mut() = Some(size.into());
             }
 
+            *patch.description_mut() = match input.description {
+                Nullable::ImplicitNull => FieldPatch::Unchanged,
+                Nullable::ExplicitNull => FieldPatch::Clear,
+                Nullable::Some(description) => FieldPatch::Set(description),
+            };
+
+            *patch.owner_id_mut() = match input.owner_id {
+                Nullable::ImplicitNull => FieldPatch::Unchanged,
+                Nullable::ExplicitNull => FieldPatch::Clear,
+                Nullable::Some(owner_id) => FieldPatch::Set(
+                    owner_id
+                        .parse::<Id<Owner>>()
+                        .map_err(|_| String::from("the provided owner ID was invalid"))?,
+                ),
+            };
+
+            *patch.tags_mut() = match (input.add_tags, input.remove_tags) {
+                (Some(_), Some(_)) => {
+                    return Err("tags cannot be added and removed in the same update".into())
+                }
+                (Some(tags), None) => Some(ArrayPatch::AddToSet(parse_tags(tags)?)),
+                (None, Some(tags)) => Some(ArrayPatch::Pull(parse_tags(tags)?)),
+                (None, None) => None,
+            };
+
+            if input.set_attributes.is_some() || input.remove_attributes.is_some() {
+                *patch.attributes_mut() = Some(parse_attributes_patch(
+                    input.set_attributes.unwrap_or_default(),
+                    input.remove_attributes.unwrap_or_default(),
+                )?);
+            }
+
+            *patch.location_mut() = match input.location {
+                Nullable::ImplicitNull => FieldPatch::Unchanged,
+                Nullable::ExplicitNull => FieldPatch::Clear,
+                Nullable::Some(location) => FieldPatch::Set(location.try_into()?),
+            };
+
             Ok(patch)
         }
     }
 }
 
 mod find {
-    use super::ItemSize;
+    use super::{parse_tags, ItemSize};
     use crate::{
+        api::schema::attributes::{parse_attribute_filters, AttributeFilterInput},
         common::{id::Id, name::Name},
-        domain::models::items::ItemFilter,
-        storage::repo::Filter,
+        domain::models::{
+            items::{Item, ItemFilter},
+            owners::Owner,
+        },
+        storage::{filter::ArrayFilter, repo::Filter},
     };
 
     #[derive(juniper::GraphQLInputObject)]
@@ -218,6 +390,15 @@ mod find {
         pub id: Option<String>,
         pub name: Option<String>,
         pub size: Option<ItemSize>,
+        pub owner_id: Option<String>,
+        #[graphql(description = "Matches items with at least one of these tags")]
+        pub has_any_tag: Option<Vec<String>>,
+        #[graphql(description = "Matches items with every one of these tags")]
+        pub has_all_tags: Option<Vec<String>>,
+        #[graphql(
+            description = "Matches items whose custom attributes meet every one of these conditions"
+        )]
+        pub attributes: Option<Vec<AttributeFilterInput>>,
     }
 
     impl TryFrom<ItemFilterInput> for ItemFilter {
@@ -228,7 +409,7 @@ mod find {
 
             if let Some(s) = input.id.as_ref() {
                 *filter.id_mut() = Some(
-                    s.parse::<Id>()
+                    s.parse::<Id<Item>>()
                         .map_err(|_| String::from("the provided ID was invalid"))?,
                 );
             }
@@ -244,7 +425,154 @@ mod find {
                 *filter.size_mut() = Some(size.into());
             }
 
+            if let Some(s) = input.owner_id.as_ref() {
+                *filter.owner_id_mut() = Some(
+                    s.parse::<Id<Owner>>()
+                        .map_err(|_| String::from("the provided owner ID was invalid"))?,
+                );
+            }
+
+            let tags = ArrayFilter {
+                any: parse_tags(input.has_any_tag.unwrap_or_default())?,
+                all: parse_tags(input.has_all_tags.unwrap_or_default())?,
+            };
+            if !tags.is_empty() {
+                *filter.tags_mut() = Some(tags);
+            }
+
+            if let Some(attributes) = input.attributes {
+                *filter.attributes_mut() = Some(parse_attribute_filters(attributes)?);
+            }
+
             Ok(filter)
         }
     }
 }
+
+mod near {
+    use super::{ItemFilterInput, ItemNode};
+    use crate::{
+        api::{
+            context::Context,
+            schema::{
+                geo::parse_point,
+                resource::{invalid_input, PageInput},
+            },
+        },
+        domain::{models::items::ItemFilter, Domain},
+        storage::repo::{GeoArea, GeoQuery},
+    };
+    use juniper::FieldResult;
+
+    #[derive(juniper::GraphQLObject)]
+    #[graphql(
+        name = "ItemDistance",
+        context = Context,
+        description = "An item and its distance from the point searched near"
+    )]
+    pub struct ItemDistanceNode {
+        #[graphql(description = "The item")]
+        pub item: ItemNode,
+        #[graphql(description = "The distance of the item from the point, in meters")]
+        pub distance_meters: f64,
+    }
+
+    /// Resolves the field listing the located items matching a filter within a distance of a
+    /// point, from the nearest to the furthest.
+    pub async fn items_near(
+        ctx: &Context,
+        lat: f64,
+        lng: f64,
+        max_distance_meters: f64,
+        filter: Option<ItemFilterInput>,
+        limit: Option<i32>,
+    ) -> FieldResult<Vec<ItemDistanceNode>> {
+        let near = parse_point(lat, lng).map_err(invalid_input)?;
+        if !(max_distance_meters > 0.0 && max_distance_meters.is_finite()) {
+            return Err(invalid_input("the maximum distance must be positive"));
+        }
+        let filter = match filter {
+            Some(filter) => ItemFilter::try_from(filter).map_err(invalid_input)?,
+            None => ItemFilter::default(),
+        };
+        let page = PageInput {
+            offset: None,
+            limit,
+        };
+        let (_, limit) = page.bounds().map_err(invalid_input)?;
+        let query = GeoQuery {
+            near,
+            within: GeoArea::Radius(max_distance_meters),
+        };
+        let items = ctx
+            .domain()
+            .items_near(&query, &filter, limit)
+            .await
+            .map_err(|e| ctx.field_error(e))?;
+        Ok(items
+            .into_iter()
+            .map(|near| ItemDistanceNode {
+                item: ItemNode::from(near.entity),
+                distance_meters: near.distance_meters,
+            })
+            .collect())
+    }
+}
+
+mod tags {
+    use super::ItemFilterInput;
+    use crate::{
+        api::{context::Context, schema::resource::invalid_input},
+        common::tag::Tag,
+        domain::{models::items::ItemFilter, Domain},
+        storage::repo::Facet,
+    };
+    use juniper::{graphql_object, FieldResult};
+
+    pub struct TagCountNode(Facet<Tag>);
+
+    #[graphql_object(context = Context)]
+    #[graphql(
+        name = "TagCount",
+        description = "A tag and the number of items tagged with it"
+    )]
+    impl TagCountNode {
+        #[graphql(description = "The tag")]
+        pub fn tag(&self) -> &str {
+            &self.0.value
+        }
+
+        #[graphql(description = "The number of items tagged with the tag")]
+        pub fn count(&self) -> i32 {
+            i32::try_from(self.0.count).unwrap_or(i32::MAX)
+        }
+    }
+
+    /// Resolves the field listing the tags of the items matching a filter, from the most to the
+    /// least used.
+    pub async fn tags(
+        ctx: &Context,
+        filter: Option<ItemFilterInput>,
+    ) -> FieldResult<Vec<TagCountNode>> {
+        let filter = match filter {
+            Some(filter) => ItemFilter::try_from(filter).map_err(invalid_input)?,
+            None => ItemFilter::default(),
+        };
+        let facets = ctx
+            .domain()
+            .item_tags(&filter)
+            .await
+            .map_err(|e| ctx.field_error(e))?;
+        Ok(facets.into_iter().map(TagCountNode).collect())
+    }
+
+    /// Parses the tags provided as input, failing on the first invalid one.
+    pub(super) fn parse_tags(tags: Vec<String>) -> Result<Vec<Tag>, String> {
+        tags.into_iter()
+            .map(|tag| {
+                tag.parse::<Tag>()
+                    .map_err(|_| format!("\"{tag}\" is not a valid tag"))
+            })
+            .collect()
+    }
+}
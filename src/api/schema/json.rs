@@ -0,0 +1,78 @@
+use juniper::{
+    graphql_scalar, parser::ScalarToken, InputValue, Object, ParseScalarResult, ParseScalarValue,
+    ScalarValue, Value,
+};
+
+/// An arbitrary JSON value, for data whose shape is chosen by users rather than by the schema.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Json(pub serde_json::Value);
+
+#[graphql_scalar(name = "JSON", description = "An arbitrary JSON value")]
+impl<S> GraphQLScalar for Json
+where
+    S: ScalarValue,
+{
+    fn resolve(&self) -> Value {
+        to_value(&self.0)
+    }
+
+    fn from_input_value(v: &InputValue) -> Option<Json> {
+        from_input_value(v).map(Json)
+    }
+
+    fn from_str<'a>(value: ScalarToken<'a>) -> ParseScalarResult<'a, S> {
+        match value {
+            ScalarToken::String(_) => <String as ParseScalarValue<S>>::from_str(value),
+            ScalarToken::Int(_) => <i32 as ParseScalarValue<S>>::from_str(value),
+            ScalarToken::Float(_) => <f64 as ParseScalarValue<S>>::from_str(value),
+        }
+    }
+}
+
+fn to_value<S: ScalarValue>(json: &serde_json::Value) -> Value<S> {
+    match json {
+        serde_json::Value::Null => Value::null(),
+        serde_json::Value::Bool(b) => Value::scalar(*b),
+        serde_json::Value::Number(n) => match n.as_i64().and_then(|n| i32::try_from(n).ok()) {
+            Some(n) => Value::scalar(n),
+            None => Value::scalar(n.as_f64().unwrap_or_default()),
+        },
+        serde_json::Value::String(s) => Value::scalar(s.clone()),
+        serde_json::Value::Array(values) => Value::list(values.iter().map(to_value).collect()),
+        serde_json::Value::Object(fields) => {
+            let mut object = Object::with_capacity(fields.len());
+            for (name, value) in fields {
+                object.add_field(name.clone(), to_value(value));
+            }
+            Value::object(object)
+        }
+    }
+}
+
+fn from_input_value<S: ScalarValue>(v: &InputValue<S>) -> Option<serde_json::Value> {
+    match v {
+        InputValue::Null => Some(serde_json::Value::Null),
+        InputValue::Scalar(s) => {
+            if let Some(b) = s.as_boolean() {
+                Some(b.into())
+            } else if let Some(n) = s.as_int() {
+                Some(n.into())
+            } else if let Some(n) = s.as_float() {
+                serde_json::Number::from_f64(n).map(serde_json::Value::Number)
+            } else {
+                s.as_string().map(serde_json::Value::String)
+            }
+        }
+        InputValue::List(values) => values
+            .iter()
+            .map(|value| from_input_value(&value.item))
+            .collect::<Option<Vec<_>>>()
+            .map(serde_json::Value::Array),
+        InputValue::Object(fields) => fields
+            .iter()
+            .map(|(name, value)| Some((name.item.clone(), from_input_value(&value.item)?)))
+            .collect::<Option<serde_json::Map<_, _>>>()
+            .map(serde_json::Value::Object),
+        InputValue::Enum(_) | InputValue::Variable(_) => None,
+    }
+}
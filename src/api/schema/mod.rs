@@ -1,11 +1,28 @@
+pub mod attributes;
+pub mod geo;
 pub mod items;
+pub mod json;
+pub mod owners;
+pub mod resource;
+pub mod stock;
 
-use crate::api::{
-    context::Context,
-    schema::items::{
-        create_item_from_input, delete_item_by_id, item_node, item_nodes, update_item_from_input,
-        CreateItemInput, ItemFilterInput, ItemNode, UpdateItemInput,
+use crate::{
+    api::{
+        context::Context,
+        schema::{
+            items::{
+                CreateItemInput, ItemDistanceNode, ItemFilterInput, ItemNode, TagCountNode,
+                UpdateItemInput,
+            },
+            owners::{CreateOwnerInput, OwnerFilterInput, OwnerNode, UpdateOwnerInput},
+            resource::PageInput,
+            stock::{
+                AdjustStockInput, StockLevelFilterInput, StockLevelNode, StockMovementFilterInput,
+                StockMovementNode, TransferStockInput,
+            },
+        },
     },
+    domain::models::{items::Item, owners::Owner},
 };
 use juniper::{graphql_object, FieldResult};
 
@@ -14,12 +31,65 @@ pub struct Query;
 
 #[graphql_object(context = Context)]
 impl Query {
-    async fn items(ctx: &Context, filter: Option<ItemFilterInput>) -> FieldResult<Vec<ItemNode>> {
-        item_nodes(ctx, filter).await
+    async fn items(
+        ctx: &Context,
+        filter: Option<ItemFilterInput>,
+        page: Option<PageInput>,
+    ) -> FieldResult<Vec<ItemNode>> {
+        resource::list::<Item>(ctx, filter, page).await
     }
 
     async fn item(ctx: &Context, id: String) -> FieldResult<Option<ItemNode>> {
-        item_node(ctx, id).await
+        resource::get::<Item>(ctx, &id).await
+    }
+
+    #[graphql(
+        description = "Lists the located items within a distance of a point, from the nearest; 100 \
+                       by default, up to 1000"
+    )]
+    async fn items_near(
+        ctx: &Context,
+        lat: f64,
+        lng: f64,
+        max_distance_meters: f64,
+        filter: Option<ItemFilterInput>,
+        limit: Option<i32>,
+    ) -> FieldResult<Vec<ItemDistanceNode>> {
+        items::items_near(ctx, lat, lng, max_distance_meters, filter, limit).await
+    }
+
+    async fn tags(
+        ctx: &Context,
+        filter: Option<ItemFilterInput>,
+    ) -> FieldResult<Vec<TagCountNode>> {
+        items::tags(ctx, filter).await
+    }
+
+    async fn owners(
+        ctx: &Context,
+        filter: Option<OwnerFilterInput>,
+        page: Option<PageInput>,
+    ) -> FieldResult<Vec<OwnerNode>> {
+        resource::list::<Owner>(ctx, filter, page).await
+    }
+
+    async fn owner(ctx: &Context, id: String) -> FieldResult<Option<OwnerNode>> {
+        resource::get::<Owner>(ctx, &id).await
+    }
+
+    async fn stock_levels(
+        ctx: &Context,
+        filter: Option<StockLevelFilterInput>,
+    ) -> FieldResult<Vec<StockLevelNode>> {
+        stock::stock_levels(ctx, filter).await
+    }
+
+    async fn stock_movements(
+        ctx: &Context,
+        filter: Option<StockMovementFilterInput>,
+        page: Option<PageInput>,
+    ) -> FieldResult<Vec<StockMovementNode>> {
+        stock::stock_movements(ctx, filter, page).await
     }
 }
 
@@ -29,15 +99,50 @@ pub struct Mutation;
 #[graphql_object(context = Context)]
 impl Mutation {
     async fn create_item(ctx: &Context, input: CreateItemInput) -> FieldResult<ItemNode> {
-        create_item_from_input(ctx, input).await
+        resource::create::<Item>(ctx, input).await
     }
 
     async fn update_item(ctx: &Context, input: UpdateItemInput) -> FieldResult<ItemNode> {
-        update_item_from_input(ctx, input).await
+        resource::update::<Item>(ctx, input).await
     }
 
-    async fn delete_item(ctx: &Context, id: String) -> FieldResult<String> {
-        delete_item_by_id(ctx, id.as_str()).await?;
+    async fn delete_item(
+        ctx: &Context,
+        id: String,
+        client_mutation_id: Option<String>,
+    ) -> FieldResult<String> {
+        resource::delete::<Item>(ctx, &id, client_mutation_id.as_deref()).await?;
         Ok(id)
     }
+
+    async fn create_owner(ctx: &Context, input: CreateOwnerInput) -> FieldResult<OwnerNode> {
+        resource::create::<Owner>(ctx, input).await
+    }
+
+    async fn update_owner(ctx: &Context, input: UpdateOwnerInput) -> FieldResult<OwnerNode> {
+        resource::update::<Owner>(ctx, input).await
+    }
+
+    async fn delete_owner(
+        ctx: &Context,
+        id: String,
+        client_mutation_id: Option<String>,
+    ) -> FieldResult<String> {
+        resource::delete::<Owner>(ctx, &id, client_mutation_id.as_deref()).await?;
+        Ok(id)
+    }
+
+    async fn adjust_stock(
+        ctx: &Context,
+        input: AdjustStockInput,
+    ) -> FieldResult<StockMovementNode> {
+        stock::adjust_stock(ctx, input).await
+    }
+
+    async fn transfer_stock(
+        ctx: &Context,
+        input: TransferStockInput,
+    ) -> FieldResult<StockMovementNode> {
+        stock::transfer_stock(ctx, input).await
+    }
 }
@@ -0,0 +1,255 @@
+pub use create::*;
+pub use find::*;
+pub use node::*;
+pub use update::*;
+
+mod resource {
+    use super::*;
+    use crate::{
+        api::{
+            context::{Context, DomainError},
+            schema::resource::{MutationInput, Resource},
+        },
+        common::{id::Id, idempotency::IdempotencyKey},
+        domain::models::owners::{Owner, OwnerFilter, OwnerPatch, OwnerSpec},
+        domain::Domain,
+    };
+    use async_trait::async_trait;
+    use std::sync::Arc;
+
+    #[async_trait]
+    impl Resource for Owner {
+        const NAME: &'static str = "owner";
+
+        type Node = OwnerNode;
+        type CreateInput = CreateOwnerInput;
+        type UpdateInput = UpdateOwnerInput;
+        type FilterInput = OwnerFilterInput;
+
+        async fn load(ctx: &Context, id: &Id<Owner>) -> Result<Option<Owner>, Arc<DomainError>> {
+            ctx.load_owner(id).await
+        }
+
+        async fn find_all(ctx: &Context, filter: &OwnerFilter) -> Result<Vec<Owner>, DomainError> {
+            ctx.domain().find_owners(filter).await
+        }
+
+        async fn find_page(
+            ctx: &Context,
+            filter: &OwnerFilter,
+            offset: usize,
+            limit: usize,
+        ) -> Result<Vec<Owner>, DomainError> {
+            ctx.domain().find_owners_page(filter, offset, limit).await
+        }
+
+        async fn create(
+            ctx: &Context,
+            spec: &OwnerSpec,
+            idempotency_key: Option<&IdempotencyKey>,
+        ) -> Result<Owner, DomainError> {
+            ctx.domain().create_owner(spec, idempotency_key).await
+        }
+
+        async fn update(
+            ctx: &Context,
+            patch: &OwnerPatch,
+            idempotency_key: Option<&IdempotencyKey>,
+        ) -> Result<Option<Owner>, DomainError> {
+            ctx.domain().update_owner(patch, idempotency_key).await
+        }
+
+        async fn delete(
+            ctx: &Context,
+            id: &Id<Owner>,
+            idempotency_key: Option<&IdempotencyKey>,
+        ) -> Result<Option<Owner>, DomainError> {
+            ctx.domain().delete_owner(id, idempotency_key).await
+        }
+    }
+
+    impl MutationInput for CreateOwnerInput {
+        fn client_mutation_id(&self) -> Option<&str> {
+            self.client_mutation_id.as_deref()
+        }
+    }
+
+    impl MutationInput for UpdateOwnerInput {
+        fn client_mutation_id(&self) -> Option<&str> {
+            self.client_mutation_id.as_deref()
+        }
+    }
+}
+
+mod node {
+    use crate::{
+        api::{
+            context::Context,
+            schema::{items::ItemNode, resource::Resource},
+        },
+        common::entity::Entity,
+        domain::models::{
+            items::{Item, ItemFilter},
+            owners::Owner,
+        },
+    };
+    use juniper::{graphql_object, FieldResult};
+
+    pub struct OwnerNode(Owner);
+
+    #[graphql_object(context = Context)]
+    #[graphql(name = "Owner", description = "A person who owns items")]
+    impl OwnerNode {
+        #[graphql(description = "The unique identifier for the owner")]
+        pub fn id(&self) -> String {
+            self.0.id().to_string()
+        }
+
+        #[graphql(description = "The name of the owner")]
+        pub fn name(&self) -> &str {
+            self.0.name()
+        }
+
+        #[graphql(description = "The email address of the owner, if it is known")]
+        pub fn email(&self) -> Option<&str> {
+            self.0.email()
+        }
+
+        #[graphql(description = "The items the owner owns")]
+        pub async fn items(&self, ctx: &Context) -> FieldResult<Vec<ItemNode>> {
+            let mut filter = ItemFilter::default();
+            *filter.owner_id_mut() = Some(self.0.id().clone());
+            let items = Item::find_all(ctx, &filter)
+                .await
+                .map_err(|e| ctx.field_error(e))?;
+            Ok(items.into_iter().map(ItemNode::from).collect())
+        }
+    }
+
+    impl From<Owner> for OwnerNode {
+        fn from(owner: Owner) -> Self {
+            Self(owner)
+        }
+    }
+}
+
+mod create {
+    use crate::{common::name::Name, domain::models::owners::OwnerSpec};
+
+    #[derive(juniper::GraphQLInputObject)]
+    #[graphql(description = "Input for creating an owner")]
+    pub struct CreateOwnerInput {
+        #[graphql(description = "The name of the owner to create")]
+        pub name: String,
+        #[graphql(description = "The email address of the owner to create")]
+        pub email: Option<String>,
+        #[graphql(description = "A key making the creation idempotent, if it is retried")]
+        pub client_mutation_id: Option<String>,
+    }
+
+    impl TryFrom<CreateOwnerInput> for OwnerSpec {
+        type Error = String;
+
+        fn try_from(input: CreateOwnerInput) -> Result<Self, Self::Error> {
+            match input.name.parse::<Name>() {
+                Ok(name) => {
+                    let mut spec = OwnerSpec::new(name);
+                    *spec.email_mut() = input.email;
+                    Ok(spec)
+                }
+                Err(_) => Err("name cannot be empty".into()),
+            }
+        }
+    }
+}
+
+mod update {
+    use crate::{
+        common::{id::Id, name::Name},
+        domain::models::owners::{Owner, OwnerPatch},
+        storage::patch::FieldPatch,
+    };
+    use juniper::Nullable;
+
+    #[derive(juniper::GraphQLInputObject)]
+    #[graphql(description = "Input for updating an owner")]
+    pub struct UpdateOwnerInput {
+        pub id: String,
+        pub name: Option<String>,
+        #[graphql(
+            description = "A new email address of the owner; null removes its email address"
+        )]
+        pub email: Nullable<String>,
+        #[graphql(description = "A key making the update idempotent, if it is retried")]
+        pub client_mutation_id: Option<String>,
+    }
+
+    impl TryFrom<UpdateOwnerInput> for OwnerPatch {
+        type Error = String;
+
+        fn try_from(input: UpdateOwnerInput) -> Result<Self, Self::Error> {
+            let id = input
+                .id
+                .parse::<Id<Owner>>()
+                .map_err(|_| String::from("the provided ID was invalid"))?;
+            let mut patch = OwnerPatch::new(id);
+
+            if let Some(s) = input.name.as_ref() {
+                *patch.name_mut() = Some(
+                    s.parse::<Name>()
+                        .map_err(|_| String::from("name cannot be empty"))?,
+                );
+            }
+
+            *patch.email_mut() = match input.email {
+                Nullable::ImplicitNull => FieldPatch::Unchanged,
+                Nullable::ExplicitNull => FieldPatch::Clear,
+                Nullable::Some(email) => FieldPatch::Set(email),
+            };
+
+            Ok(patch)
+        }
+    }
+}
+
+mod find {
+    use crate::{
+        common::{id::Id, name::Name},
+        domain::models::owners::{Owner, OwnerFilter},
+        storage::repo::Filter,
+    };
+
+    #[derive(juniper::GraphQLInputObject)]
+    #[graphql(description = "Input for finding owners")]
+    pub struct OwnerFilterInput {
+        pub id: Option<String>,
+        pub name: Option<String>,
+        pub email: Option<String>,
+    }
+
+    impl TryFrom<OwnerFilterInput> for OwnerFilter {
+        type Error = String;
+
+        fn try_from(input: OwnerFilterInput) -> Result<Self, Self::Error> {
+            let mut filter = OwnerFilter::default();
+
+            if let Some(s) = input.id.as_ref() {
+                *filter.id_mut() = Some(
+                    s.parse::<Id<Owner>>()
+                        .map_err(|_| String::from("the provided ID was invalid"))?,
+                );
+            }
+
+            if let Some(s) = input.name.as_ref() {
+                *filter.name_mut() = Some(
+                    s.parse::<Name>()
+                        .map_err(|_| String::from("cannot search for an empty name"))?,
+                );
+            }
+
+            *filter.email_mut() = input.email;
+
+            Ok(filter)
+        }
+    }
+}
@@ -0,0 +1,213 @@
+use crate::{
+    api::context::{Context, DomainError},
+    common::{id::Id, idempotency::IdempotencyKey},
+    storage::repo::Reposable,
+};
+use async_trait::async_trait;
+use juniper::{graphql_value, FieldError, FieldResult};
+use std::sync::Arc;
+
+/// The number of entities listed at once when a page does not specify a limit.
+pub const DEFAULT_PAGE_LIMIT: i32 = 100;
+
+/// The most entities that can be listed at once.
+pub const MAX_PAGE_LIMIT: i32 = 1000;
+
+/// An entity exposed through the GraphQL API, with the node and input types representing it there
+/// and the domain operations behind them. Implementing this is all it takes to resolve the fields
+/// getting, listing, creating, updating and deleting the entity with the functions in this module,
+/// so that every entity's fields behave and fail alike.
+#[async_trait]
+pub trait Resource: Reposable + Send + Sync + Sized + 'static {
+    /// The name of the entity in error messages, e.g. `item`.
+    const NAME: &'static str;
+
+    type Node: From<Self>;
+    type CreateInput: MutationInput + TryInto<Self::Spec, Error = String>;
+    type UpdateInput: MutationInput + TryInto<Self::Patch, Error = String>;
+    type FilterInput: TryInto<Self::Filter, Error = String>;
+
+    /// Loads an entity, in one batch with the other entities loaded while resolving the same level
+    /// of the query.
+    async fn load(ctx: &Context, id: &Id<Self>) -> Result<Option<Self>, Arc<DomainError>>;
+    async fn find_all(ctx: &Context, filter: &Self::Filter) -> Result<Vec<Self>, DomainError>;
+    async fn find_page(
+        ctx: &Context,
+        filter: &Self::Filter,
+        offset: usize,
+        limit: usize,
+    ) -> Result<Vec<Self>, DomainError>;
+    async fn create(
+        ctx: &Context,
+        spec: &Self::Spec,
+        idempotency_key: Option<&IdempotencyKey>,
+    ) -> Result<Self, DomainError>;
+    async fn update(
+        ctx: &Context,
+        patch: &Self::Patch,
+        idempotency_key: Option<&IdempotencyKey>,
+    ) -> Result<Option<Self>, DomainError>;
+    async fn delete(
+        ctx: &Context,
+        id: &Id<Self>,
+        idempotency_key: Option<&IdempotencyKey>,
+    ) -> Result<Option<Self>, DomainError>;
+}
+
+/// The input to a mutation, which can make the mutation idempotent.
+pub trait MutationInput {
+    fn client_mutation_id(&self) -> Option<&str>;
+}
+
+#[derive(Default, juniper::GraphQLInputObject)]
+#[graphql(description = "Input for listing a page of entities")]
+pub struct PageInput {
+    #[graphql(description = "The number of entities to skip; 0 by default")]
+    pub offset: Option<i32>,
+    #[graphql(description = "The most entities to list, up to 1000; 100 by default")]
+    pub limit: Option<i32>,
+}
+
+impl PageInput {
+    /// Returns the offset and limit of the page, if they are in range.
+    pub(super) fn bounds(&self) -> Result<(usize, usize), String> {
+        let offset = self.offset.unwrap_or(0);
+        let limit = self.limit.unwrap_or(DEFAULT_PAGE_LIMIT);
+        if offset < 0 {
+            return Err("the offset of a page cannot be negative".into());
+        }
+        if !(1..=MAX_PAGE_LIMIT).contains(&limit) {
+            return Err(format!(
+                "the limit of a page must be between 1 and {MAX_PAGE_LIMIT}"
+            ));
+        }
+        Ok((offset as usize, limit as usize))
+    }
+}
+
+/// Resolves the field getting an entity by ID.
+pub async fn get<R: Resource>(ctx: &Context, id: &str) -> FieldResult<Option<R::Node>> {
+    let id = parse_id::<R>(id)?;
+    let entity = R::load(ctx, &id).await.map_err(|e| ctx.field_error(e))?;
+    Ok(entity.map(R::Node::from))
+}
+
+/// Resolves the field listing the entities matching a filter; all of them, or only a page of
+/// them if a page is provided.
+pub async fn list<R: Resource>(
+    ctx: &Context,
+    filter: Option<R::FilterInput>,
+    page: Option<PageInput>,
+) -> FieldResult<Vec<R::Node>> {
+    let filter = match filter {
+        Some(filter) => filter.try_into().map_err(invalid_input)?,
+        None => R::Filter::default(),
+    };
+    let entities = match page {
+        Some(page) => {
+            let (offset, limit) = page.bounds().map_err(invalid_input)?;
+            R::find_page(ctx, &filter, offset, limit).await
+        }
+        None => R::find_all(ctx, &filter).await,
+    }
+    .map_err(|e| ctx.field_error(e))?;
+    Ok(entities.into_iter().map(R::Node::from).collect())
+}
+
+/// Resolves the mutation creating an entity.
+pub async fn create<R: Resource>(ctx: &Context, input: R::CreateInput) -> FieldResult<R::Node> {
+    let idempotency_key = idempotency_key(ctx, &input)?;
+    let spec = input.try_into().map_err(invalid_input)?;
+    let entity = R::create(ctx, &spec, idempotency_key.as_ref())
+        .await
+        .map_err(|e| ctx.field_error(e))?;
+    Ok(R::Node::from(entity))
+}
+
+/// Resolves the mutation updating an entity; it is an error for the entity not to exist.
+pub async fn update<R: Resource>(ctx: &Context, input: R::UpdateInput) -> FieldResult<R::Node> {
+    let idempotency_key = idempotency_key(ctx, &input)?;
+    let patch = input.try_into().map_err(invalid_input)?;
+    match R::update(ctx, &patch, idempotency_key.as_ref())
+        .await
+        .map_err(|e| ctx.field_error(e))?
+    {
+        Some(entity) => Ok(R::Node::from(entity)),
+        None => Err(not_found::<R>()),
+    }
+}
+
+/// Resolves the mutation deleting an entity; it is an error for the entity not to exist.
+pub async fn delete<R: Resource>(
+    ctx: &Context,
+    id: &str,
+    client_mutation_id: Option<&str>,
+) -> FieldResult<()> {
+    let id = parse_id::<R>(id)?;
+    let idempotency_key = ctx
+        .idempotency_key(client_mutation_id)
+        .map_err(invalid_input)?;
+    match R::delete(ctx, &id, idempotency_key.as_ref())
+        .await
+        .map_err(|e| ctx.field_error(e))?
+    {
+        Some(_) => Ok(()),
+        None => Err(not_found::<R>()),
+    }
+}
+
+fn parse_id<R: Resource>(id: &str) -> FieldResult<Id<R>> {
+    id.parse()
+        .map_err(|_| invalid_input("the provided ID was invalid"))
+}
+
+pub(super) fn idempotency_key(
+    ctx: &Context,
+    input: &impl MutationInput,
+) -> FieldResult<Option<IdempotencyKey>> {
+    ctx.idempotency_key(input.client_mutation_id())
+        .map_err(invalid_input)
+}
+
+/// An error reported with the code `INVALID_INPUT`, for arguments that cannot be used.
+pub(super) fn invalid_input(e: impl ToString) -> FieldError {
+    FieldError::new(e.to_string(), graphql_value!({ "code": "INVALID_INPUT" }))
+}
+
+/// An error reported with the code `NOT_FOUND`, for entities that must exist but do not.
+pub(super) fn not_found<R: Resource>() -> FieldError {
+    FieldError::new(
+        format!("no {} exists with the provided ID", R::NAME),
+        graphql_value!({ "code": "NOT_FOUND" }),
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn page(offset: Option<i32>, limit: Option<i32>) -> PageInput {
+        PageInput { offset, limit }
+    }
+
+    #[test]
+    fn page_defaults_to_first_page() {
+        assert_eq!(
+            page(None, None).bounds(),
+            Ok((0, DEFAULT_PAGE_LIMIT as usize))
+        );
+        assert_eq!(page(Some(20), Some(10)).bounds(), Ok((20, 10)));
+    }
+
+    #[test]
+    fn page_with_negative_offset_is_invalid() {
+        assert!(page(Some(-1), None).bounds().is_err());
+    }
+
+    #[test]
+    fn page_with_limit_out_of_range_is_invalid() {
+        assert!(page(None, Some(0)).bounds().is_err());
+        assert!(page(None, Some(MAX_PAGE_LIMIT + 1)).bounds().is_err());
+        assert!(page(None, Some(MAX_PAGE_LIMIT)).bounds().is_ok());
+    }
+}
@@ -0,0 +1,327 @@
+pub use find::*;
+pub use mutate::*;
+pub use node::*;
+
+mod node {
+    use crate::{
+        api::{context::Context, schema::items::ItemNode},
+        common::entity::Entity,
+        domain::models::stock::{StockLevel, StockMovement},
+    };
+    use juniper::{graphql_object, FieldResult};
+
+    pub struct StockLevelNode(StockLevel);
+
+    #[graphql_object(context = Context)]
+    #[graphql(
+        name = "StockLevel",
+        description = "The quantity of an item on hand at a location"
+    )]
+    impl StockLevelNode {
+        #[graphql(description = "The item in stock")]
+        pub async fn item(&self, ctx: &Context) -> FieldResult<Option<ItemNode>> {
+            let item = ctx
+                .load_item(self.0.item_id())
+                .await
+                .map_err(|e| ctx.field_error(e))?;
+            Ok(item.map(ItemNode::from))
+        }
+
+        #[graphql(description = "The location the item is stocked at")]
+        pub fn location(&self) -> &str {
+            self.0.location()
+        }
+
+        #[graphql(description = "The quantity of the item on hand at the location")]
+        pub fn quantity(&self) -> i32 {
+            i32::try_from(self.0.quantity()).unwrap_or(i32::MAX)
+        }
+    }
+
+    impl From<StockLevel> for StockLevelNode {
+        fn from(stock_level: StockLevel) -> Self {
+            Self(stock_level)
+        }
+    }
+
+    pub struct StockMovementNode(StockMovement);
+
+    #[graphql_object(context = Context)]
+    #[graphql(
+        name = "StockMovement",
+        description = "An entry in the ledger of movements of stock between locations"
+    )]
+    impl StockMovementNode {
+        #[graphql(description = "The unique identifier for the movement")]
+        pub fn id(&self) -> String {
+            self.0.id().to_string()
+        }
+
+        #[graphql(description = "The item moved")]
+        pub async fn item(&self, ctx: &Context) -> FieldResult<Option<ItemNode>> {
+            let item = ctx
+                .load_item(self.0.item_id())
+                .await
+                .map_err(|e| ctx.field_error(e))?;
+            Ok(item.map(ItemNode::from))
+        }
+
+        #[graphql(description = "The location the stock was taken from; null if it was added")]
+        pub fn from_location(&self) -> Option<&str> {
+            self.0.from_location().map(AsRef::as_ref)
+        }
+
+        #[graphql(description = "The location the stock was added at; null if it was taken")]
+        pub fn to_location(&self) -> Option<&str> {
+            self.0.to_location().map(AsRef::as_ref)
+        }
+
+        #[graphql(description = "The quantity of the item moved")]
+        pub fn quantity(&self) -> i32 {
+            i32::try_from(self.0.quantity()).unwrap_or(i32::MAX)
+        }
+
+        #[graphql(description = "When the movement was recorded, as an RFC 3339 timestamp")]
+        pub fn recorded_at(&self) -> String {
+            self.0
+                .recorded_at()
+                .try_to_rfc3339_string()
+                .unwrap_or_default()
+        }
+    }
+
+    impl From<StockMovement> for StockMovementNode {
+        fn from(stock_movement: StockMovement) -> Self {
+            Self(stock_movement)
+        }
+    }
+}
+
+mod find {
+    use super::{StockLevelNode, StockMovementNode};
+    use crate::{
+        api::{
+            context::Context,
+            schema::resource::{invalid_input, PageInput},
+        },
+        common::{id::Id, name::Name},
+        domain::{
+            models::{
+                items::Item,
+                stock::{StockLevelFilter, StockMovementFilter},
+            },
+            Domain,
+        },
+    };
+    use juniper::FieldResult;
+
+    #[derive(juniper::GraphQLInputObject)]
+    #[graphql(description = "Input for finding stock levels")]
+    pub struct StockLevelFilterInput {
+        pub item_id: Option<String>,
+        pub location: Option<String>,
+    }
+
+    impl TryFrom<StockLevelFilterInput> for StockLevelFilter {
+        type Error = String;
+
+        fn try_from(input: StockLevelFilterInput) -> Result<Self, Self::Error> {
+            let mut filter = StockLevelFilter::default();
+            *filter.item_id_mut() = input.item_id.as_deref().map(parse_item_id).transpose()?;
+            *filter.location_mut() = input.location.as_deref().map(parse_location).transpose()?;
+            Ok(filter)
+        }
+    }
+
+    #[derive(juniper::GraphQLInputObject)]
+    #[graphql(description = "Input for finding stock movements")]
+    pub struct StockMovementFilterInput {
+        pub item_id: Option<String>,
+        pub from_location: Option<String>,
+        pub to_location: Option<String>,
+    }
+
+    impl TryFrom<StockMovementFilterInput> for StockMovementFilter {
+        type Error = String;
+
+        fn try_from(input: StockMovementFilterInput) -> Result<Self, Self::Error> {
+            let mut filter = StockMovementFilter::default();
+            *filter.item_id_mut() = input.item_id.as_deref().map(parse_item_id).transpose()?;
+            *filter.from_location_mut() = input
+                .from_location
+                .as_deref()
+                .map(parse_location)
+                .transpose()?;
+            *filter.to_location_mut() = input
+                .to_location
+                .as_deref()
+                .map(parse_location)
+                .transpose()?;
+            Ok(filter)
+        }
+    }
+
+    /// Resolves the field listing the stock levels matching a filter.
+    pub async fn stock_levels(
+        ctx: &Context,
+        filter: Option<StockLevelFilterInput>,
+    ) -> FieldResult<Vec<StockLevelNode>> {
+        let filter = match filter {
+            Some(filter) => StockLevelFilter::try_from(filter).map_err(invalid_input)?,
+            None => StockLevelFilter::default(),
+        };
+        let stock_levels = ctx
+            .domain()
+            .stock_levels(&filter)
+            .await
+            .map_err(|e| ctx.field_error(e))?;
+        Ok(stock_levels.into_iter().map(StockLevelNode::from).collect())
+    }
+
+    /// Resolves the field listing a page of the stock movements matching a filter, from the first
+    /// recorded.
+    pub async fn stock_movements(
+        ctx: &Context,
+        filter: Option<StockMovementFilterInput>,
+        page: Option<PageInput>,
+    ) -> FieldResult<Vec<StockMovementNode>> {
+        let filter = match filter {
+            Some(filter) => StockMovementFilter::try_from(filter).map_err(invalid_input)?,
+            None => StockMovementFilter::default(),
+        };
+        let (offset, limit) = page.unwrap_or_default().bounds().map_err(invalid_input)?;
+        let stock_movements = ctx
+            .domain()
+            .find_stock_movements_page(&filter, offset, limit)
+            .await
+            .map_err(|e| ctx.field_error(e))?;
+        Ok(stock_movements
+            .into_iter()
+            .map(StockMovementNode::from)
+            .collect())
+    }
+
+    pub(super) fn parse_item_id(item_id: &str) -> Result<Id<Item>, String> {
+        item_id
+            .parse()
+            .map_err(|_| String::from("the provided item ID was invalid"))
+    }
+
+    pub(super) fn parse_location(location: &str) -> Result<Name, String> {
+        location
+            .parse()
+            .map_err(|_| String::from("a location cannot be empty"))
+    }
+}
+
+mod mutate {
+    use super::{
+        find::{parse_item_id, parse_location},
+        StockMovementNode,
+    };
+    use crate::{
+        api::{
+            context::Context,
+            schema::resource::{idempotency_key, invalid_input, not_found, MutationInput},
+        },
+        domain::{
+            models::{items::Item, stock::StockChange},
+            Domain,
+        },
+    };
+    use juniper::{graphql_value, FieldError, FieldResult};
+
+    #[derive(juniper::GraphQLInputObject)]
+    #[graphql(description = "Input for adding or taking stock of an item at a location")]
+    pub struct AdjustStockInput {
+        pub item_id: String,
+        pub location: String,
+        #[graphql(description = "The quantity to add; a negative quantity is taken")]
+        pub delta: i32,
+        #[graphql(description = "A key making the adjustment idempotent, if it is retried")]
+        pub client_mutation_id: Option<String>,
+    }
+
+    impl MutationInput for AdjustStockInput {
+        fn client_mutation_id(&self) -> Option<&str> {
+            self.client_mutation_id.as_deref()
+        }
+    }
+
+    #[derive(juniper::GraphQLInputObject)]
+    #[graphql(description = "Input for moving stock of an item from one location to another")]
+    pub struct TransferStockInput {
+        pub item_id: String,
+        pub from_location: String,
+        pub to_location: String,
+        #[graphql(description = "The quantity to move, which must be positive")]
+        pub quantity: i32,
+        #[graphql(description = "A key making the transfer idempotent, if it is retried")]
+        pub client_mutation_id: Option<String>,
+    }
+
+    impl MutationInput for TransferStockInput {
+        fn client_mutation_id(&self) -> Option<&str> {
+            self.client_mutation_id.as_deref()
+        }
+    }
+
+    /// Resolves the mutation adjusting the stock of an item at a location.
+    pub async fn adjust_stock(
+        ctx: &Context,
+        input: AdjustStockInput,
+    ) -> FieldResult<StockMovementNode> {
+        let idempotency_key = idempotency_key(ctx, &input)?;
+        let item_id = parse_item_id(&input.item_id).map_err(invalid_input)?;
+        let location = parse_location(&input.location).map_err(invalid_input)?;
+        let change = ctx
+            .domain()
+            .adjust_stock(
+                &item_id,
+                &location,
+                input.delta.into(),
+                idempotency_key.as_ref(),
+            )
+            .await
+            .map_err(|e| ctx.field_error(e))?;
+        stock_movement(change)
+    }
+
+    /// Resolves the mutation transferring stock of an item between locations.
+    pub async fn transfer_stock(
+        ctx: &Context,
+        input: TransferStockInput,
+    ) -> FieldResult<StockMovementNode> {
+        let idempotency_key = idempotency_key(ctx, &input)?;
+        let item_id = parse_item_id(&input.item_id).map_err(invalid_input)?;
+        let from = parse_location(&input.from_location).map_err(invalid_input)?;
+        let to = parse_location(&input.to_location).map_err(invalid_input)?;
+        let change = ctx
+            .domain()
+            .transfer_stock(
+                &item_id,
+                &from,
+                &to,
+                input.quantity.into(),
+                idempotency_key.as_ref(),
+            )
+            .await
+            .map_err(|e| ctx.field_error(e))?;
+        stock_movement(change)
+    }
+
+    /// Returns the movement a change to stock was recorded as, or the error it failed with; a lack
+    /// of stock is reported with the code `INSUFFICIENT_STOCK`, and an invalid change as invalid
+    /// input.
+    fn stock_movement(change: StockChange) -> FieldResult<StockMovementNode> {
+        match change {
+            StockChange::Made(movement) => Ok(StockMovementNode::from(movement)),
+            StockChange::ItemNotFound => Err(not_found::<Item>()),
+            StockChange::InsufficientStock => Err(FieldError::new(
+                "there is not enough stock of the item at the location to take",
+                graphql_value!({ "code": "INSUFFICIENT_STOCK" }),
+            )),
+            StockChange::Invalid(e) => Err(invalid_input(e)),
+        }
+    }
+}
@@ -1,32 +1,50 @@
-use crate::api::{
-    context::{Context, ContextFactory},
-    schema::{Mutation, Query},
+use crate::{
+    api::{
+        context::{Context, ContextError, ContextFactory},
+        schema::{Mutation, Query},
+    },
+    metrics::{
+        self, GRAPHQL_OPERATIONS, GRAPHQL_OPERATION_DURATION, HTTP_REQUESTS, HTTP_REQUEST_DURATION,
+    },
 };
 use futures::Future;
 use hyper::{
+    header::{self, HeaderValue},
     service::{make_service_fn, service_fn},
-    Body, Method, Response, Server, StatusCode,
+    Body, HeaderMap, Method, Request, Response, Server, StatusCode,
 };
 use juniper::{EmptySubscription, RootNode};
-use log::{debug, error, info};
 use std::{
     convert::Infallible,
     net::{IpAddr, Ipv4Addr},
     sync::Arc,
 };
+use tracing::{debug, error, info, info_span, Instrument};
+use uuid::Uuid;
 
 pub const DEFAULT_BIND_IP: IpAddr = IpAddr::V4(Ipv4Addr::new(0, 0, 0, 0));
 pub const DEFAULT_BIND_PORT: u16 = 3000;
 
+/// The header identifying a request; it is taken from the request if present, and always set on
+/// the response.
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+
+/// The longest request ID that is accepted from a client.
+const MAX_REQUEST_ID_LEN: usize = 128;
+
+/// The longest GraphQL operation name that is used as a metric label as-is.
+const MAX_OPERATION_LABEL_LEN: usize = 64;
+
+type Schema = RootNode<'static, Query, Mutation, EmptySubscription<Context>>;
+
 pub async fn run_api_server(
     bind_ip_addr: IpAddr,
     bind_port: u16,
-    mongo_client: mongodb::Client,
+    ctx_factory: ContextFactory,
     shutdown_signal: impl Future<Output = ()>,
 ) {
     info!("starting api server");
 
-    let ctx_factory = ContextFactory::new(mongo_client);
     let root_node = Arc::new(RootNode::new(
         Query,
         Mutation,
@@ -39,22 +57,67 @@ pub async fn run_api_server(
 
         async move {
             Ok::<_, hyper::Error>(service_fn(move |req| {
-                debug!("{} {} {:?}", req.method(), req.uri(), req.version());
-                let ctx = ctx_factory.create_context();
+                let request_id = request_id_from_headers(req.headers());
+                let span = info_span!(
+                    "http_request",
+                    request_id = request_id.to_str().unwrap_or_default(),
+                    method = %req.method(),
+                    path = %req.uri().path(),
+                );
+                let ctx_factory = ctx_factory.clone();
                 let root_node = root_node.clone();
                 async move {
-                    Ok::<_, Infallible>(match (req.method(), req.uri().path()) {
+                    debug!("{} {} {:?}", req.method(), req.uri(), req.version());
+                    let method = req.method().clone();
+                    let route = route_label(req.uri().path());
+                    let timer = HTTP_REQUEST_DURATION
+                        .with_label_values(&[method.as_str(), route])
+                        .start_timer();
+
+                    let response = match (req.method(), req.uri().path()) {
                         (&Method::GET, "/") => juniper_hyper::graphiql("/graphql", None).await,
                         (&Method::GET, "/graphql") | (&Method::POST, "/graphql") => {
-                            juniper_hyper::graphql(root_node, Arc::new(ctx), req).await
+                            match ctx_factory.create_context(req.headers()).await {
+                                Ok(ctx) => execute_graphql(root_node, ctx, req).await,
+                                Err(e) => {
+                                    let status = context_error_status(&e);
+                                    let mut response = Response::new(Body::from(e.to_string()));
+                                    *response.status_mut() = status;
+                                    if status == StatusCode::UNAUTHORIZED {
+                                        response.headers_mut().insert(
+                                            header::WWW_AUTHENTICATE,
+                                            HeaderValue::from_static("Bearer"),
+                                        );
+                                    }
+                                    response
+                                }
+                            }
+                        }
+                        (&Method::GET, "/metrics") => {
+                            let (content_type, body) = metrics::render();
+                            let mut response = Response::new(Body::from(body));
+                            response.headers_mut().insert(
+                                header::CONTENT_TYPE,
+                                content_type.parse().expect("invalid metrics content type"),
+                            );
+                            response
                         }
                         _ => {
                             let mut response = Response::new(Body::empty());
                             *response.status_mut() = StatusCode::NOT_FOUND;
                             response
                         }
-                    })
+                    };
+
+                    let mut response = response;
+                    timer.observe_duration();
+                    HTTP_REQUESTS
+                        .with_label_values(&[method.as_str(), route, response.status().as_str()])
+                        .inc();
+                    response.headers_mut().insert(REQUEST_ID_HEADER, request_id);
+                    Ok::<_, Infallible>(response)
                 }
+                .instrument(span)
             }))
         }
     });
@@ -69,3 +132,284 @@ pub async fn run_api_server(
 
     info!("stopped");
 }
+
+/// Executes a GraphQL request, recording metrics labelled with the name of the operation.
+async fn execute_graphql(
+    root_node: Arc<Schema>,
+    ctx: Context,
+    req: Request<Body>,
+) -> Response<Body> {
+    let (req, operation_name) = match graphql_operation_name(req).await {
+        Ok(result) => result,
+        Err(e) => {
+            let mut response = Response::new(Body::from(e.to_string()));
+            *response.status_mut() = StatusCode::BAD_REQUEST;
+            return response;
+        }
+    };
+    let operation = operation_label(operation_name.as_deref());
+
+    let timer = GRAPHQL_OPERATION_DURATION
+        .with_label_values(&[&operation])
+        .start_timer();
+    let (parts, body) = juniper_hyper::graphql(root_node, Arc::new(ctx), req)
+        .instrument(info_span!("graphql_operation", operation = %operation))
+        .await
+        .into_parts();
+    // the response is already serialized in full, so buffering it costs nothing
+    let body = hyper::body::to_bytes(body).await;
+    timer.observe_duration();
+
+    let (response, outcome) = match body {
+        Ok(body) => {
+            let outcome = operation_outcome(parts.status, &body);
+            (Response::from_parts(parts, Body::from(body)), outcome)
+        }
+        Err(e) => {
+            error!("error reading GraphQL response: {}", e);
+            let mut response = Response::new(Body::empty());
+            *response.status_mut() = StatusCode::INTERNAL_SERVER_ERROR;
+            (response, "error")
+        }
+    };
+    GRAPHQL_OPERATIONS
+        .with_label_values(&[&operation, outcome])
+        .inc();
+    response
+}
+
+/// Determines whether a GraphQL operation succeeded from its response. Operations that fail to
+/// parse or validate are rejected outright, but those that fail in a resolver still respond with
+/// `200 OK`, reporting the failure in the `errors` of the response body; a batch fails if any of
+/// its operations do.
+fn operation_outcome(status: StatusCode, body: &[u8]) -> &'static str {
+    let has_errors = |response: &serde_json::Value| {
+        response
+            .get("errors")
+            .and_then(serde_json::Value::as_array)
+            .is_some_and(|errors| !errors.is_empty())
+    };
+    let failed = match serde_json::from_slice::<serde_json::Value>(body) {
+        Ok(serde_json::Value::Array(responses)) => responses.iter().any(has_errors),
+        Ok(response) => has_errors(&response),
+        Err(_) => true,
+    };
+    match status.is_success() && !failed {
+        true => "ok",
+        false => "error",
+    }
+}
+
+/// Returns the status to reject a request with if a context could not be created for it.
+fn context_error_status(e: &ContextError) -> StatusCode {
+    match e {
+        ContextError::MissingCredentials | ContextError::InvalidCredentials => {
+            StatusCode::UNAUTHORIZED
+        }
+        ContextError::TenantMismatch => StatusCode::FORBIDDEN,
+        ContextError::TenantNotMigrated(_) => StatusCode::SERVICE_UNAVAILABLE,
+        _ => StatusCode::BAD_REQUEST,
+    }
+}
+
+/// Returns the ID of a request, taken from its headers if it has a usable one, otherwise newly
+/// generated.
+fn request_id_from_headers(headers: &HeaderMap) -> HeaderValue {
+    headers
+        .get(REQUEST_ID_HEADER)
+        .filter(|request_id| {
+            !request_id.is_empty()
+                && request_id.len() <= MAX_REQUEST_ID_LEN
+                && request_id.to_str().is_ok()
+        })
+        .cloned()
+        .unwrap_or_else(|| {
+            HeaderValue::from_str(&Uuid::new_v4().to_string())
+                .expect("invalid generated request ID")
+        })
+}
+
+/// Determines the name of the operation requested by a GraphQL request, buffering the request body
+/// if needed to do so.
+///
+/// # Returns
+/// the request, ready to be executed, and the name of the operation if it has one
+async fn graphql_operation_name(
+    req: Request<Body>,
+) -> Result<(Request<Body>, Option<String>), hyper::Error> {
+    if req.method() == Method::GET {
+        let operation_name = req.uri().query().and_then(|query| {
+            let params: Vec<(String, String)> = form_urlencoded::parse(query.as_bytes())
+                .into_owned()
+                .collect();
+            let param = |name: &str| params.iter().find(|(key, _)| key == name);
+            match param("operationName") {
+                Some((_, operation_name)) => Some(operation_name.clone()),
+                None => param("query").and_then(|(_, query)| operation_name_from_query(query)),
+            }
+        });
+        return Ok((req, operation_name));
+    }
+
+    let (parts, body) = req.into_parts();
+    let body = hyper::body::to_bytes(body).await?;
+    let is_json = parts
+        .headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|content_type| content_type.to_str().ok())
+        .is_some_and(is_json_media_type);
+
+    let operation_name = if is_json {
+        match serde_json::from_slice::<serde_json::Value>(&body) {
+            Ok(serde_json::Value::Object(request)) => {
+                match (request.get("operationName"), request.get("query")) {
+                    (Some(serde_json::Value::String(operation_name)), _) => {
+                        Some(operation_name.clone())
+                    }
+                    (_, Some(serde_json::Value::String(query))) => operation_name_from_query(query),
+                    _ => None,
+                }
+            }
+            Ok(serde_json::Value::Array(_)) => Some(String::from("batch")),
+            _ => None,
+        }
+    } else {
+        std::str::from_utf8(&body)
+            .ok()
+            .and_then(operation_name_from_query)
+    };
+
+    Ok((Request::from_parts(parts, Body::from(body)), operation_name))
+}
+
+/// Returns whether a `Content-Type` header value is the JSON media type, ignoring any parameters
+/// such as its charset.
+fn is_json_media_type(content_type: &str) -> bool {
+    content_type
+        .split(';')
+        .next()
+        .is_some_and(|media_type| media_type.trim().eq_ignore_ascii_case("application/json"))
+}
+
+/// Extracts the name of the first operation defined in a GraphQL document, if it is named.
+fn operation_name_from_query(query: &str) -> Option<String> {
+    let query = query.trim_start();
+    ["query", "mutation", "subscription"]
+        .iter()
+        .find_map(|keyword| query.strip_prefix(keyword))
+        .map(|rest| {
+            rest.trim_start()
+                .chars()
+                .take_while(|c| c.is_ascii_alphanumeric() || *c == '_')
+                .collect::<String>()
+        })
+        .filter(|name| !name.is_empty())
+}
+
+/// Maps an operation name to a metric label; since operation names are chosen by clients, anything
+/// that is not a reasonably sized GraphQL name is grouped under one label.
+fn operation_label(operation_name: Option<&str>) -> String {
+    match operation_name {
+        None => String::from("anonymous"),
+        Some(name)
+            if !name.is_empty()
+                && name.len() <= MAX_OPERATION_LABEL_LEN
+                && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') =>
+        {
+            name.to_string()
+        }
+        Some(_) => String::from("invalid"),
+    }
+}
+
+/// Maps a request path to a metric label, grouping unknown paths under one label.
+fn route_label(path: &str) -> &'static str {
+    match path {
+        "/" => "/",
+        "/graphql" => "/graphql",
+        "/metrics" => "/metrics",
+        _ => "other",
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn named_operation_name_is_extracted() {
+        let query =
+            "mutation CreateItem($input: CreateItemInput!) { createItem(input: $input) { id } }";
+        assert_eq!(
+            operation_name_from_query(query),
+            Some(String::from("CreateItem"))
+        );
+    }
+
+    #[test]
+    fn anonymous_operation_has_no_name() {
+        assert_eq!(operation_name_from_query("{ items { id } }"), None);
+        assert_eq!(operation_name_from_query("query { items { id } }"), None);
+    }
+
+    #[test]
+    fn unusable_operation_names_are_grouped() {
+        assert_eq!(operation_label(None), "anonymous");
+        assert_eq!(operation_label(Some("GetItems")), "GetItems");
+        assert_eq!(operation_label(Some("Get Items")), "invalid");
+        assert_eq!(
+            operation_label(Some(&"a".repeat(MAX_OPERATION_LABEL_LEN + 1))),
+            "invalid"
+        );
+    }
+
+    struct FailingQuery;
+
+    #[juniper::graphql_object]
+    impl FailingQuery {
+        fn ok() -> i32 {
+            1
+        }
+
+        fn fail() -> juniper::FieldResult<i32> {
+            Err("resolver failed".into())
+        }
+    }
+
+    fn outcome_of(query: &str) -> &'static str {
+        let schema = RootNode::new(
+            FailingQuery,
+            juniper::EmptyMutation::<()>::new(),
+            EmptySubscription::<()>::new(),
+        );
+        let request = juniper::http::GraphQLRequest::new(query.to_string(), None, None);
+        let response = request.execute_sync(&schema, &());
+        let body = serde_json::to_vec(&response).unwrap();
+        operation_outcome(StatusCode::OK, &body)
+    }
+
+    #[test]
+    fn json_media_type_is_recognised_with_parameters() {
+        assert!(is_json_media_type("application/json"));
+        assert!(is_json_media_type("application/json; charset=utf-8"));
+        assert!(is_json_media_type("Application/JSON;charset=UTF-8"));
+        assert!(!is_json_media_type("application/graphql"));
+        assert!(!is_json_media_type("application/jsonp"));
+    }
+
+    #[test]
+    fn resolver_error_counts_as_error() {
+        assert_eq!(outcome_of("{ ok }"), "ok");
+        assert_eq!(outcome_of("{ ok fail }"), "error");
+    }
+
+    #[test]
+    fn rejected_or_unreadable_response_counts_as_error() {
+        assert_eq!(operation_outcome(StatusCode::BAD_REQUEST, b"{}"), "error");
+        assert_eq!(operation_outcome(StatusCode::OK, b"not json"), "error");
+        assert_eq!(
+            operation_outcome(StatusCode::OK, br#"[{"data": {}}, {"errors": [{}]}]"#),
+            "error"
+        );
+    }
+}
@@ -1,20 +1,121 @@
 use ::mongo_repo::{
-    api::{self, server::run_api_server},
-    storage::mongo_repo::{self},
+    api::{
+        self,
+        auth::TenantKeys,
+        context::{ContextFactory, Tenancy},
+        server::run_api_server,
+    },
+    common::tenant::TenantId,
+    domain::{
+        models::{
+            items::Item,
+            owners::Owner,
+            stock::{StockLevel, StockMovement},
+        },
+        MongoDomainContext,
+    },
+    metrics::PoolMetricsHandler,
+    migrations::{self, MigrationError, Migrator, TenantMigrator},
+    storage::{
+        cached_repo::{self, RepoCache},
+        idempotency,
+        mongo_options::{
+            parse_read_concern, parse_read_preference, parse_validation_action,
+            parse_validation_level, parse_write_concern, MongoRepoOptions,
+        },
+        mongo_repo::{self, MongoRepo, MongoReposable, WriteMode},
+        namespace::NamespaceResolver,
+        retrying_repo::{self, RetryPolicy},
+        transfer::{self, ExportFormat},
+    },
+    telemetry::{self, LogFormat},
 };
 use futures::Future;
-use log::{error, info};
-use std::{env, net::IpAddr};
+use mongodb::{
+    bson::{Bson, Document},
+    options::TransactionOptions,
+};
+use std::{
+    collections::HashMap,
+    env,
+    error::Error,
+    fmt::Display,
+    fs::File,
+    io::{self, BufReader, BufWriter},
+    net::IpAddr,
+    path::PathBuf,
+    process,
+    str::FromStr,
+    sync::Arc,
+    time::Duration,
+};
 use tokio::{sync::oneshot, task::JoinHandle, try_join};
+use tracing::{error, info};
 
 const MONGO_HOST_ENV_KEY: &str = "MONGO_HOST";
 const MONGO_PORT_ENV_KEY: &str = "MONGO_PORT";
+const MONGO_DB_NAME_ENV_KEY: &str = "MONGO_DB_NAME";
+const MONGO_COLLECTION_PREFIX_ENV_KEY: &str = "MONGO_COLLECTION_PREFIX";
 const API_BIND_IP_ENV_KEY: &str = "API_BIND_IP";
 const API_BIND_PORT_ENV_KEY: &str = "API_BIND_PORT";
+const API_MULTI_TENANT_ENV_KEY: &str = "API_MULTI_TENANT";
+const API_TENANT_KEYS_FILE_ENV_KEY: &str = "API_TENANT_KEYS_FILE";
+const API_TRUST_TENANT_HEADER_ENV_KEY: &str = "API_TRUST_TENANT_HEADER";
+const API_REQUEST_TIMEOUT_MS_ENV_KEY: &str = "API_REQUEST_TIMEOUT_MS";
+const ITEM_CACHE_CAPACITY_ENV_KEY: &str = "ITEM_CACHE_CAPACITY";
+const ITEM_CACHE_TTL_SECS_ENV_KEY: &str = "ITEM_CACHE_TTL_SECS";
+const ITEM_CACHE_WATCH_CHANGES_ENV_KEY: &str = "ITEM_CACHE_WATCH_CHANGES";
+const MONGO_RETRY_MAX_ATTEMPTS_ENV_KEY: &str = "MONGO_RETRY_MAX_ATTEMPTS";
+const MONGO_RETRY_INITIAL_BACKOFF_MS_ENV_KEY: &str = "MONGO_RETRY_INITIAL_BACKOFF_MS";
+const MONGO_RETRY_MAX_BACKOFF_MS_ENV_KEY: &str = "MONGO_RETRY_MAX_BACKOFF_MS";
+const MONGO_WRITE_CONCERN_ENV_KEY: &str = "MONGO_WRITE_CONCERN";
+const MONGO_WRITE_JOURNAL_ENV_KEY: &str = "MONGO_WRITE_JOURNAL";
+const MONGO_READ_CONCERN_ENV_KEY: &str = "MONGO_READ_CONCERN";
+const MONGO_READ_PREFERENCE_ENV_KEY: &str = "MONGO_READ_PREFERENCE";
+const MONGO_LIST_READ_PREFERENCE_ENV_KEY: &str = "MONGO_LIST_READ_PREFERENCE";
+const MONGO_TRANSACTION_WRITE_CONCERN_ENV_KEY: &str = "MONGO_TRANSACTION_WRITE_CONCERN";
+const MONGO_TRANSACTION_READ_CONCERN_ENV_KEY: &str = "MONGO_TRANSACTION_READ_CONCERN";
+const IDEMPOTENCY_KEY_TTL_SECS_ENV_KEY: &str = "IDEMPOTENCY_KEY_TTL_SECS";
+const MIGRATE_ON_STARTUP_ENV_KEY: &str = "MIGRATE_ON_STARTUP";
+const MONGO_APPLY_VALIDATORS_ENV_KEY: &str = "MONGO_APPLY_VALIDATORS";
+const MONGO_VALIDATION_LEVEL_ENV_KEY: &str = "MONGO_VALIDATION_LEVEL";
+const MONGO_VALIDATION_ACTION_ENV_KEY: &str = "MONGO_VALIDATION_ACTION";
+const LOG_FORMAT_ENV_KEY: &str = "LOG_FORMAT";
+const OTLP_ENDPOINT_ENV_KEY: &str = "OTEL_EXPORTER_OTLP_ENDPOINT";
+
+const USAGE: &str = "\
+usage: mongo_repo
+       mongo_repo migrate (status | up [<version>] | down [<version>]) [--tenant <tenant ID>]
+       mongo_repo export <collection> [--format ndjson|ejson] [--filter <query>] [--output <file>]
+                         [--tenant <tenant ID>]
+       mongo_repo import <file> [--mode insert|upsert] [--collection <collection>]
+                         [--errors <file>] [--batch-size <size>] [--tenant <tenant ID>]";
 
 #[tokio::main]
 async fn main() {
-    env_logger::init();
+    let log_format = env::var(LOG_FORMAT_ENV_KEY)
+        .map(|log_format_string| {
+            log_format_string
+                .parse::<LogFormat>()
+                .unwrap_or_else(|_| panic!("invalid log format: {log_format_string}"))
+        })
+        .unwrap_or(LogFormat::Text);
+    let otlp_endpoint = env::var(OTLP_ENDPOINT_ENV_KEY).ok();
+    let _telemetry_guard = telemetry::init(log_format, otlp_endpoint)
+        .unwrap_or_else(|e| panic!("error initializing telemetry: {}", e));
+
+    let args = env::args().skip(1).collect::<Vec<_>>();
+    if let Some(command) = args.first() {
+        let command = parse_command(command, &args[1..]).unwrap_or_else(|e| {
+            eprintln!("{e}\n{USAGE}");
+            process::exit(2);
+        });
+        if let Err(e) = run_command(command).await {
+            eprintln!("error: {e}");
+            process::exit(1);
+        }
+        return;
+    }
 
     let (tx_shutdown, rx_shutdown) = oneshot::channel::<()>();
     let shutdown_signal = async move {
@@ -40,16 +141,8 @@ async fn main() {
 
 fn start_api_server(shutdown_signal: impl Future<Output = ()> + Send + 'static) -> JoinHandle<()> {
     // get mongo info
-    let mongo_host =
-        env::var(MONGO_HOST_ENV_KEY).unwrap_or_else(|_| mongo_repo::DEFAULT_HOST.into());
-    let mongo_port = env::var(MONGO_PORT_ENV_KEY)
-        .map(|mongo_port_string| {
-            mongo_port_string
-                .parse::<u16>()
-                .unwrap_or_else(|_| panic!("invalid mongo port: {mongo_port_string}"))
-        })
-        .unwrap_or(mongo_repo::DEFAULT_PORT);
-    let mongo_connect_string = format!("mongodb://{mongo_host}:{mongo_port}");
+    let mongo_connect_string = mongo_connect_string_from_env();
+    let namespace = namespace_from_env();
 
     // get server bind info
     let server_bind_ip = env::var(API_BIND_IP_ENV_KEY)
@@ -67,28 +160,603 @@ fn start_api_server(shutdown_signal: impl Future<Output = ()> + Send + 'static)
         })
         .unwrap_or(api::server::DEFAULT_BIND_PORT);
 
+    // get tenancy info
+    let tenancy = env::var(API_MULTI_TENANT_ENV_KEY)
+        .map(|multi_tenant_string| {
+            match multi_tenant_string
+                .parse::<bool>()
+                .unwrap_or_else(|_| panic!("invalid multi-tenant flag: {multi_tenant_string}"))
+            {
+                true => Tenancy::Multi,
+                false => Tenancy::Single,
+            }
+        })
+        .unwrap_or(Tenancy::Single);
+    let tenant_keys = env::var(API_TENANT_KEYS_FILE_ENV_KEY)
+        .map(|path| {
+            std::fs::read_to_string(&path)
+                .unwrap_or_else(|e| panic!("error reading tenant API keys from {path}: {e}"))
+                .parse::<TenantKeys>()
+                .unwrap_or_else(|e| panic!("invalid tenant API keys in {path}: {e}"))
+        })
+        .unwrap_or_default();
+    let trust_tenant_header = env::var(API_TRUST_TENANT_HEADER_ENV_KEY)
+        .map(|trust_string| {
+            trust_string
+                .parse::<bool>()
+                .unwrap_or_else(|_| panic!("invalid trust tenant header flag: {trust_string}"))
+        })
+        .unwrap_or(false);
+    if tenancy == Tenancy::Multi {
+        for db_name in [
+            Item::db_name(),
+            Owner::db_name(),
+            StockLevel::db_name(),
+            StockMovement::db_name(),
+        ] {
+            namespace
+                .check_tenant_db_name(db_name)
+                .unwrap_or_else(|e| panic!("invalid multi-tenant namespace: {e}"));
+        }
+    }
+    if tenancy == Tenancy::Multi && tenant_keys.is_empty() && !trust_tenant_header {
+        panic!(
+            "multi-tenant mode requires {API_TENANT_KEYS_FILE_ENV_KEY} or \
+             {API_TRUST_TENANT_HEADER_ENV_KEY}, so that requests can be authenticated as tenants"
+        );
+    }
+
+    // get request timeout info
+    let request_timeout = env::var(API_REQUEST_TIMEOUT_MS_ENV_KEY)
+        .map(|timeout_string| {
+            Duration::from_millis(
+                timeout_string
+                    .parse::<u64>()
+                    .unwrap_or_else(|_| panic!("invalid request timeout: {timeout_string}")),
+            )
+        })
+        .unwrap_or(api::context::DEFAULT_REQUEST_TIMEOUT);
+
+    // get idempotency info
+    let idempotency_ttl = env::var(IDEMPOTENCY_KEY_TTL_SECS_ENV_KEY)
+        .map(|ttl_string| {
+            Duration::from_secs(
+                ttl_string
+                    .parse::<u64>()
+                    .unwrap_or_else(|_| panic!("invalid idempotency key TTL: {ttl_string}")),
+            )
+        })
+        .unwrap_or(idempotency::DEFAULT_TTL);
+
+    // get migration info
+    let migrate_on_startup = env::var(MIGRATE_ON_STARTUP_ENV_KEY)
+        .map(|migrate_string| {
+            migrate_string
+                .parse::<bool>()
+                .unwrap_or_else(|_| panic!("invalid migrate on startup flag: {migrate_string}"))
+        })
+        .unwrap_or(false);
+
+    // get schema validation info
+    let apply_validators = env::var(MONGO_APPLY_VALIDATORS_ENV_KEY)
+        .map(|apply_string| {
+            apply_string
+                .parse::<bool>()
+                .unwrap_or_else(|_| panic!("invalid apply validators flag: {apply_string}"))
+        })
+        .unwrap_or(false);
+    let validation_level = env::var(MONGO_VALIDATION_LEVEL_ENV_KEY)
+        .map(|level_string| {
+            parse_validation_level(&level_string)
+                .unwrap_or_else(|e| panic!("invalid validation level: {e}"))
+        })
+        .unwrap_or(mongo_repo::DEFAULT_VALIDATION_LEVEL);
+    let validation_action = env::var(MONGO_VALIDATION_ACTION_ENV_KEY)
+        .map(|action_string| {
+            parse_validation_action(&action_string)
+                .unwrap_or_else(|e| panic!("invalid validation action: {e}"))
+        })
+        .unwrap_or(mongo_repo::DEFAULT_VALIDATION_ACTION);
+
+    // get item cache info
+    let item_cache_capacity = env::var(ITEM_CACHE_CAPACITY_ENV_KEY)
+        .map(|capacity_string| {
+            capacity_string
+                .parse::<usize>()
+                .unwrap_or_else(|_| panic!("invalid item cache capacity: {capacity_string}"))
+        })
+        .unwrap_or(cached_repo::DEFAULT_CAPACITY);
+    let item_cache_ttl = env::var(ITEM_CACHE_TTL_SECS_ENV_KEY)
+        .map(|ttl_string| {
+            Duration::from_secs(
+                ttl_string
+                    .parse::<u64>()
+                    .unwrap_or_else(|_| panic!("invalid item cache TTL: {ttl_string}")),
+            )
+        })
+        .unwrap_or(cached_repo::DEFAULT_TTL);
+    let item_cache_watch_changes = env::var(ITEM_CACHE_WATCH_CHANGES_ENV_KEY)
+        .map(|watch_string| {
+            watch_string
+                .parse::<bool>()
+                .unwrap_or_else(|_| panic!("invalid item cache watch flag: {watch_string}"))
+        })
+        .unwrap_or(false);
+
+    // get retry info
+    let retry_max_attempts = env::var(MONGO_RETRY_MAX_ATTEMPTS_ENV_KEY)
+        .map(|max_attempts_string| {
+            max_attempts_string
+                .parse::<u32>()
+                .unwrap_or_else(|_| panic!("invalid retry max attempts: {max_attempts_string}"))
+        })
+        .unwrap_or(retrying_repo::DEFAULT_MAX_ATTEMPTS);
+    let retry_initial_backoff = env::var(MONGO_RETRY_INITIAL_BACKOFF_MS_ENV_KEY)
+        .map(|backoff_string| {
+            Duration::from_millis(
+                backoff_string
+                    .parse::<u64>()
+                    .unwrap_or_else(|_| panic!("invalid retry initial backoff: {backoff_string}")),
+            )
+        })
+        .unwrap_or(retrying_repo::DEFAULT_INITIAL_BACKOFF);
+    let retry_max_backoff = env::var(MONGO_RETRY_MAX_BACKOFF_MS_ENV_KEY)
+        .map(|backoff_string| {
+            Duration::from_millis(
+                backoff_string
+                    .parse::<u64>()
+                    .unwrap_or_else(|_| panic!("invalid retry max backoff: {backoff_string}")),
+            )
+        })
+        .unwrap_or(retrying_repo::DEFAULT_MAX_BACKOFF);
+    let retry_policy =
+        RetryPolicy::new(retry_max_attempts, retry_initial_backoff, retry_max_backoff);
+
+    // get read/write concern and read preference info
+    let write_journal = env::var(MONGO_WRITE_JOURNAL_ENV_KEY)
+        .ok()
+        .map(|journal_string| {
+            journal_string
+                .parse::<bool>()
+                .unwrap_or_else(|_| panic!("invalid write journal flag: {journal_string}"))
+        });
+    let parse_write_concern_env = |key: &str| {
+        env::var(key).ok().map(|w_string| {
+            parse_write_concern(&w_string, write_journal)
+                .unwrap_or_else(|e| panic!("invalid write concern: {e}"))
+        })
+    };
+    let parse_read_concern_env = |key: &str| {
+        env::var(key).ok().map(|level_string| {
+            parse_read_concern(&level_string)
+                .unwrap_or_else(|e| panic!("invalid read concern: {e}"))
+        })
+    };
+    let parse_read_preference_env = |key: &str| {
+        env::var(key).ok().map(|mode_string| {
+            parse_read_preference(&mode_string)
+                .unwrap_or_else(|e| panic!("invalid read preference: {e}"))
+        })
+    };
+    let mut repo_options = MongoRepoOptions::new();
+    if let Some(write_concern) = parse_write_concern_env(MONGO_WRITE_CONCERN_ENV_KEY) {
+        repo_options = repo_options.with_write_concern(write_concern);
+    }
+    if let Some(read_concern) = parse_read_concern_env(MONGO_READ_CONCERN_ENV_KEY) {
+        repo_options = repo_options.with_read_concern(read_concern);
+    }
+    if let Some(read_preference) = parse_read_preference_env(MONGO_READ_PREFERENCE_ENV_KEY) {
+        repo_options = repo_options.with_read_preference(read_preference);
+    }
+    if let Some(read_preference) = parse_read_preference_env(MONGO_LIST_READ_PREFERENCE_ENV_KEY) {
+        repo_options = repo_options.with_list_read_preference(read_preference);
+    }
+    let transaction_options = TransactionOptions::builder()
+        .write_concern(parse_write_concern_env(
+            MONGO_TRANSACTION_WRITE_CONCERN_ENV_KEY,
+        ))
+        .read_concern(parse_read_concern_env(
+            MONGO_TRANSACTION_READ_CONCERN_ENV_KEY,
+        ))
+        .build();
+
     tokio::spawn(async move {
-        // create the mongo client
-        info!("creating mongo client for {}", mongo_connect_string);
-        let mongo_client_options =
-            mongodb::options::ClientOptions::parse(mongo_connect_string.as_str())
+        let mongo_client = create_mongo_client(&mongo_connect_string).await;
+
+        // bring the database(s) up to date, as far as requested
+        let namespaces = match tenancy {
+            Tenancy::Single => vec![namespace.clone()],
+            Tenancy::Multi => tenant_namespaces(&mongo_client, &namespace)
                 .await
-                .unwrap_or_else(|e| panic!("error creating mongo client options: {:?}", e));
-        let mongo_client = mongodb::Client::with_options(mongo_client_options)
-            .unwrap_or_else(|e| panic!("error creating mongo client: {:?}", e));
+                .unwrap_or_else(|e| panic!("error listing tenant databases: {}", e)),
+        };
+        for namespace in namespaces {
+            if migrate_on_startup {
+                let applied = migrator_for(&mongo_client, namespace.clone())
+                    .up(None)
+                    .await
+                    .unwrap_or_else(|e| panic!("error migrating database: {}", e));
+                info!("applied {} migration(s) on startup", applied.len());
+            }
+            // items can't be found by location without their geo index, so it's created whether
+            // or not migrations are run
+            MongoRepo::<Item>::new(mongo_client.clone(), namespace.clone())
+                .create_geo_indexes()
+                .await
+                .unwrap_or_else(|e| panic!("error creating item geo indexes: {}", e));
+            if apply_validators {
+                MongoRepo::<Item>::new(mongo_client.clone(), namespace.clone())
+                    .apply_validator(validation_level.clone(), validation_action.clone())
+                    .await
+                    .unwrap_or_else(|e| panic!("error applying item validator: {}", e));
+                MongoRepo::<Owner>::new(mongo_client.clone(), namespace.clone())
+                    .apply_validator(validation_level.clone(), validation_action.clone())
+                    .await
+                    .unwrap_or_else(|e| panic!("error applying owner validator: {}", e));
+                MongoRepo::<StockLevel>::new(mongo_client.clone(), namespace.clone())
+                    .apply_validator(validation_level.clone(), validation_action.clone())
+                    .await
+                    .unwrap_or_else(|e| panic!("error applying stock level validator: {}", e));
+                MongoRepo::<StockMovement>::new(mongo_client.clone(), namespace)
+                    .apply_validator(validation_level.clone(), validation_action.clone())
+                    .await
+                    .unwrap_or_else(|e| panic!("error applying stock movement validator: {}", e));
+            }
+        }
+
+        // create the item cache, invalidating it on changes made elsewhere if requested
+        let items_cache = RepoCache::new(item_cache_capacity, item_cache_ttl);
+        if item_cache_watch_changes {
+            let items_repo = MongoRepo::<Item>::new(mongo_client.clone(), namespace.clone());
+            let items_cache = items_cache.clone();
+            tokio::spawn(async move {
+                items_cache
+                    .invalidate_from(items_repo.watch_changed_ids())
+                    .await
+            });
+        }
 
         // start the server
         info!(
             "starting api server on {}:{}",
             server_bind_ip, server_bind_port
         );
+        let tenant_migrator =
+            TenantMigrator::new(mongo_client.clone(), namespace.clone(), migrate_on_startup);
+        let domain_ctx = MongoDomainContext::new(mongo_client, namespace)
+            .with_retry_policy(retry_policy)
+            .with_repo_options(repo_options)
+            .with_transaction_options(transaction_options)
+            .with_idempotency_ttl(idempotency_ttl)
+            .with_cache(items_cache);
+        let ctx_factory = ContextFactory::new(domain_ctx, tenancy)
+            .with_tenant_keys(tenant_keys)
+            .with_trusted_tenant_header(trust_tenant_header)
+            .with_tenant_migrator(tenant_migrator)
+            .with_request_timeout(request_timeout);
         run_api_server(
             server_bind_ip,
             server_bind_port,
-            mongo_client,
+            ctx_factory,
             shutdown_signal,
         )
         .await;
         info!("api server stopped");
     })
 }
+
+fn mongo_connect_string_from_env() -> String {
+    let mongo_host =
+        env::var(MONGO_HOST_ENV_KEY).unwrap_or_else(|_| mongo_repo::DEFAULT_HOST.into());
+    let mongo_port = env::var(MONGO_PORT_ENV_KEY)
+        .map(|mongo_port_string| {
+            mongo_port_string
+                .parse::<u16>()
+                .unwrap_or_else(|_| panic!("invalid mongo port: {mongo_port_string}"))
+        })
+        .unwrap_or(mongo_repo::DEFAULT_PORT);
+    format!("mongodb://{mongo_host}:{mongo_port}")
+}
+
+fn namespace_from_env() -> NamespaceResolver {
+    let mut namespace = NamespaceResolver::new();
+    if let Ok(db_name) = env::var(MONGO_DB_NAME_ENV_KEY) {
+        namespace = namespace.with_db_name(db_name);
+    }
+    if let Ok(collection_prefix) = env::var(MONGO_COLLECTION_PREFIX_ENV_KEY) {
+        namespace = namespace.with_collection_prefix(collection_prefix);
+    }
+    namespace
+}
+
+async fn create_mongo_client(mongo_connect_string: &str) -> mongodb::Client {
+    info!("creating mongo client for {}", mongo_connect_string);
+    let mut mongo_client_options = mongodb::options::ClientOptions::parse(mongo_connect_string)
+        .await
+        .unwrap_or_else(|e| panic!("error creating mongo client options: {:?}", e));
+    mongo_client_options.cmap_event_handler = Some(Arc::new(PoolMetricsHandler));
+    mongodb::Client::with_options(mongo_client_options)
+        .unwrap_or_else(|e| panic!("error creating mongo client: {:?}", e))
+}
+
+fn migrator_for(mongo_client: &mongodb::Client, namespace: NamespaceResolver) -> Migrator {
+    let db = mongo_client.database(&namespace.db_name(Item::db_name()));
+    Migrator::new(db, namespace, migrations::all())
+        .unwrap_or_else(|e| panic!("invalid migrations: {}", e))
+}
+
+/// Returns the namespaces of every tenant that has a database.
+async fn tenant_namespaces(
+    mongo_client: &mongodb::Client,
+    namespace: &NamespaceResolver,
+) -> Result<Vec<NamespaceResolver>, mongodb::error::Error> {
+    Ok(mongo_client
+        .list_database_names(None, None)
+        .await?
+        .iter()
+        .filter_map(|db_name| namespace.tenant_of_db(db_name, Item::db_name()))
+        .map(|tenant_id| namespace.for_tenant(tenant_id))
+        .collect())
+}
+
+/// A one-off command run by the binary instead of the api server.
+enum Command {
+    Migrate {
+        command: MigrateCommand,
+        tenant_id: Option<TenantId>,
+    },
+    Export {
+        collection: String,
+        format: ExportFormat,
+        filter: Document,
+        output: Option<PathBuf>,
+        tenant_id: Option<TenantId>,
+    },
+    Import {
+        file: PathBuf,
+        collection: String,
+        mode: WriteMode,
+        errors: PathBuf,
+        batch_size: usize,
+        tenant_id: Option<TenantId>,
+    },
+}
+
+enum MigrateCommand {
+    Status,
+    Up(Option<u32>),
+    Down(Option<u32>),
+}
+
+/// The positional arguments and `--name value` options of a command.
+struct CommandArgs {
+    positional: Vec<String>,
+    options: HashMap<String, String>,
+}
+
+impl CommandArgs {
+    fn parse(args: &[String], allowed_options: &[&str]) -> Result<Self, String> {
+        let mut positional = vec![];
+        let mut options = HashMap::new();
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            match arg.strip_prefix("--") {
+                Some(name) if allowed_options.contains(&name) => {
+                    let value = args.next().ok_or(format!("--{name} requires a value"))?;
+                    options.insert(name.to_string(), value.clone());
+                }
+                Some(_) => return Err(format!("unexpected option: {arg}")),
+                None => positional.push(arg.clone()),
+            }
+        }
+        Ok(Self {
+            positional,
+            options,
+        })
+    }
+
+    fn option<T>(&self, name: &str) -> Result<Option<T>, String>
+    where
+        T: FromStr,
+        T::Err: Display,
+    {
+        self.options
+            .get(name)
+            .map(|value| {
+                value
+                    .parse::<T>()
+                    .map_err(|e| format!("invalid --{name} {value}: {e}"))
+            })
+            .transpose()
+    }
+}
+
+fn parse_command(command: &str, args: &[String]) -> Result<Command, String> {
+    match command {
+        "migrate" => {
+            let args = CommandArgs::parse(args, &["tenant"])?;
+            let target = match args.positional.get(1) {
+                Some(version_string) => Some(
+                    version_string
+                        .parse::<u32>()
+                        .map_err(|_| format!("invalid migration version: {version_string}"))?,
+                ),
+                None => None,
+            };
+            let command = match (args.positional.first().map(String::as_str), target) {
+                (Some("status"), None) if args.positional.len() == 1 => MigrateCommand::Status,
+                (Some("up"), target) if args.positional.len() <= 2 => MigrateCommand::Up(target),
+                (Some("down"), target) if args.positional.len() <= 2 => {
+                    MigrateCommand::Down(target)
+                }
+                _ => return Err(String::from("invalid migrate command")),
+            };
+            Ok(Command::Migrate {
+                command,
+                tenant_id: args.option("tenant")?,
+            })
+        }
+        "export" => {
+            let args = CommandArgs::parse(args, &["format", "filter", "output", "tenant"])?;
+            let [collection] = args.positional.as_slice() else {
+                return Err(String::from("export requires exactly one collection"));
+            };
+            let filter = match args.options.get("filter") {
+                Some(filter_string) => serde_json::from_str::<serde_json::Value>(filter_string)
+                    .ok()
+                    .and_then(|filter| Bson::try_from(filter).ok())
+                    .and_then(|filter| filter.as_document().cloned())
+                    .ok_or(format!("invalid --filter {filter_string}"))?,
+                None => Document::new(),
+            };
+            Ok(Command::Export {
+                collection: collection.clone(),
+                format: args.option("format")?.unwrap_or(ExportFormat::Ndjson),
+                filter,
+                output: args.option("output")?,
+                tenant_id: args.option("tenant")?,
+            })
+        }
+        "import" => {
+            let args = CommandArgs::parse(
+                args,
+                &["mode", "collection", "errors", "batch-size", "tenant"],
+            )?;
+            let [file] = args.positional.as_slice() else {
+                return Err(String::from("import requires exactly one file"));
+            };
+            let file = PathBuf::from(file);
+            // by default, the collection is named by the file, e.g. items.ndjson
+            let collection = match args.options.get("collection") {
+                Some(collection) => collection.clone(),
+                None => file
+                    .file_name()
+                    .and_then(|file_name| file_name.to_str())
+                    .and_then(|file_name| file_name.split('.').next())
+                    .ok_or("--collection is required for this file")?
+                    .to_string(),
+            };
+            let errors = args
+                .option("errors")?
+                .unwrap_or_else(|| PathBuf::from(format!("{}.errors.ndjson", file.display())));
+            Ok(Command::Import {
+                file,
+                collection,
+                mode: args.option("mode")?.unwrap_or(WriteMode::Insert),
+                errors,
+                batch_size: args
+                    .option("batch-size")?
+                    .unwrap_or(transfer::DEFAULT_BATCH_SIZE),
+                tenant_id: args.option("tenant")?,
+            })
+        }
+        _ => Err(format!("unknown command: {command}")),
+    }
+}
+
+async fn run_command(command: Command) -> Result<(), Box<dyn Error>> {
+    let mongo_client = create_mongo_client(&mongo_connect_string_from_env()).await;
+    let tenant_namespace = |tenant_id: Option<TenantId>| {
+        let namespace = namespace_from_env();
+        match tenant_id {
+            Some(tenant_id) => namespace.for_tenant(tenant_id),
+            None => namespace,
+        }
+    };
+
+    match command {
+        Command::Migrate { command, tenant_id } => {
+            let migrator = migrator_for(&mongo_client, tenant_namespace(tenant_id));
+            run_migrate_command(&migrator, command).await?;
+        }
+        Command::Export {
+            collection,
+            format,
+            filter,
+            output,
+            tenant_id,
+        } => {
+            if collection != Item::collection_name() {
+                return Err(format!("unknown collection: {collection}").into());
+            }
+            let repo = MongoRepo::<Item>::new(mongo_client, tenant_namespace(tenant_id));
+            let count = match output {
+                Some(output) => {
+                    let mut output = BufWriter::new(File::create(output)?);
+                    transfer::export(&repo, filter, format, &mut output).await?
+                }
+                None => transfer::export(&repo, filter, format, &mut io::stdout().lock()).await?,
+            };
+            eprintln!("exported {count} record(s)");
+        }
+        Command::Import {
+            file,
+            collection,
+            mode,
+            errors,
+            batch_size,
+            tenant_id,
+        } => {
+            if collection != Item::collection_name() {
+                return Err(format!("unknown collection: {collection}").into());
+            }
+            let repo = MongoRepo::<Item>::new(mongo_client, tenant_namespace(tenant_id));
+            let input = BufReader::new(File::open(&file)?);
+            let mut errors_output = BufWriter::new(File::create(&errors)?);
+            let progress = transfer::import(
+                &repo,
+                input,
+                mode,
+                batch_size,
+                &mut errors_output,
+                |progress| {
+                    eprintln!(
+                        "read {} record(s): {} written, {} failed",
+                        progress.read, progress.written, progress.failed
+                    )
+                },
+            )
+            .await?;
+            if progress.failed > 0 {
+                return Err(format!(
+                    "{} record(s) could not be imported; see {}",
+                    progress.failed,
+                    errors.display()
+                )
+                .into());
+            }
+        }
+    }
+    Ok(())
+}
+
+async fn run_migrate_command(
+    migrator: &Migrator,
+    command: MigrateCommand,
+) -> Result<(), MigrationError> {
+    match command {
+        MigrateCommand::Status => {
+            for status in migrator.status().await? {
+                let applied_at = status.applied_at.map_or_else(
+                    || String::from("pending"),
+                    |applied_at| applied_at.to_string(),
+                );
+                println!(
+                    "{:>5}  {:<32}  {}",
+                    status.version, applied_at, status.description
+                );
+            }
+        }
+        MigrateCommand::Up(target) => {
+            for version in migrator.up(target).await? {
+                println!("applied {version}");
+            }
+        }
+        MigrateCommand::Down(target) => {
+            for version in migrator.down(target).await? {
+                println!("reverted {version}");
+            }
+        }
+    }
+    Ok(())
+}
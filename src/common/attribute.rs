@@ -0,0 +1,403 @@
+use crate::storage::patch::{FieldPatch, FieldUpdate, MapPatch};
+use mongodb::bson::{Bson, DateTime};
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use std::collections::{BTreeMap, BTreeSet};
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+use std::ops::Deref;
+use std::str::FromStr;
+
+/// The maximum length of an attribute key, in characters.
+pub const MAX_ATTRIBUTE_KEY_LEN: usize = 64;
+
+/// The maximum length of a string attribute value, in characters.
+pub const MAX_ATTRIBUTE_STRING_LEN: usize = 1024;
+
+/// The most attributes an entity can have.
+pub const MAX_ATTRIBUTES: usize = 50;
+
+/// The key of a custom attribute, e.g. `colour`. Keys are stored as field names, so a key is
+/// non-empty, at most `MAX_ATTRIBUTE_KEY_LEN` characters long and consists only of ASCII letters,
+/// digits, `-` and `_`.
+#[derive(Clone, Debug, Eq, PartialEq, Ord, PartialOrd, Hash, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct AttributeKey(String);
+
+/// An error indicating an attribute key could not be created from a string.
+#[derive(Debug, Clone)]
+pub struct InvalidAttributeKeyError;
+
+impl FromStr for AttributeKey {
+    type Err = InvalidAttributeKeyError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if is_valid_key(s) {
+            Ok(AttributeKey(s.to_string()))
+        } else {
+            Err(InvalidAttributeKeyError)
+        }
+    }
+}
+
+impl TryFrom<String> for AttributeKey {
+    type Error = InvalidAttributeKeyError;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        if is_valid_key(&value) {
+            Ok(AttributeKey(value))
+        } else {
+            Err(InvalidAttributeKeyError)
+        }
+    }
+}
+
+impl From<AttributeKey> for String {
+    fn from(key: AttributeKey) -> Self {
+        key.0
+    }
+}
+
+impl AsRef<str> for AttributeKey {
+    fn as_ref(&self) -> &str {
+        self.0.as_ref()
+    }
+}
+
+impl Deref for AttributeKey {
+    type Target = str;
+
+    fn deref(&self) -> &Self::Target {
+        self.0.as_str()
+    }
+}
+
+impl Display for AttributeKey {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl Error for InvalidAttributeKeyError {}
+
+impl Display for InvalidAttributeKeyError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "an attribute key must be 1 to {MAX_ATTRIBUTE_KEY_LEN} ASCII letters, digits, hyphens or underscores"
+        )
+    }
+}
+
+fn is_valid_key(s: &str) -> bool {
+    !s.is_empty()
+        && s.len() <= MAX_ATTRIBUTE_KEY_LEN
+        && s.chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+/// The value of a custom attribute. Values are stored as the corresponding BSON type, so they can
+/// be compared with values of the same type when filtering.
+#[derive(Clone, Debug, PartialEq)]
+pub enum AttributeValue {
+    String(String),
+    Number(f64),
+    Bool(bool),
+    Date(DateTime),
+}
+
+impl AttributeValue {
+    /// Checks that this value is within the limits on attribute values: strings can be at most
+    /// `MAX_ATTRIBUTE_STRING_LEN` characters long, and numbers must be finite.
+    fn validate(&self, key: &AttributeKey) -> Result<(), InvalidAttributesError> {
+        match self {
+            Self::String(s) if s.chars().count() > MAX_ATTRIBUTE_STRING_LEN => {
+                Err(InvalidAttributesError::StringTooLong(key.clone()))
+            }
+            Self::Number(n) if !n.is_finite() => {
+                Err(InvalidAttributesError::NonFiniteNumber(key.clone()))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+impl From<&AttributeValue> for Bson {
+    fn from(value: &AttributeValue) -> Self {
+        match value {
+            AttributeValue::String(s) => Bson::String(s.clone()),
+            AttributeValue::Number(n) => Bson::Double(*n),
+            AttributeValue::Bool(b) => Bson::Boolean(*b),
+            AttributeValue::Date(d) => Bson::DateTime(*d),
+        }
+    }
+}
+
+impl TryFrom<Bson> for AttributeValue {
+    type Error = String;
+
+    fn try_from(bson: Bson) -> Result<Self, Self::Error> {
+        match bson {
+            Bson::String(s) => Ok(Self::String(s)),
+            Bson::Double(n) => Ok(Self::Number(n)),
+            Bson::Int32(n) => Ok(Self::Number(n.into())),
+            Bson::Int64(n) => Ok(Self::Number(n as f64)),
+            Bson::Boolean(b) => Ok(Self::Bool(b)),
+            Bson::DateTime(d) => Ok(Self::Date(d)),
+            bson => Err(format!(
+                "an attribute value cannot be of type {:?}",
+                bson.element_type()
+            )),
+        }
+    }
+}
+
+impl Serialize for AttributeValue {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        Bson::from(self).serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for AttributeValue {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Bson::deserialize(deserializer)?
+            .try_into()
+            .map_err(de::Error::custom)
+    }
+}
+
+/// The custom attributes of an entity: at most `MAX_ATTRIBUTES` values, each within the limits
+/// on attribute values. Attributes are deserialized through `Attributes::new`, so that attributes
+/// beyond the limits cannot be read in, e.g. by an import.
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+#[serde(transparent)]
+pub struct Attributes(BTreeMap<AttributeKey, AttributeValue>);
+
+impl Attributes {
+    /// Creates attributes from their values, if they are within the limits on attributes.
+    pub fn new(
+        values: BTreeMap<AttributeKey, AttributeValue>,
+    ) -> Result<Self, InvalidAttributesError> {
+        if values.len() > MAX_ATTRIBUTES {
+            return Err(InvalidAttributesError::TooMany);
+        }
+        for (key, value) in values.iter() {
+            value.validate(key)?;
+        }
+        Ok(Self(values))
+    }
+
+    pub fn get(&self, key: &AttributeKey) -> Option<&AttributeValue> {
+        self.0.get(key)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&AttributeKey, &AttributeValue)> {
+        self.0.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+impl<'de> Deserialize<'de> for Attributes {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Self::new(BTreeMap::deserialize(deserializer)?).map_err(de::Error::custom)
+    }
+}
+
+/// A change to some of the custom attributes of an entity: values to set, and keys to remove.
+///
+/// A patch can set at most `MAX_ATTRIBUTES` values, each within the limits on attribute values;
+/// whether the patched entity has no more than `MAX_ATTRIBUTES` in all depends on the attributes
+/// it already has, which `AttributesPatch::check` checks.
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+#[serde(transparent)]
+pub struct AttributesPatch(MapPatch<AttributeKey, AttributeValue>);
+
+impl AttributesPatch {
+    /// Creates a patch setting and removing attributes, if it is within the limits on attributes
+    /// and does not both set and remove the same attribute.
+    pub fn new(
+        set: BTreeMap<AttributeKey, AttributeValue>,
+        remove: BTreeSet<AttributeKey>,
+    ) -> Result<Self, InvalidAttributesError> {
+        let set = Attributes::new(set)?;
+        if let Some(key) = remove.iter().find(|key| set.get(key).is_some()) {
+            return Err(InvalidAttributesError::SetAndRemoved(key.clone()));
+        }
+        let mut entries = BTreeMap::new();
+        for (key, value) in set.0 {
+            entries.insert(key, FieldPatch::Set(value));
+        }
+        for key in remove {
+            entries.insert(key, FieldPatch::Clear);
+        }
+        Ok(Self(MapPatch(entries)))
+    }
+
+    /// Checks that applying this patch to the provided attributes leaves no more than
+    /// `MAX_ATTRIBUTES`.
+    pub fn check(&self, attributes: &Attributes) -> Result<(), InvalidAttributesError> {
+        let MapPatch(entries) = &self.0;
+        let added = entries
+            .iter()
+            .filter(|(key, patch)| {
+                matches!(patch, FieldPatch::Set(_)) && attributes.get(key).is_none()
+            })
+            .count();
+        let removed = entries
+            .iter()
+            .filter(|(key, patch)| {
+                matches!(patch, FieldPatch::Clear) && attributes.get(key).is_some()
+            })
+            .count();
+        if attributes.len() + added - removed > MAX_ATTRIBUTES {
+            return Err(InvalidAttributesError::TooMany);
+        }
+        Ok(())
+    }
+}
+
+impl FieldUpdate for AttributesPatch {
+    fn is_idempotent(&self) -> bool {
+        self.0.is_idempotent()
+    }
+}
+
+/// An error indicating attributes are not within the limits on attributes.
+#[derive(Debug, Clone)]
+pub enum InvalidAttributesError {
+    TooMany,
+    StringTooLong(AttributeKey),
+    NonFiniteNumber(AttributeKey),
+    SetAndRemoved(AttributeKey),
+}
+
+impl Error for InvalidAttributesError {}
+
+impl Display for InvalidAttributesError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::TooMany => write!(f, "there can be at most {MAX_ATTRIBUTES} attributes"),
+            Self::StringTooLong(key) => write!(
+                f,
+                "the value of attribute {key} is longer than {MAX_ATTRIBUTE_STRING_LEN} characters"
+            ),
+            Self::NonFiniteNumber(key) => {
+                write!(f, "the value of attribute {key} is not a finite number")
+            }
+            Self::SetAndRemoved(key) => {
+                write!(f, "attribute {key} cannot be both set and removed")
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use mongodb::bson::{doc, from_bson, to_bson};
+
+    fn key(s: &str) -> AttributeKey {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn key_with_invalid_characters_cannot_be_constructed() {
+        assert!(AttributeKey::from_str("").is_err());
+        assert!(AttributeKey::from_str("a.b").is_err());
+        assert!(AttributeKey::from_str("$where").is_err());
+        assert!(AttributeKey::from_str(&"a".repeat(MAX_ATTRIBUTE_KEY_LEN + 1)).is_err());
+        assert!(AttributeKey::from_str("shelf_life-days").is_ok());
+    }
+
+    #[test]
+    fn values_are_stored_as_bson_types() {
+        let now = DateTime::now();
+        let mut values = BTreeMap::new();
+        values.insert(key("colour"), AttributeValue::String("red".into()));
+        values.insert(key("weight"), AttributeValue::Number(2.5));
+        values.insert(key("fragile"), AttributeValue::Bool(true));
+        values.insert(key("expires"), AttributeValue::Date(now));
+        let attributes = Attributes::new(values).unwrap();
+
+        let bson = to_bson(&attributes).unwrap();
+        assert_eq!(
+            bson,
+            Bson::Document(doc! {
+                "colour": "red",
+                "expires": now,
+                "fragile": true,
+                "weight": 2.5,
+            })
+        );
+        assert_eq!(from_bson::<Attributes>(bson).unwrap(), attributes);
+    }
+
+    #[test]
+    fn integers_are_read_as_numbers() {
+        let value = from_bson::<AttributeValue>(Bson::Int32(3)).unwrap();
+        assert_eq!(value, AttributeValue::Number(3.0));
+        assert!(from_bson::<AttributeValue>(Bson::Null).is_err());
+    }
+
+    #[test]
+    fn attributes_beyond_limits_are_invalid() {
+        let too_many = (0..=MAX_ATTRIBUTES)
+            .map(|i| (key(&format!("a{i}")), AttributeValue::Bool(true)))
+            .collect();
+        assert!(Attributes::new(too_many).is_err());
+
+        let mut too_long = BTreeMap::new();
+        too_long.insert(
+            key("notes"),
+            AttributeValue::String("a".repeat(MAX_ATTRIBUTE_STRING_LEN + 1)),
+        );
+        assert!(Attributes::new(too_long).is_err());
+
+        let mut not_finite = BTreeMap::new();
+        not_finite.insert(key("weight"), AttributeValue::Number(f64::NAN));
+        assert!(Attributes::new(not_finite).is_err());
+    }
+
+    #[test]
+    fn attributes_beyond_limits_cannot_be_deserialized() {
+        let too_many = (0..=MAX_ATTRIBUTES)
+            .map(|i| (format!("a{i}"), Bson::Boolean(true)))
+            .collect::<mongodb::bson::Document>();
+        assert!(from_bson::<Attributes>(Bson::Document(too_many)).is_err());
+    }
+
+    #[test]
+    fn patch_cannot_leave_too_many_attributes() {
+        let existing = (1..MAX_ATTRIBUTES)
+            .map(|i| (key(&format!("a{i}")), AttributeValue::Bool(true)))
+            .collect();
+        let existing = Attributes::new(existing).unwrap();
+
+        let mut set = BTreeMap::new();
+        set.insert(key("a1"), AttributeValue::Bool(false));
+        set.insert(key("b1"), AttributeValue::Bool(true));
+        let patch = AttributesPatch::new(set.clone(), BTreeSet::new()).unwrap();
+        assert!(patch.check(&existing).is_ok());
+
+        set.insert(key("b2"), AttributeValue::Bool(true));
+        let patch = AttributesPatch::new(set.clone(), BTreeSet::new()).unwrap();
+        assert!(patch.check(&existing).is_err());
+
+        let patch = AttributesPatch::new(set, BTreeSet::from([key("a2")])).unwrap();
+        assert!(patch.check(&existing).is_ok());
+    }
+
+    #[test]
+    fn patch_cannot_set_and_remove_the_same_attribute() {
+        let mut set = BTreeMap::new();
+        set.insert(key("colour"), AttributeValue::String("red".into()));
+        let remove = BTreeSet::from([key("colour")]);
+        assert!(AttributesPatch::new(set, remove).is_err());
+    }
+}
@@ -0,0 +1,56 @@
+use std::time::{Duration, Instant};
+
+/// The moment by which an operation, such as the handling of a request, must be complete.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub struct Deadline(Instant);
+
+impl Deadline {
+    /// Creates a deadline the provided duration from now.
+    pub fn after(timeout: Duration) -> Self {
+        Self(Instant::now() + timeout)
+    }
+
+    /// Returns the time left before this deadline, or `None` if it has passed.
+    pub fn remaining(&self) -> Option<Duration> {
+        self.0
+            .checked_duration_since(Instant::now())
+            .filter(|remaining| !remaining.is_zero())
+    }
+
+    /// Returns `true` if this deadline has passed.
+    pub fn is_expired(&self) -> bool {
+        self.remaining().is_none()
+    }
+
+    /// Returns whichever of this and the provided deadline comes first.
+    pub fn min(self, other: Deadline) -> Self {
+        Ord::min(self, other)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn future_deadline_has_time_remaining() {
+        let deadline = Deadline::after(Duration::from_secs(60));
+        assert!(!deadline.is_expired());
+        assert!(deadline.remaining().unwrap() <= Duration::from_secs(60));
+    }
+
+    #[test]
+    fn past_deadline_is_expired() {
+        let deadline = Deadline::after(Duration::ZERO);
+        assert!(deadline.is_expired());
+        assert_eq!(deadline.remaining(), None);
+    }
+
+    #[test]
+    fn earliest_deadline_is_chosen() {
+        let early = Deadline::after(Duration::from_secs(1));
+        let late = Deadline::after(Duration::from_secs(60));
+        assert_eq!(early.min(late), early);
+        assert_eq!(late.min(early), early);
+    }
+}
@@ -1,7 +1,32 @@
-use crate::common::id::Id;
+use crate::common::id::{Id, Key};
+use std::error::Error;
+use std::fmt::{Display, Formatter};
 
 /// An `Entity` is a thing that can be uniquely identified.
-pub trait Entity {
+pub trait Entity: Sized {
+    /// The type of key the entity is identified by.
+    type Key: Key;
+
     /// Returns the ID of the entity.
-    fn id(&self) -> &Id;
+    fn id(&self) -> &Id<Self>;
+}
+
+/// An error indicating a change would leave an entity breaking one of its invariants, e.g. by
+/// giving it too many attributes.
+#[derive(Debug, Clone)]
+pub struct InvalidEntityError(String);
+
+impl InvalidEntityError {
+    /// Creates an error describing the invariant that would be broken.
+    pub fn new(reason: impl Display) -> Self {
+        Self(reason.to_string())
+    }
+}
+
+impl Error for InvalidEntityError {}
+
+impl Display for InvalidEntityError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
 }
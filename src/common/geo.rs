@@ -0,0 +1,181 @@
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+
+const POINT: &str = "Point";
+const POLYGON: &str = "Polygon";
+
+/// A point on the earth, stored as a GeoJSON point so that it can be indexed and queried
+/// spatially.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(try_from = "GeoJson<[f64; 2]>", into = "GeoJson<[f64; 2]>")]
+pub struct GeoPoint {
+    longitude: f64,
+    latitude: f64,
+}
+
+/// An error indicating a point or polygon could not be created from coordinates.
+#[derive(Debug, Clone)]
+pub enum InvalidGeoError {
+    Latitude,
+    Longitude,
+    TooFewPoints,
+    NotGeoJson,
+}
+
+impl GeoPoint {
+    /// Creates a point, if its latitude is between -90 and 90 degrees and its longitude between
+    /// -180 and 180 degrees.
+    pub fn new(latitude: f64, longitude: f64) -> Result<Self, InvalidGeoError> {
+        if !(-90.0..=90.0).contains(&latitude) {
+            return Err(InvalidGeoError::Latitude);
+        }
+        if !(-180.0..=180.0).contains(&longitude) {
+            return Err(InvalidGeoError::Longitude);
+        }
+        Ok(Self {
+            longitude,
+            latitude,
+        })
+    }
+
+    pub fn latitude(&self) -> f64 {
+        self.latitude
+    }
+
+    pub fn longitude(&self) -> f64 {
+        self.longitude
+    }
+}
+
+/// An area of the earth bounded by a ring of at least three points, stored as a GeoJSON polygon.
+/// The ring is closed when stored, so its last point need not repeat its first.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(into = "GeoJson<[Vec<[f64; 2]>; 1]>")]
+pub struct GeoPolygon {
+    ring: Vec<GeoPoint>,
+}
+
+impl GeoPolygon {
+    pub fn new(mut ring: Vec<GeoPoint>) -> Result<Self, InvalidGeoError> {
+        if ring.len() > 1 && ring.first() == ring.last() {
+            ring.pop();
+        }
+        if ring.len() < 3 {
+            return Err(InvalidGeoError::TooFewPoints);
+        }
+        Ok(Self { ring })
+    }
+
+    pub fn ring(&self) -> &[GeoPoint] {
+        &self.ring
+    }
+}
+
+/// A GeoJSON geometry, with the coordinates of its type.
+#[derive(Serialize, Deserialize)]
+struct GeoJson<C> {
+    #[serde(rename = "type")]
+    kind: String,
+    coordinates: C,
+}
+
+impl TryFrom<GeoJson<[f64; 2]>> for GeoPoint {
+    type Error = InvalidGeoError;
+
+    fn try_from(geo_json: GeoJson<[f64; 2]>) -> Result<Self, Self::Error> {
+        match geo_json.kind.as_str() {
+            POINT => {
+                let [longitude, latitude] = geo_json.coordinates;
+                GeoPoint::new(latitude, longitude)
+            }
+            _ => Err(InvalidGeoError::NotGeoJson),
+        }
+    }
+}
+
+impl From<GeoPoint> for GeoJson<[f64; 2]> {
+    fn from(point: GeoPoint) -> Self {
+        GeoJson {
+            kind: POINT.to_string(),
+            coordinates: coordinates(&point),
+        }
+    }
+}
+
+impl From<GeoPolygon> for GeoJson<[Vec<[f64; 2]>; 1]> {
+    fn from(polygon: GeoPolygon) -> Self {
+        let mut ring: Vec<_> = polygon.ring.iter().map(coordinates).collect();
+        ring.push(coordinates(&polygon.ring[0]));
+        GeoJson {
+            kind: POLYGON.to_string(),
+            coordinates: [ring],
+        }
+    }
+}
+
+fn coordinates(point: &GeoPoint) -> [f64; 2] {
+    [point.longitude, point.latitude]
+}
+
+impl Error for InvalidGeoError {}
+
+impl Display for InvalidGeoError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Latitude => write!(f, "a latitude must be between -90 and 90 degrees"),
+            Self::Longitude => write!(f, "a longitude must be between -180 and 180 degrees"),
+            Self::TooFewPoints => write!(f, "a polygon must have at least 3 points"),
+            Self::NotGeoJson => write!(f, "a point must be a GeoJSON point"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use mongodb::bson::{doc, from_bson, to_bson, Bson};
+
+    #[test]
+    fn point_is_stored_as_geo_json() {
+        let point = GeoPoint::new(51.5, -0.12).unwrap();
+        let bson = to_bson(&point).unwrap();
+        assert_eq!(
+            bson,
+            Bson::Document(doc! { "type": "Point", "coordinates": [-0.12, 51.5] })
+        );
+        assert_eq!(from_bson::<GeoPoint>(bson).unwrap(), point);
+    }
+
+    #[test]
+    fn point_out_of_range_is_invalid() {
+        assert!(GeoPoint::new(90.5, 0.0).is_err());
+        assert!(GeoPoint::new(0.0, -180.5).is_err());
+        assert!(GeoPoint::new(f64::NAN, 0.0).is_err());
+        let bson = Bson::Document(doc! { "type": "Point", "coordinates": [0.0, 91.0] });
+        assert!(from_bson::<GeoPoint>(bson).is_err());
+    }
+
+    #[test]
+    fn polygon_ring_is_closed() {
+        let points = vec![
+            GeoPoint::new(0.0, 0.0).unwrap(),
+            GeoPoint::new(0.0, 1.0).unwrap(),
+            GeoPoint::new(1.0, 1.0).unwrap(),
+        ];
+        let polygon = GeoPolygon::new(points).unwrap();
+        assert_eq!(
+            to_bson(&polygon).unwrap(),
+            Bson::Document(doc! {
+                "type": "Polygon",
+                "coordinates": [[[0.0, 0.0], [1.0, 0.0], [1.0, 1.0], [0.0, 0.0]]],
+            })
+        );
+    }
+
+    #[test]
+    fn polygon_needs_three_points() {
+        let point = GeoPoint::new(0.0, 0.0).unwrap();
+        assert!(GeoPolygon::new(vec![point, GeoPoint::new(1.0, 1.0).unwrap(), point]).is_err());
+    }
+}
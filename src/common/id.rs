@@ -1,39 +1,184 @@
-use std::{fmt::Display, str::FromStr};
-
-use mongodb::bson::{
-    oid::{self, ObjectId},
-    Bson,
+use std::{
+    error::Error,
+    fmt::{Debug, Display, Formatter},
+    hash::{Hash, Hasher},
+    marker::PhantomData,
+    str::FromStr,
 };
-use serde::{Deserialize, Serialize};
 
-/// A unique identifier for identifying an entity.
-#[derive(Clone, Debug, Eq, PartialEq, Hash, Serialize, Deserialize)]
-#[serde(transparent)]
-pub struct Id(ObjectId);
+use mongodb::bson::{oid::ObjectId, Bson, Uuid};
+use serde::{de::DeserializeOwned, Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::common::entity::Entity;
+
+/// A type of value that can uniquely identify an entity, such as an `ObjectId`, a UUID, a string
+/// or an integer.
+pub trait Key:
+    Clone + Debug + Display + Eq + Hash + Serialize + DeserializeOwned + Send + Sync + 'static
+{
+    /// Parses a key from its string representation, as produced by `Display`.
+    fn parse(s: &str) -> Option<Self>;
+
+    /// Converts a BSON value to a key, if it is a value of this type.
+    fn from_bson(bson: &Bson) -> Option<Self>;
+
+    /// Converts this key to a BSON value.
+    fn to_bson(&self) -> Bson;
+}
+
+impl Key for ObjectId {
+    fn parse(s: &str) -> Option<Self> {
+        ObjectId::parse_str(s).ok()
+    }
+
+    fn from_bson(bson: &Bson) -> Option<Self> {
+        bson.as_object_id()
+    }
+
+    fn to_bson(&self) -> Bson {
+        Bson::ObjectId(*self)
+    }
+}
+
+impl Key for Uuid {
+    fn parse(s: &str) -> Option<Self> {
+        Uuid::parse_str(s).ok()
+    }
+
+    fn from_bson(bson: &Bson) -> Option<Self> {
+        match bson {
+            Bson::Binary(binary) => binary.to_uuid().ok(),
+            _ => None,
+        }
+    }
+
+    fn to_bson(&self) -> Bson {
+        Bson::from(*self)
+    }
+}
+
+impl Key for String {
+    fn parse(s: &str) -> Option<Self> {
+        Some(s.to_string())
+    }
+
+    fn from_bson(bson: &Bson) -> Option<Self> {
+        bson.as_str().map(str::to_string)
+    }
+
+    fn to_bson(&self) -> Bson {
+        Bson::String(self.clone())
+    }
+}
+
+impl Key for i64 {
+    fn parse(s: &str) -> Option<Self> {
+        s.parse().ok()
+    }
+
+    fn from_bson(bson: &Bson) -> Option<Self> {
+        match bson {
+            Bson::Int32(i) => Some(*i as i64),
+            Bson::Int64(i) => Some(*i),
+            _ => None,
+        }
+    }
+
+    fn to_bson(&self) -> Bson {
+        Bson::Int64(*self)
+    }
+}
+
+/// A unique identifier for identifying an entity of type `T`, wrapping a key of the entity's key
+/// type. IDs of different entity types cannot be mixed up, even when their keys are of the same
+/// type.
+pub struct Id<T: Entity>(T::Key, PhantomData<fn() -> T>);
+
+/// An error indicating an ID could not be parsed from a string.
+#[derive(Debug, Clone)]
+pub struct InvalidIdError;
+
+impl<T: Entity> Id<T> {
+    pub fn new(key: T::Key) -> Self {
+        Id(key, PhantomData)
+    }
+
+    pub fn key(&self) -> &T::Key {
+        &self.0
+    }
+}
+
+// implemented by hand, since deriving would require `T` itself to implement these traits
+
+impl<T: Entity> Clone for Id<T> {
+    fn clone(&self) -> Self {
+        Id::new(self.0.clone())
+    }
+}
 
-impl Display for Id {
-    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+impl<T: Entity> Debug for Id<T> {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        f.debug_tuple("Id").field(&self.0).finish()
+    }
+}
+
+impl<T: Entity> PartialEq for Id<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.0 == other.0
+    }
+}
+
+impl<T: Entity> Eq for Id<T> {}
+
+impl<T: Entity> Hash for Id<T> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.0.hash(state)
+    }
+}
+
+impl<T: Entity> Serialize for Id<T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.0.serialize(serializer)
+    }
+}
+
+impl<'de, T: Entity> Deserialize<'de> for Id<T> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        T::Key::deserialize(deserializer).map(Id::new)
+    }
+}
+
+impl<T: Entity> Display for Id<T> {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
         write!(f, "{}", self.0)
     }
 }
 
-impl From<ObjectId> for Id {
+impl<T: Entity<Key = ObjectId>> From<ObjectId> for Id<T> {
     fn from(oid: ObjectId) -> Self {
-        Id(oid)
+        Id::new(oid)
     }
 }
 
-impl From<Id> for Bson {
-    fn from(id: Id) -> Self {
-        Bson::ObjectId(id.0)
+impl<T: Entity> From<Id<T>> for Bson {
+    fn from(id: Id<T>) -> Self {
+        id.0.to_bson()
     }
 }
 
-impl FromStr for Id {
-    type Err = oid::Error;
+impl<T: Entity> FromStr for Id<T> {
+    type Err = InvalidIdError;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        Ok(ObjectId::parse_str(s)?.into())
+        T::Key::parse(s).map(Id::new).ok_or(InvalidIdError)
+    }
+}
+
+impl Error for InvalidIdError {}
+
+impl Display for InvalidIdError {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        write!(f, "an ID must be a valid key")
     }
 }
 
@@ -41,22 +186,91 @@ impl FromStr for Id {
 mod test {
     use super::*;
 
+    struct Thing {
+        id: Id<Thing>,
+    }
+
+    impl Entity for Thing {
+        type Key = ObjectId;
+
+        fn id(&self) -> &Id<Thing> {
+            &self.id
+        }
+    }
+
+    struct UuidThing {
+        id: Id<UuidThing>,
+    }
+
+    impl Entity for UuidThing {
+        type Key = Uuid;
+
+        fn id(&self) -> &Id<UuidThing> {
+            &self.id
+        }
+    }
+
+    struct NumberedThing {
+        id: Id<NumberedThing>,
+    }
+
+    impl Entity for NumberedThing {
+        type Key = i64;
+
+        fn id(&self) -> &Id<NumberedThing> {
+            &self.id
+        }
+    }
+
     #[test]
     fn new_ids_are_unique() {
-        let id1: Id = ObjectId::new().into();
-        let id2: Id = ObjectId::new().into();
+        let id1: Id<Thing> = ObjectId::new().into();
+        let id2: Id<Thing> = ObjectId::new().into();
         assert_ne!(id1, id2);
     }
 
     #[test]
     fn id_is_equal_after_serializing_and_deserializing() {
-        let orig_id: Id = ObjectId::new().into();
+        let orig_id: Id<Thing> = ObjectId::new().into();
         let ser_id = serde_json::to_string(&orig_id).unwrap();
-        let deser_id_result = serde_json::from_str::<Id>(ser_id.as_str());
+        let deser_id_result = serde_json::from_str::<Id<Thing>>(ser_id.as_str());
 
         assert!(deser_id_result.is_ok());
         let deser_id = deser_id_result.unwrap();
 
         assert_eq!(orig_id, deser_id);
     }
+
+    #[test]
+    fn id_serializes_as_its_key() {
+        let oid = ObjectId::new();
+        let id: Id<Thing> = oid.into();
+        assert_eq!(
+            serde_json::to_value(&id).unwrap(),
+            serde_json::to_value(oid).unwrap()
+        );
+        assert_eq!(Bson::from(id), Bson::ObjectId(oid));
+    }
+
+    #[test]
+    fn keys_round_trip_through_bson() {
+        let uuid = Uuid::new();
+        assert_eq!(Uuid::from_bson(&uuid.to_bson()), Some(uuid));
+        assert_eq!(i64::from_bson(&Bson::Int32(7)), Some(7));
+        assert_eq!(
+            String::from_bson(&String::from("sku-1").to_bson()),
+            Some(String::from("sku-1"))
+        );
+        assert_eq!(ObjectId::from_bson(&Bson::Int64(7)), None);
+    }
+
+    #[test]
+    fn ids_of_any_key_type_can_be_parsed() {
+        let uuid = Uuid::new();
+        let uuid_id = uuid.to_string().parse::<Id<UuidThing>>().unwrap();
+        assert_eq!(uuid_id, Id::new(uuid));
+        assert_eq!("42".parse::<Id<NumberedThing>>().unwrap(), Id::new(42));
+        assert!("forty-two".parse::<Id<NumberedThing>>().is_err());
+        assert!("42".parse::<Id<Thing>>().is_err());
+    }
 }
@@ -0,0 +1,88 @@
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+
+/// The maximum length of an idempotency key.
+pub const MAX_IDEMPOTENCY_KEY_LEN: usize = 255;
+
+/// A key chosen by a client to identify a mutation, so that retrying the mutation with the same
+/// key replays its original result instead of making the change again.
+///
+/// Idempotency keys are non-empty, at most `MAX_IDEMPOTENCY_KEY_LEN` characters long and consist
+/// only of visible ASCII characters, e.g. a UUID.
+#[derive(Clone, Debug, Eq, PartialEq, Hash, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct IdempotencyKey(String);
+
+/// An error indicating an idempotency key could not be created from a string.
+#[derive(Debug, Clone)]
+pub struct InvalidIdempotencyKeyError;
+
+impl FromStr for IdempotencyKey {
+    type Err = InvalidIdempotencyKeyError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if is_valid_idempotency_key(s) {
+            Ok(IdempotencyKey(s.to_string()))
+        } else {
+            Err(InvalidIdempotencyKeyError)
+        }
+    }
+}
+
+impl AsRef<str> for IdempotencyKey {
+    fn as_ref(&self) -> &str {
+        self.0.as_ref()
+    }
+}
+
+impl Display for IdempotencyKey {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl Error for InvalidIdempotencyKeyError {}
+
+impl Display for InvalidIdempotencyKeyError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "an idempotency key must be 1 to {MAX_IDEMPOTENCY_KEY_LEN} visible ASCII characters"
+        )
+    }
+}
+
+fn is_valid_idempotency_key(s: &str) -> bool {
+    !s.is_empty() && s.len() <= MAX_IDEMPOTENCY_KEY_LEN && s.chars().all(|c| c.is_ascii_graphic())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn uuid_is_a_valid_idempotency_key() {
+        let key_result = IdempotencyKey::from_str("0b6e2f62-7f4c-4b8a-9d43-2f1e7a6c5d10");
+        assert!(key_result.is_ok());
+    }
+
+    #[test]
+    fn empty_idempotency_key_cannot_be_constructed() {
+        assert!(IdempotencyKey::from_str("").is_err());
+    }
+
+    #[test]
+    fn idempotency_key_with_invisible_characters_cannot_be_constructed() {
+        assert!(IdempotencyKey::from_str("retry 1").is_err());
+        assert!(IdempotencyKey::from_str("retry\n1").is_err());
+        assert!(IdempotencyKey::from_str("rétry").is_err());
+    }
+
+    #[test]
+    fn overlong_idempotency_key_cannot_be_constructed() {
+        let s = "a".repeat(MAX_IDEMPOTENCY_KEY_LEN + 1);
+        assert!(IdempotencyKey::from_str(&s).is_err());
+    }
+}
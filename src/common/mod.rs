@@ -1,3 +1,9 @@
+pub mod attribute;
+pub mod deadline;
 pub mod entity;
+pub mod geo;
 pub mod id;
+pub mod idempotency;
 pub mod name;
+pub mod tag;
+pub mod tenant;
@@ -0,0 +1,122 @@
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+use std::ops::Deref;
+use std::str::FromStr;
+
+/// The maximum length of a tag, in characters.
+pub const MAX_TAG_LEN: usize = 50;
+
+/// A label attached to an entity, e.g. `outdoor` or `fragile`.
+///
+/// Tags are normalized when they are created: surrounding whitespace is trimmed and letters are
+/// made lowercase, so that `Outdoor ` and `outdoor` are the same tag. A normalized tag is non-empty,
+/// at most `MAX_TAG_LEN` characters long and consists only of letters, digits, `-` and `_`.
+#[derive(Clone, Debug, Eq, PartialEq, Ord, PartialOrd, Hash, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Tag(String);
+
+/// An error indicating a tag could not be created from a string.
+#[derive(Debug, Clone)]
+pub struct InvalidTagError;
+
+impl FromStr for Tag {
+    type Err = InvalidTagError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let tag = s.trim().to_lowercase();
+        if is_valid_tag(&tag) {
+            Ok(Tag(tag))
+        } else {
+            Err(InvalidTagError)
+        }
+    }
+}
+
+impl TryFrom<String> for Tag {
+    type Error = InvalidTagError;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl From<Tag> for String {
+    fn from(tag: Tag) -> Self {
+        tag.0
+    }
+}
+
+impl AsRef<str> for Tag {
+    fn as_ref(&self) -> &str {
+        self.0.as_ref()
+    }
+}
+
+impl Deref for Tag {
+    type Target = str;
+
+    fn deref(&self) -> &Self::Target {
+        self.0.as_str()
+    }
+}
+
+impl Display for Tag {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl Error for InvalidTagError {}
+
+impl Display for InvalidTagError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "a tag must be 1 to {MAX_TAG_LEN} letters, digits, hyphens or underscores"
+        )
+    }
+}
+
+fn is_valid_tag(s: &str) -> bool {
+    !s.is_empty()
+        && s.chars().count() <= MAX_TAG_LEN
+        && s.chars()
+            .all(|c| c.is_alphanumeric() || c == '-' || c == '_')
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn tag_is_normalized() {
+        let tag = Tag::from_str("  Outdoor-Gear ").unwrap();
+        assert_eq!(tag.as_ref(), "outdoor-gear");
+    }
+
+    #[test]
+    fn empty_tag_cannot_be_constructed() {
+        assert!(Tag::from_str("").is_err());
+        assert!(Tag::from_str("   ").is_err());
+    }
+
+    #[test]
+    fn tag_with_invalid_characters_cannot_be_constructed() {
+        assert!(Tag::from_str("outdoor gear").is_err());
+        assert!(Tag::from_str("outdoor/gear").is_err());
+    }
+
+    #[test]
+    fn overlong_tag_cannot_be_constructed() {
+        let s = "a".repeat(MAX_TAG_LEN + 1);
+        assert!(Tag::from_str(&s).is_err());
+    }
+
+    #[test]
+    fn unnormalized_tag_is_normalized_when_deserialized() {
+        let tag = serde_json::from_str::<Tag>(r#""Fragile""#).unwrap();
+        assert_eq!(tag, Tag::from_str("fragile").unwrap());
+        assert!(serde_json::from_str::<Tag>(r#""""#).is_err());
+    }
+}
@@ -0,0 +1,110 @@
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+
+/// The maximum length of a tenant ID; this keeps tenant-scoped database names well within the
+/// limits imposed by mongo.
+pub const MAX_TENANT_ID_LEN: usize = 32;
+
+/// Identifies a tenant, i.e. a customer whose data is kept isolated from all other customers.
+///
+/// Tenant IDs are non-empty, at most `MAX_TENANT_ID_LEN` characters long and consist only of
+/// lowercase ASCII letters, digits, `-` and `_`, so they can safely be used to derive storage
+/// names; mongo does not allow database names that differ only by case, so neither do tenant
+/// IDs.
+#[derive(Clone, Debug, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub struct TenantId(String);
+
+/// An error indicating a tenant ID could not be created from a string.
+#[derive(Debug, Clone)]
+pub struct InvalidTenantIdError;
+
+impl FromStr for TenantId {
+    type Err = InvalidTenantIdError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if is_valid_tenant_id(s) {
+            Ok(TenantId(s.to_string()))
+        } else {
+            Err(InvalidTenantIdError)
+        }
+    }
+}
+
+impl TryFrom<String> for TenantId {
+    type Error = InvalidTenantIdError;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        if is_valid_tenant_id(&value) {
+            Ok(TenantId(value))
+        } else {
+            Err(InvalidTenantIdError)
+        }
+    }
+}
+
+impl AsRef<str> for TenantId {
+    fn as_ref(&self) -> &str {
+        self.0.as_ref()
+    }
+}
+
+impl Display for TenantId {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl Error for InvalidTenantIdError {}
+
+impl Display for InvalidTenantIdError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "a tenant ID must be 1 to {MAX_TENANT_ID_LEN} characters of lowercase letters, digits, '-' or '_'"
+        )
+    }
+}
+
+fn is_valid_tenant_id(s: &str) -> bool {
+    !s.is_empty()
+        && s.len() <= MAX_TENANT_ID_LEN
+        && s.chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-' || c == '_')
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn valid_tenant_id_can_be_constructed() {
+        let tenant_id_result = TenantId::from_str("acme-corp_1");
+        assert!(tenant_id_result.is_ok());
+    }
+
+    #[test]
+    fn empty_tenant_id_cannot_be_constructed() {
+        let tenant_id_result = TenantId::from_str("");
+        assert!(tenant_id_result.is_err());
+    }
+
+    #[test]
+    fn tenant_id_with_invalid_characters_cannot_be_constructed() {
+        assert!(TenantId::from_str("acme.corp").is_err());
+        assert!(TenantId::from_str("acme/corp").is_err());
+        assert!(TenantId::from_str("acme corp").is_err());
+    }
+
+    #[test]
+    fn tenant_id_with_uppercase_letters_cannot_be_constructed() {
+        assert!(TenantId::from_str("Acme").is_err());
+        assert!(TenantId::try_from(String::from("ACME")).is_err());
+    }
+
+    #[test]
+    fn overlong_tenant_id_cannot_be_constructed() {
+        let s = "a".repeat(MAX_TENANT_ID_LEN + 1);
+        assert!(TenantId::try_from(s).is_err());
+    }
+}
@@ -2,24 +2,177 @@ pub mod models;
 
 pub use context::*;
 
-use self::models::items::{Item, ItemFilter, ItemPatch, ItemSpec};
+use self::models::{
+    items::{Item, ItemFilter, ItemPatch, ItemSpec},
+    owners::{Owner, OwnerFilter, OwnerPatch, OwnerSpec},
+    stock::{
+        StockChange, StockLevel, StockLevelFilter, StockLevelPatch, StockLevelSpec, StockMovement,
+        StockMovementFilter, StockMovementSpec,
+    },
+};
 use crate::{
-    common::id::Id,
-    storage::repo::{Patch, Repo},
+    common::{
+        entity::{Entity, InvalidEntityError},
+        id::Id,
+        idempotency::IdempotencyKey,
+        name::Name,
+        tag::Tag,
+    },
+    storage::{
+        filter::RangeFilter,
+        idempotency::IdempotencyStore,
+        mongo_repo::MongoReposable,
+        patch::Increment,
+        repo::{Facet, GeoQuery, Near, Patch, Repo, Reposable, ReturnDocument},
+        retrying_repo::{retry, RetryableError},
+    },
 };
 use async_trait::async_trait;
+use futures::Future;
+use mongodb::bson::DateTime;
+use serde::{de::DeserializeOwned, Serialize};
 use std::error::Error;
+use tracing::instrument;
+
+// the names under which the responses to idempotent mutations are recorded
+const CREATE_ITEM_OPERATION: &str = "create_item";
+const UPDATE_ITEM_OPERATION: &str = "update_item";
+const DELETE_ITEM_OPERATION: &str = "delete_item";
+const CREATE_OWNER_OPERATION: &str = "create_owner";
+const UPDATE_OWNER_OPERATION: &str = "update_owner";
+const DELETE_OWNER_OPERATION: &str = "delete_owner";
+const ADJUST_STOCK_OPERATION: &str = "adjust_stock";
+const TRANSFER_STOCK_OPERATION: &str = "transfer_stock";
+
+/// An entity the domain can store, i.e. one that any `DomainContext` can provide a repository of.
+pub trait DomainEntity:
+    MongoReposable<
+        Spec: Serialize + Send + Sync,
+        Patch: Serialize + Send + Sync,
+        Filter: Serialize + Send + Sync,
+    > + Clone
+    + Serialize
+    + DeserializeOwned
+    + Send
+    + Sync
+    + Unpin
+    + 'static
+{
+}
+
+impl<T> DomainEntity for T
+where
+    T: MongoReposable + Clone + Serialize + DeserializeOwned + Send + Sync + Unpin + 'static,
+    T::Spec: Serialize + Send + Sync,
+    T::Patch: Serialize + Send + Sync,
+    T::Filter: Serialize + Send + Sync,
+{
+}
 
 #[async_trait]
 pub trait Domain {
     type DomainError: Error;
 
-    async fn item(&self, id: &Id) -> Result<Option<Item>, Self::DomainError>;
+    async fn item(&self, id: &Id<Item>) -> Result<Option<Item>, Self::DomainError>;
+    async fn items(&self, ids: &[Id<Item>]) -> Result<Vec<Item>, Self::DomainError>;
     async fn all_items(&self) -> Result<Vec<Item>, Self::DomainError>;
     async fn find_items(&self, filter: &ItemFilter) -> Result<Vec<Item>, Self::DomainError>;
-    async fn create_item(&self, spec: &ItemSpec) -> Result<Item, Self::DomainError>;
-    async fn update_item(&self, patch: &ItemPatch) -> Result<Option<Item>, Self::DomainError>;
-    async fn delete_item(&self, id: &Id) -> Result<bool, Self::DomainError>;
+    async fn find_items_page(
+        &self,
+        filter: &ItemFilter,
+        offset: usize,
+        limit: usize,
+    ) -> Result<Vec<Item>, Self::DomainError>;
+    /// Returns every tag on the items matching the filter, with the number of those items tagged
+    /// with it, from the most to the least used.
+    async fn item_tags(&self, filter: &ItemFilter) -> Result<Vec<Facet<Tag>>, Self::DomainError>;
+    /// Returns at most `limit` located items matching the filter within an area around a point,
+    /// from the nearest to the furthest.
+    async fn items_near(
+        &self,
+        query: &GeoQuery,
+        filter: &ItemFilter,
+        limit: usize,
+    ) -> Result<Vec<Near<Item>>, Self::DomainError>;
+
+    /// Mutations made with an idempotency key are made at most once; a mutation retried with the
+    /// same key returns the original result.
+    async fn create_item(
+        &self,
+        spec: &ItemSpec,
+        idempotency_key: Option<&IdempotencyKey>,
+    ) -> Result<Item, Self::DomainError>;
+    async fn update_item(
+        &self,
+        patch: &ItemPatch,
+        idempotency_key: Option<&IdempotencyKey>,
+    ) -> Result<Option<Item>, Self::DomainError>;
+    async fn delete_item(
+        &self,
+        id: &Id<Item>,
+        idempotency_key: Option<&IdempotencyKey>,
+    ) -> Result<Option<Item>, Self::DomainError>;
+
+    async fn owner(&self, id: &Id<Owner>) -> Result<Option<Owner>, Self::DomainError>;
+    async fn owners(&self, ids: &[Id<Owner>]) -> Result<Vec<Owner>, Self::DomainError>;
+    async fn find_owners(&self, filter: &OwnerFilter) -> Result<Vec<Owner>, Self::DomainError>;
+    async fn find_owners_page(
+        &self,
+        filter: &OwnerFilter,
+        offset: usize,
+        limit: usize,
+    ) -> Result<Vec<Owner>, Self::DomainError>;
+    async fn create_owner(
+        &self,
+        spec: &OwnerSpec,
+        idempotency_key: Option<&IdempotencyKey>,
+    ) -> Result<Owner, Self::DomainError>;
+    async fn update_owner(
+        &self,
+        patch: &OwnerPatch,
+        idempotency_key: Option<&IdempotencyKey>,
+    ) -> Result<Option<Owner>, Self::DomainError>;
+
+    /// Deleting an owner leaves its items in place, still referring to it.
+    async fn delete_owner(
+        &self,
+        id: &Id<Owner>,
+        idempotency_key: Option<&IdempotencyKey>,
+    ) -> Result<Option<Owner>, Self::DomainError>;
+
+    async fn stock_levels(
+        &self,
+        filter: &StockLevelFilter,
+    ) -> Result<Vec<StockLevel>, Self::DomainError>;
+    async fn find_stock_movements_page(
+        &self,
+        filter: &StockMovementFilter,
+        offset: usize,
+        limit: usize,
+    ) -> Result<Vec<StockMovement>, Self::DomainError>;
+
+    /// Adds stock of an item at a location, or takes it if `delta` is negative, and records the
+    /// movement in the ledger; stock can only be taken if there is enough of it, and `delta`
+    /// cannot be 0.
+    async fn adjust_stock(
+        &self,
+        item_id: &Id<Item>,
+        location: &Name,
+        delta: i64,
+        idempotency_key: Option<&IdempotencyKey>,
+    ) -> Result<StockChange, Self::DomainError>;
+
+    /// Moves a positive quantity of an item from one location to a different one, and records the
+    /// movement in the ledger; either both locations' stock changes or, if there is not enough
+    /// stock to take, neither does.
+    async fn transfer_stock(
+        &self,
+        item_id: &Id<Item>,
+        from: &Name,
+        to: &Name,
+        quantity: i64,
+        idempotency_key: Option<&IdempotencyKey>,
+    ) -> Result<StockChange, Self::DomainError>;
 }
 
 #[derive(Clone)]
@@ -37,137 +190,805 @@ impl<C: DomainContext> DomainImpl<C> {
 impl<C: DomainContext> Domain for DomainImpl<C>
 where
     C: Send + Sync,
-    C::ItemsRepo: Sync,
-    <C::ItemsRepo as Repo<Item>>::RepoError: Send,
 {
     // FIXME: this is a hack...what should the error type be?
-    type DomainError = <C::ItemsRepo as Repo<Item>>::RepoError;
+    type DomainError = C::RepoError;
+
+    #[instrument(name = "Domain::item", skip_all, fields(%id))]
+    async fn item(&self, id: &Id<Item>) -> Result<Option<Item>, Self::DomainError> {
+        self.ctx.repo::<Item>().retrieve(id).await
+    }
 
-    async fn item(&self, id: &Id) -> Result<Option<Item>, Self::DomainError> {
-        self.ctx.items_repo().retrieve(id).await
+    #[instrument(name = "Domain::items", skip_all, fields(count = ids.len()))]
+    async fn items(&self, ids: &[Id<Item>]) -> Result<Vec<Item>, Self::DomainError> {
+        self.ctx.repo::<Item>().retrieve_many(ids).await
     }
 
+    #[instrument(name = "Domain::all_items", skip(self))]
     async fn all_items(&self) -> Result<Vec<Item>, Self::DomainError> {
-        self.ctx.items_repo().retrieve_all().await
+        self.ctx.repo::<Item>().retrieve_all().await
     }
 
+    #[instrument(name = "Domain::find_items", skip_all)]
     async fn find_items(&self, filter: &ItemFilter) -> Result<Vec<Item>, Self::DomainError> {
-        self.ctx.items_repo().find_all(filter).await
-    }
-
-    async fn create_item(&self, spec: &ItemSpec) -> Result<Item, Self::DomainError> {
-        let ctx = self.ctx.start_transaction().await;
-        let items_repo = ctx.items_repo();
-        let id = items_repo.create(spec).await?;
-        if let Some(item) = items_repo.retrieve(&id).await? {
-            ctx.commit_transaction().await;
-            Ok(item)
-        } else {
-            panic!("item could not be retrieved following creation");
-        }
-    }
-
-    async fn update_item(&self, patch: &ItemPatch) -> Result<Option<Item>, Self::DomainError> {
-        let ctx = self.ctx.start_transaction().await;
-        let items_repo = ctx.items_repo();
-        match items_repo.update(patch).await? {
-            true => {
-                if let Some(item) = items_repo.retrieve(patch.id()).await? {
-                    ctx.commit_transaction().await;
-                    Ok(Some(item))
-                } else {
-                    panic!("item could not be retrieved following update");
+        self.ctx.repo::<Item>().find_all(filter).await
+    }
+
+    #[instrument(name = "Domain::find_items_page", skip(self, filter))]
+    async fn find_items_page(
+        &self,
+        filter: &ItemFilter,
+        offset: usize,
+        limit: usize,
+    ) -> Result<Vec<Item>, Self::DomainError> {
+        self.ctx
+            .repo::<Item>()
+            .find_page(filter, offset, limit)
+            .await
+    }
+
+    #[instrument(name = "Domain::item_tags", skip_all)]
+    async fn item_tags(&self, filter: &ItemFilter) -> Result<Vec<Facet<Tag>>, Self::DomainError> {
+        self.ctx.repo::<Item>().facet("tags", filter).await
+    }
+
+    #[instrument(name = "Domain::items_near", skip_all)]
+    async fn items_near(
+        &self,
+        query: &GeoQuery,
+        filter: &ItemFilter,
+        limit: usize,
+    ) -> Result<Vec<Near<Item>>, Self::DomainError> {
+        self.ctx
+            .repo::<Item>()
+            .find_near("location", query, filter, limit)
+            .await
+    }
+
+    #[instrument(name = "Domain::create_item", skip_all)]
+    async fn create_item(
+        &self,
+        spec: &ItemSpec,
+        idempotency_key: Option<&IdempotencyKey>,
+    ) -> Result<Item, Self::DomainError> {
+        create(&self.ctx, spec, idempotency_key, CREATE_ITEM_OPERATION).await
+    }
+
+    #[instrument(name = "Domain::update_item", skip_all, fields(id = %patch.id()))]
+    async fn update_item(
+        &self,
+        patch: &ItemPatch,
+        idempotency_key: Option<&IdempotencyKey>,
+    ) -> Result<Option<Item>, Self::DomainError> {
+        update(
+            &self.ctx,
+            patch,
+            Some(Item::check_patch),
+            idempotency_key,
+            UPDATE_ITEM_OPERATION,
+        )
+        .await
+    }
+
+    #[instrument(name = "Domain::delete_item", skip_all, fields(%id))]
+    async fn delete_item(
+        &self,
+        id: &Id<Item>,
+        idempotency_key: Option<&IdempotencyKey>,
+    ) -> Result<Option<Item>, Self::DomainError> {
+        delete(&self.ctx, id, idempotency_key, DELETE_ITEM_OPERATION).await
+    }
+
+    #[instrument(name = "Domain::owner", skip_all, fields(%id))]
+    async fn owner(&self, id: &Id<Owner>) -> Result<Option<Owner>, Self::DomainError> {
+        self.ctx.repo::<Owner>().retrieve(id).await
+    }
+
+    #[instrument(name = "Domain::owners", skip_all, fields(count = ids.len()))]
+    async fn owners(&self, ids: &[Id<Owner>]) -> Result<Vec<Owner>, Self::DomainError> {
+        self.ctx.repo::<Owner>().retrieve_many(ids).await
+    }
+
+    #[instrument(name = "Domain::find_owners", skip_all)]
+    async fn find_owners(&self, filter: &OwnerFilter) -> Result<Vec<Owner>, Self::DomainError> {
+        self.ctx.repo::<Owner>().find_all(filter).await
+    }
+
+    #[instrument(name = "Domain::find_owners_page", skip(self, filter))]
+    async fn find_owners_page(
+        &self,
+        filter: &OwnerFilter,
+        offset: usize,
+        limit: usize,
+    ) -> Result<Vec<Owner>, Self::DomainError> {
+        self.ctx
+            .repo::<Owner>()
+            .find_page(filter, offset, limit)
+            .await
+    }
+
+    #[instrument(name = "Domain::create_owner", skip_all)]
+    async fn create_owner(
+        &self,
+        spec: &OwnerSpec,
+        idempotency_key: Option<&IdempotencyKey>,
+    ) -> Result<Owner, Self::DomainError> {
+        create(&self.ctx, spec, idempotency_key, CREATE_OWNER_OPERATION).await
+    }
+
+    #[instrument(name = "Domain::update_owner", skip_all, fields(id = %patch.id()))]
+    async fn update_owner(
+        &self,
+        patch: &OwnerPatch,
+        idempotency_key: Option<&IdempotencyKey>,
+    ) -> Result<Option<Owner>, Self::DomainError> {
+        update(
+            &self.ctx,
+            patch,
+            None,
+            idempotency_key,
+            UPDATE_OWNER_OPERATION,
+        )
+        .await
+    }
+
+    #[instrument(name = "Domain::delete_owner", skip_all, fields(%id))]
+    async fn delete_owner(
+        &self,
+        id: &Id<Owner>,
+        idempotency_key: Option<&IdempotencyKey>,
+    ) -> Result<Option<Owner>, Self::DomainError> {
+        delete(&self.ctx, id, idempotency_key, DELETE_OWNER_OPERATION).await
+    }
+
+    #[instrument(name = "Domain::stock_levels", skip_all)]
+    async fn stock_levels(
+        &self,
+        filter: &StockLevelFilter,
+    ) -> Result<Vec<StockLevel>, Self::DomainError> {
+        self.ctx.repo::<StockLevel>().find_all(filter).await
+    }
+
+    #[instrument(name = "Domain::find_stock_movements_page", skip(self, filter))]
+    async fn find_stock_movements_page(
+        &self,
+        filter: &StockMovementFilter,
+        offset: usize,
+        limit: usize,
+    ) -> Result<Vec<StockMovement>, Self::DomainError> {
+        self.ctx
+            .repo::<StockMovement>()
+            .find_page(filter, offset, limit)
+            .await
+    }
+
+    #[instrument(name = "Domain::adjust_stock", skip_all, fields(%item_id, %location, delta))]
+    async fn adjust_stock(
+        &self,
+        item_id: &Id<Item>,
+        location: &Name,
+        delta: i64,
+        idempotency_key: Option<&IdempotencyKey>,
+    ) -> Result<StockChange, Self::DomainError> {
+        let movement = match StockMovementSpec::adjustment(
+            item_id.clone(),
+            location.clone(),
+            delta,
+            DateTime::now(),
+        ) {
+            Ok(movement) => movement,
+            Err(e) => return Ok(StockChange::Invalid(e)),
+        };
+        change_stock(
+            &self.ctx,
+            &[(location, delta)],
+            &movement,
+            idempotency_key,
+            ADJUST_STOCK_OPERATION,
+        )
+        .await
+    }
+
+    #[instrument(name = "Domain::transfer_stock", skip_all, fields(%item_id, %from, %to, quantity))]
+    async fn transfer_stock(
+        &self,
+        item_id: &Id<Item>,
+        from: &Name,
+        to: &Name,
+        quantity: i64,
+        idempotency_key: Option<&IdempotencyKey>,
+    ) -> Result<StockChange, Self::DomainError> {
+        let movement = match StockMovementSpec::transfer(
+            item_id.clone(),
+            from.clone(),
+            to.clone(),
+            quantity,
+            DateTime::now(),
+        ) {
+            Ok(movement) => movement,
+            Err(e) => return Ok(StockChange::Invalid(e)),
+        };
+        change_stock(
+            &self.ctx,
+            &[(from, -quantity), (to, quantity)],
+            &movement,
+            idempotency_key,
+            TRANSFER_STOCK_OPERATION,
+        )
+        .await
+    }
+}
+
+/// Runs a unit of work in a transaction, which it must commit or abort; the work is run again in a
+/// new transaction if the transaction is aborted by a transient error, such as a write conflict,
+/// as far as the context's retry policy allows.
+async fn in_transaction<C, T, F, Fut>(ctx: &C, work: F) -> Result<T, C::RepoError>
+where
+    C: DomainContext,
+    F: Fn(C) -> Fut,
+    Fut: Future<Output = Result<T, C::RepoError>>,
+{
+    let work = &work;
+    retry(
+        &ctx.transaction_retry_policy(),
+        "transaction",
+        RetryableError::is_transient_transaction_error,
+        || async move { work(ctx.start_transaction().await?).await },
+    )
+    .await
+}
+
+/// Creates an entity in a transaction, replaying the response recorded for the idempotency key
+/// instead if there is one.
+///
+/// # Arguments
+/// * `ctx` - the context to start the transaction from
+/// * `spec` - a specification for the entity to create
+/// * `idempotency_key` - the key to make the creation idempotent with, if any
+/// * `operation` - the name under which the response is recorded
+async fn create<C, R>(
+    ctx: &C,
+    spec: &R::Spec,
+    idempotency_key: Option<&IdempotencyKey>,
+    operation: &str,
+) -> Result<R, C::RepoError>
+where
+    C: DomainContext,
+    R: DomainEntity,
+{
+    in_transaction(ctx, |ctx| async move {
+        let idempotency_store = ctx.idempotency_store();
+        if let Some(entity) = recorded(idempotency_store, idempotency_key, operation).await? {
+            ctx.abort_transaction().await?;
+            return Ok(entity);
+        }
+        let repo = ctx.repo::<R>();
+        let entity = repo.create_and_get(spec).await?;
+        record(idempotency_store, idempotency_key, operation, &entity).await?;
+        ctx.commit_transaction().await?;
+        Ok(entity)
+    })
+    .await
+}
+
+/// Checks that a patch keeps the invariants of an entity, as the entity is before the update.
+type PatchCheck<R> = fn(&R, &<R as Reposable>::Patch) -> Result<(), InvalidEntityError>;
+
+/// Updates an entity in a transaction, replaying the response recorded for the idempotency key
+/// instead if there is one.
+///
+/// # Arguments
+/// * `ctx` - the context to start the transaction from
+/// * `patch` - the patch to update the entity with
+/// * `check` - if provided, checks the patch keeps the invariants of the entity as it is before
+///   the update; the entity is read first in the same transaction, and not updated if it fails
+/// * `idempotency_key` - the key to make the update idempotent with, if any
+/// * `operation` - the name under which the response is recorded
+async fn update<C, R>(
+    ctx: &C,
+    patch: &R::Patch,
+    check: Option<PatchCheck<R>>,
+    idempotency_key: Option<&IdempotencyKey>,
+    operation: &str,
+) -> Result<Option<R>, C::RepoError>
+where
+    C: DomainContext,
+    R: DomainEntity,
+{
+    in_transaction(ctx, |ctx| async move {
+        let idempotency_store = ctx.idempotency_store();
+        if let Some(entity) = recorded(idempotency_store, idempotency_key, operation).await? {
+            ctx.abort_transaction().await?;
+            return Ok(entity);
+        }
+        let repo = ctx.repo::<R>();
+        if let Some(check) = check {
+            if let Some(entity) = repo.retrieve(patch.id()).await? {
+                if let Err(e) = check(&entity, patch) {
+                    ctx.abort_transaction().await?;
+                    return Err(e.into());
                 }
             }
-            false => Ok(None),
         }
-    }
+        let entity = repo.update_and_get(patch, ReturnDocument::After).await?;
+        record(idempotency_store, idempotency_key, operation, &entity).await?;
+        ctx.commit_transaction().await?;
+        Ok(entity)
+    })
+    .await
+}
 
-    async fn delete_item(&self, id: &Id) -> Result<bool, Self::DomainError> {
-        let ctx = self.ctx.start_transaction().await;
-        let items_repo = ctx.items_repo();
-        match items_repo.delete(id).await? {
-            true => {
-                ctx.commit_transaction().await;
-                Ok(true)
+/// Deletes an entity in a transaction, replaying the response recorded for the idempotency key
+/// instead if there is one.
+async fn delete<C, R>(
+    ctx: &C,
+    id: &Id<R>,
+    idempotency_key: Option<&IdempotencyKey>,
+    operation: &str,
+) -> Result<Option<R>, C::RepoError>
+where
+    C: DomainContext,
+    R: DomainEntity,
+{
+    in_transaction(ctx, |ctx| async move {
+        let idempotency_store = ctx.idempotency_store();
+        if let Some(entity) = recorded(idempotency_store, idempotency_key, operation).await? {
+            ctx.abort_transaction().await?;
+            return Ok(entity);
+        }
+        let entity = ctx.repo::<R>().delete_and_get(id).await?;
+        record(idempotency_store, idempotency_key, operation, &entity).await?;
+        ctx.commit_transaction().await?;
+        Ok(entity)
+    })
+    .await
+}
+
+/// Changes the stock levels of an item in a transaction and records the movement in the ledger,
+/// replaying the movement recorded for the idempotency key instead if there is one. Nothing is
+/// changed unless the item exists and every stock level can be changed.
+///
+/// # Arguments
+/// * `ctx` - the context to start the transaction from
+/// * `changes` - the locations whose stock to change, with the amount to change each by
+/// * `movement` - the movement to record in the ledger
+/// * `idempotency_key` - the key to make the change idempotent with, if any
+/// * `operation` - the name under which the response is recorded
+async fn change_stock<C>(
+    ctx: &C,
+    changes: &[(&Name, i64)],
+    movement: &StockMovementSpec,
+    idempotency_key: Option<&IdempotencyKey>,
+    operation: &str,
+) -> Result<StockChange, C::RepoError>
+where
+    C: DomainContext,
+{
+    in_transaction(ctx, |ctx| async move {
+        let idempotency_store = ctx.idempotency_store();
+        if let Some(movement) = recorded(idempotency_store, idempotency_key, operation).await? {
+            ctx.abort_transaction().await?;
+            return Ok(StockChange::Made(movement));
+        }
+        let item_id = movement.item_id();
+        if ctx.repo::<Item>().retrieve(item_id).await?.is_none() {
+            ctx.abort_transaction().await?;
+            return Ok(StockChange::ItemNotFound);
+        }
+        for (location, delta) in changes {
+            if change_stock_level(&ctx, item_id, location, *delta)
+                .await?
+                .is_none()
+            {
+                ctx.abort_transaction().await?;
+                return Ok(StockChange::InsufficientStock);
             }
-            false => Ok(false),
         }
+        let repo = ctx.repo::<StockMovement>();
+        let movement = repo.create_and_get(movement).await?;
+        record(idempotency_store, idempotency_key, operation, &movement).await?;
+        ctx.commit_transaction().await?;
+        Ok(StockChange::Made(movement))
+    })
+    .await
+}
+
+/// Changes the stock of an item at a location by an amount, creating its stock level there if it
+/// has none yet; the update is conditional on the quantity not becoming negative.
+///
+/// # Returns
+/// `Some()` of the changed stock level, or `None` if there was not enough stock to take
+async fn change_stock_level<C>(
+    ctx: &C,
+    item_id: &Id<Item>,
+    location: &Name,
+    delta: i64,
+) -> Result<Option<StockLevel>, C::RepoError>
+where
+    C: DomainContext,
+{
+    let repo = ctx.repo::<StockLevel>();
+    let mut filter = StockLevelFilter::default();
+    *filter.item_id_mut() = Some(item_id.clone());
+    *filter.location_mut() = Some(location.clone());
+    let id = match repo.find_all(&filter).await?.into_iter().next() {
+        Some(stock_level) => stock_level.id().clone(),
+        None if delta < 0 => return Ok(None),
+        None => {
+            repo.create(&StockLevelSpec::new(item_id.clone(), location.clone(), 0))
+                .await?
+        }
+    };
+    let mut patch = StockLevelPatch::new(id);
+    *patch.quantity_mut() = Some(Increment(delta));
+    let mut condition = StockLevelFilter::default();
+    *condition.quantity_mut() = Some(RangeFilter::at_least(-delta));
+    repo.update_and_get_if(&patch, &condition, ReturnDocument::After)
+        .await
+}
+
+/// Returns the response recorded for a mutation, if it was made with an idempotency key that has
+/// been used for the same mutation before.
+async fn recorded<S, T>(
+    idempotency_store: &S,
+    idempotency_key: Option<&IdempotencyKey>,
+    operation: &str,
+) -> Result<Option<T>, S::StoreError>
+where
+    S: IdempotencyStore + Sync,
+    T: DeserializeOwned + Send + Sync + Unpin,
+{
+    match idempotency_key {
+        Some(key) => idempotency_store.recorded(key, operation).await,
+        None => Ok(None),
+    }
+}
+
+/// Records the response to a mutation, if it was made with an idempotency key.
+async fn record<S, T>(
+    idempotency_store: &S,
+    idempotency_key: Option<&IdempotencyKey>,
+    operation: &str,
+    response: &T,
+) -> Result<(), S::StoreError>
+where
+    S: IdempotencyStore + Sync,
+    T: Serialize + Send + Sync,
+{
+    match idempotency_key {
+        Some(key) => idempotency_store.record(key, operation, response).await,
+        None => Ok(()),
     }
 }
 
 mod context {
-    use super::models::items::Item;
-    use crate::storage::{mongo_repo::MongoRepo, repo::Repo};
+    use super::{models::items::Item, DomainEntity};
+    use crate::{
+        common::{deadline::Deadline, entity::InvalidEntityError, tenant::TenantId},
+        metrics::TRANSACTIONS,
+        storage::{
+            cached_repo::{self, CachedRepo, RepoCache, RepoCaches},
+            idempotency::{self, IdempotencyStore, MongoIdempotencyStore},
+            instrumented_repo::InstrumentedRepo,
+            mongo_options::MongoRepoOptions,
+            mongo_repo::{MongoRepo, MongoRepoError, MongoReposable},
+            namespace::NamespaceResolver,
+            repo::Repo,
+            retrying_repo::{RetryPolicy, RetryableError, RetryingRepo},
+        },
+    };
     use async_trait::async_trait;
-    use std::{ops::DerefMut, sync::Arc};
+    use mongodb::options::TransactionOptions;
+    use std::{
+        any::{Any, TypeId},
+        collections::HashMap,
+        ops::DerefMut,
+        sync::Arc,
+        time::Duration,
+    };
     use tokio::sync::Mutex;
+    use tracing::warn;
 
+    /// A unit of work: the repositories of every entity of the domain, which all take part in the
+    /// same transaction once one is started.
     #[async_trait]
     pub trait DomainContext: Clone {
-        type ItemsRepo: Repo<Item>;
+        type RepoError: RetryableError + From<InvalidEntityError> + Send;
+        type Repo<R: DomainEntity>: Repo<R, RepoError = Self::RepoError> + Send + Sync;
+        type IdempotencyStore: IdempotencyStore<StoreError = Self::RepoError> + Sync;
+
+        /// Returns the repository of a type of entity, e.g. `ctx.repo::<Item>()`.
+        fn repo<R: DomainEntity>(&self) -> Self::Repo<R>;
+
+        /// Returns the store of responses to idempotent mutations; within a transaction, responses
+        /// are recorded in the same transaction.
+        fn idempotency_store(&self) -> &Self::IdempotencyStore;
 
-        fn items_repo(&self) -> &Self::ItemsRepo;
+        /// Returns the policy by which transactions aborted by transient errors are run again,
+        /// and commits whose outcome is unknown are attempted again.
+        fn transaction_retry_policy(&self) -> RetryPolicy;
 
-        async fn start_transaction(&self) -> Self;
-        async fn abort_transaction(mut self);
-        async fn commit_transaction(mut self);
+        /// Returns a copy of this context whose repositories take part in a newly started
+        /// transaction.
+        async fn start_transaction(&self) -> Result<Self, Self::RepoError>;
+
+        /// Aborts the transaction of a context returned by `start_transaction`.
+        async fn abort_transaction(mut self) -> Result<(), Self::RepoError>;
+
+        /// Commits the transaction of a context returned by `start_transaction`; a commit that
+        /// does not complete before the context's deadline fails as timed out.
+        async fn commit_transaction(mut self) -> Result<(), Self::RepoError>;
     }
 
     #[derive(Clone)]
     pub struct MongoDomainContext {
         mongo_client: mongodb::Client,
+        namespace: NamespaceResolver,
+        retry_policy: RetryPolicy,
+        repo_options: MongoRepoOptions,
+        transaction_options: Option<TransactionOptions>,
+        idempotency_ttl: Duration,
+        deadline: Option<Deadline>,
         mongo_session: Option<Arc<Mutex<mongodb::ClientSession>>>,
-        items_repo: MongoRepo<Item>,
+        caches: RepoCaches,
+        repos: Repos,
+        idempotency_store: MongoIdempotencyStore,
     }
 
+    type MongoEntityRepo<R> = CachedRepo<R, RetryingRepo<R, InstrumentedRepo<R, MongoRepo<R>>>>;
+
     impl MongoDomainContext {
-        pub fn new(mongo_client: mongodb::Client) -> Self {
-            let mongo_session = None;
-            let items_repo = MongoRepo::new(mongo_client.clone());
+        pub fn new(mongo_client: mongodb::Client, namespace: NamespaceResolver) -> Self {
+            let idempotency_store = MongoIdempotencyStore::new(
+                mongo_client.clone(),
+                namespace.clone(),
+                Item::db_name(),
+            );
             Self {
                 mongo_client,
-                mongo_session,
-                items_repo,
+                namespace,
+                retry_policy: RetryPolicy::default(),
+                repo_options: MongoRepoOptions::default(),
+                transaction_options: None,
+                idempotency_ttl: idempotency::DEFAULT_TTL,
+                deadline: None,
+                mongo_session: None,
+                caches: RepoCaches::new(),
+                repos: Repos::default(),
+                idempotency_store,
+            }
+            .rebuild()
+        }
+
+        /// Returns this context configured to retry operations outside of transactions according
+        /// to the provided policy.
+        pub fn with_retry_policy(mut self, retry_policy: RetryPolicy) -> Self {
+            self.retry_policy = retry_policy;
+            self.rebuild()
+        }
+
+        /// Returns this context configured to use the provided read/write concerns and read
+        /// preferences for repository operations outside of transactions.
+        pub fn with_repo_options(mut self, repo_options: MongoRepoOptions) -> Self {
+            self.repo_options = repo_options;
+            self.rebuild()
+        }
+
+        /// Returns this context configured to start transactions with the provided options.
+        pub fn with_transaction_options(mut self, transaction_options: TransactionOptions) -> Self {
+            self.transaction_options = Some(transaction_options);
+            self
+        }
+
+        /// Returns this context configured to replay the responses to idempotent mutations for the
+        /// provided time after they are made.
+        pub fn with_idempotency_ttl(mut self, idempotency_ttl: Duration) -> Self {
+            self.idempotency_ttl = idempotency_ttl;
+            self.rebuild()
+        }
+
+        /// Returns this context configured to cache entities of the provided cache's type in it;
+        /// entities of other types are not cached unless given a cache of their own.
+        pub fn with_cache<R: DomainEntity>(mut self, cache: RepoCache<R>) -> Self {
+            self.caches = self.caches.with(cache);
+            self.rebuild()
+        }
+
+        /// Returns a copy of this context whose repositories can only access the provided
+        /// tenant's data.
+        pub fn for_tenant(&self, tenant_id: TenantId) -> Self {
+            let mut ctx = self.clone();
+            ctx.namespace = self.namespace.for_tenant(tenant_id);
+            ctx.rebuild()
+        }
+
+        /// Returns a copy of this context whose repository operations fail once the provided
+        /// deadline has passed.
+        pub fn with_deadline(&self, deadline: Deadline) -> Self {
+            let mut ctx = self.clone();
+            ctx.deadline = Some(deadline);
+            ctx.rebuild()
+        }
+
+        /// Discards the repositories of this context after its configuration has changed, so that
+        /// they are built again with the new configuration.
+        fn rebuild(mut self) -> Self {
+            self.caches = self.caches.scoped(
+                self.namespace
+                    .tenant_id()
+                    .map_or("", |tenant| tenant.as_ref()),
+            );
+            self.repos = Repos::default();
+            self.idempotency_store = MongoIdempotencyStore::new(
+                self.mongo_client.clone(),
+                self.namespace.clone(),
+                Item::db_name(),
+            )
+            .with_ttl(self.idempotency_ttl);
+            self
+        }
+
+        /// Builds the repository of a type of entity; within a transaction, operations are not
+        /// retried individually, reads bypass the cache and the transaction's options apply
+        /// instead of the repository options.
+        fn build_repo<R: DomainEntity>(&self) -> MongoEntityRepo<R> {
+            let (mongo_repo, retry_policy) = match self.mongo_session {
+                Some(ref mongo_session) => (
+                    MongoRepo::new_with_session(
+                        self.mongo_client.clone(),
+                        self.namespace.clone(),
+                        Arc::clone(mongo_session),
+                    ),
+                    RetryPolicy::none(),
+                ),
+                None => (
+                    MongoRepo::new(self.mongo_client.clone(), self.namespace.clone())
+                        .with_options(self.repo_options.clone()),
+                    self.retry_policy,
+                ),
+            };
+            let mongo_repo = match self.deadline {
+                Some(deadline) => mongo_repo.with_deadline(deadline),
+                None => mongo_repo,
+            };
+            let repo = RetryingRepo::new(
+                InstrumentedRepo::new(mongo_repo, R::collection_name()),
+                retry_policy,
+            );
+            // a cache with no capacity caches nothing
+            let cache = self.caches.get::<R>().unwrap_or_else(|| {
+                RepoCache::new(cached_repo::DEFAULT_CAPACITY, cached_repo::DEFAULT_TTL)
+            });
+            match self.mongo_session {
+                Some(_) => CachedRepo::new_invalidating(repo, cache),
+                None => CachedRepo::new(repo, cache),
             }
         }
     }
 
     #[async_trait]
     impl DomainContext for MongoDomainContext {
-        type ItemsRepo = MongoRepo<Item>;
+        type RepoError = MongoRepoError;
+        type Repo<R: DomainEntity> = MongoEntityRepo<R>;
+        type IdempotencyStore = MongoIdempotencyStore;
 
-        fn items_repo(&self) -> &MongoRepo<Item> {
-            &self.items_repo
+        fn repo<R: DomainEntity>(&self) -> Self::Repo<R> {
+            self.repos.get_or_build(|| self.build_repo::<R>())
         }
 
-        async fn start_transaction(&self) -> Self {
-            let mongo_client = self.mongo_client.clone();
-            let mut mongo_session = mongo_client.start_session(None).await.unwrap();
-            mongo_session.start_transaction(None).await.unwrap();
-            let mongo_session = Arc::new(tokio::sync::Mutex::new(mongo_session));
-            let items_repo =
-                MongoRepo::new_with_session(mongo_client.clone(), Arc::clone(&mongo_session));
-            let mongo_session = Some(mongo_session);
-            Self {
-                mongo_client,
-                mongo_session,
-                items_repo,
+        fn idempotency_store(&self) -> &Self::IdempotencyStore {
+            &self.idempotency_store
+        }
+
+        fn transaction_retry_policy(&self) -> RetryPolicy {
+            self.retry_policy
+        }
+
+        async fn start_transaction(&self) -> Result<Self, Self::RepoError> {
+            let mut mongo_session = self.mongo_client.start_session(None).await?;
+            let mut transaction_options = self.transaction_options.clone().unwrap_or_default();
+            if let Some(remaining) = self.deadline.and_then(|deadline| deadline.remaining()) {
+                transaction_options.max_commit_time = Some(
+                    transaction_options
+                        .max_commit_time
+                        .map_or(remaining, |max_commit_time| max_commit_time.min(remaining)),
+                );
             }
+            mongo_session.start_transaction(transaction_options).await?;
+            let mongo_session = Arc::new(tokio::sync::Mutex::new(mongo_session));
+            let idempotency_store = MongoIdempotencyStore::new_with_session(
+                self.mongo_client.clone(),
+                self.namespace.clone(),
+                Item::db_name(),
+                Arc::clone(&mongo_session),
+            )
+            .with_ttl(self.idempotency_ttl);
+            Ok(Self {
+                mongo_session: Some(mongo_session),
+                repos: Repos::default(),
+                idempotency_store,
+                ..self.clone()
+            })
         }
 
-        async fn abort_transaction(mut self) {
-            let session = self.mongo_session.take().unwrap();
+        async fn abort_transaction(mut self) -> Result<(), Self::RepoError> {
+            let session = self.mongo_session.take().expect("no transaction to abort");
             let mut session_guard = session.lock().await;
             let session = session_guard.deref_mut();
-            session.abort_transaction().await.unwrap();
+            session.abort_transaction().await?;
+            TRANSACTIONS.with_label_values(&["abort"]).inc();
+            Ok(())
         }
 
-        async fn commit_transaction(mut self) {
-            let session = self.mongo_session.take().unwrap();
+        async fn commit_transaction(mut self) -> Result<(), Self::RepoError> {
+            let session = self.mongo_session.take().expect("no transaction to commit");
             let mut session_guard = session.lock().await;
             let session = session_guard.deref_mut();
-            session.commit_transaction().await.unwrap();
+            let mut attempt = 1;
+            loop {
+                match session
+                    .commit_transaction()
+                    .await
+                    .map_err(MongoRepoError::from)
+                {
+                    Err(e)
+                        if e.is_unknown_commit_result()
+                            && attempt < self.retry_policy.max_attempts() =>
+                    {
+                        warn!("commit failed on attempt {}; retrying: {}", attempt, e);
+                        attempt += 1;
+                    }
+                    result => break result?,
+                }
+            }
+            TRANSACTIONS.with_label_values(&["commit"]).inc();
+            self.repos.invalidate_touched();
+            Ok(())
+        }
+    }
+
+    /// The repositories obtained from a context, each built on first use and shared with the
+    /// context's clones.
+    #[derive(Clone, Default)]
+    struct Repos {
+        repos: Arc<std::sync::Mutex<HashMap<TypeId, Box<dyn AnyRepo>>>>,
+    }
+
+    impl Repos {
+        /// Returns the repository of the provided type, building it if it has not been yet.
+        fn get_or_build<T: AnyRepo + Clone + 'static>(&self, build: impl FnOnce() -> T) -> T {
+            let mut repos = self.repos.lock().unwrap();
+            let repo = repos
+                .entry(TypeId::of::<T>())
+                .or_insert_with(|| Box::new(build()));
+            repo.as_any()
+                .downcast_ref::<T>()
+                .expect("repositories are stored by type")
+                .clone()
+        }
+
+        /// Invalidates the cached entities written through every repository, once the
+        /// transaction they took part in has committed; see `CachedRepo::invalidate_touched`.
+        fn invalidate_touched(&self) {
+            for repo in self.repos.lock().unwrap().values() {
+                repo.invalidate_touched();
+            }
+        }
+    }
+
+    /// The repository of any type of entity.
+    trait AnyRepo: Send + Sync {
+        fn invalidate_touched(&self);
+        fn as_any(&self) -> &dyn Any;
+    }
+
+    impl<R: DomainEntity> AnyRepo for MongoEntityRepo<R> {
+        fn invalidate_touched(&self) {
+            CachedRepo::invalidate_touched(self);
+        }
+
+        fn as_any(&self) -> &dyn Any {
+            self
         }
     }
 }
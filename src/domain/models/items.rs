@@ -1,23 +1,50 @@
-pub use filter::*;
-pub use patch::*;
-pub use spec::*;
-
+use mongo_repo_derive::Reposable;
+use mongodb::bson::{doc, Document};
 use serde::{Deserialize, Serialize};
 
-use crate::common::{entity::Entity, id::Id, name::Name};
-
-const MONGO_DB: &str = "repotest";
-const MONGO_COLLECTION: &str = "items";
-
-#[derive(Deserialize)]
+use super::owners::Owner;
+use crate::common::{
+    attribute::{AttributeKey, AttributeValue, Attributes, AttributesPatch, MAX_ATTRIBUTES},
+    entity::InvalidEntityError,
+    geo::GeoPoint,
+    id::Id,
+    name::Name,
+    tag::Tag,
+};
+use crate::storage::{
+    filter::{ArrayFilter, MapFilter, ValueFilter},
+    patch::ArrayPatch,
+};
+
+#[derive(Clone, Serialize, Deserialize, Reposable)]
+#[reposable(db = "repotest", collection = "items", json_schema = "json_schema")]
 pub struct Item {
     #[serde(rename = "_id")]
-    id: Id,
+    id: Id<Item>,
+    #[reposable(filter)]
     name: Name,
+    #[reposable(filter)]
     size: ItemSize,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    description: Option<String>,
+    #[reposable(filter)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    owner_id: Option<Id<Owner>>,
+    #[reposable(patch = "ArrayPatch<Tag>", filter = "ArrayFilter<Tag>")]
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    tags: Vec<Tag>,
+    #[reposable(
+        patch = "AttributesPatch",
+        filter = "MapFilter<AttributeKey, ValueFilter<AttributeValue>>"
+    )]
+    #[serde(default, skip_serializing_if = "Attributes::is_empty")]
+    attributes: Attributes,
+    #[reposable(geo)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    location: Option<GeoPoint>,
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Clone, Serialize, Deserialize)]
 pub enum ItemSize {
     Small,
     Medium,
@@ -25,8 +52,17 @@ pub enum ItemSize {
 }
 
 impl Item {
-    pub fn new(id: Id, name: Name, size: ItemSize) -> Self {
-        Self { id, name, size }
+    pub fn new(id: Id<Item>, name: Name, size: ItemSize) -> Self {
+        Self {
+            id,
+            name,
+            size,
+            description: None,
+            owner_id: None,
+            tags: vec![],
+            attributes: Attributes::default(),
+            location: None,
+        }
     }
 
     pub fn name(&self) -> &Name {
@@ -36,163 +72,71 @@ impl Item {
     pub fn size(&self) -> &ItemSize {
         &self.size
     }
-}
 
-impl Entity for Item {
-    fn id(&self) -> &Id {
-        &self.id
+    pub fn description(&self) -> Option<&str> {
+        self.description.as_deref()
     }
-}
-
-mod repo {
-    use super::*;
-    use crate::storage::{mongo_repo::MongoReposable, repo::Reposable};
 
-    impl Reposable for Item {
-        type Spec = ItemSpec;
-        type Patch = ItemPatch;
-        type Filter = ItemFilter;
+    /// Returns the ID of the owner of the item, if it has one; the owner may have since been
+    /// deleted.
+    pub fn owner_id(&self) -> Option<&Id<Owner>> {
+        self.owner_id.as_ref()
     }
 
-    impl MongoReposable for Item {
-        fn db_name() -> &'static str {
-            MONGO_DB
-        }
-
-        fn collection_name() -> &'static str {
-            MONGO_COLLECTION
-        }
+    pub fn tags(&self) -> &[Tag] {
+        &self.tags
     }
-}
-
-mod spec {
-    use super::*;
-    use crate::common::name::Name;
-    use serde::Serialize;
 
-    #[derive(Serialize)]
-    pub struct ItemSpec {
-        name: Name,
-        size: ItemSize,
+    pub fn attributes(&self) -> &Attributes {
+        &self.attributes
     }
 
-    impl ItemSpec {
-        pub fn new(name: Name, size: ItemSize) -> Self {
-            Self { name, size }
-        }
-
-        pub fn name(&self) -> &Name {
-            &self.name
-        }
-
-        pub fn name_mut(&mut self) -> &mut Name {
-            &mut self.name
-        }
-
-        pub fn size(&self) -> &ItemSize {
-            &self.size
-        }
-
-        pub fn size_mut(&mut self) -> &mut ItemSize {
-            &mut self.size
+    /// Checks that a patch leaves this item within the limits on attributes, which depend on the
+    /// attributes it already has.
+    pub fn check_patch(&self, patch: &ItemPatch) -> Result<(), InvalidEntityError> {
+        match patch.attributes() {
+            Some(attributes) => attributes
+                .check(&self.attributes)
+                .map_err(InvalidEntityError::new),
+            None => Ok(()),
         }
     }
-}
-
-mod patch {
-    use super::*;
-    use crate::{
-        common::{id::Id, name::Name},
-        storage::repo::Patch,
-    };
-    use serde::Serialize;
 
-    #[derive(Serialize)]
-    pub struct ItemPatch {
-        #[serde(skip)]
-        id: Id,
-        #[serde(skip_serializing_if = "Option::is_none")]
-        name: Option<Name>,
-        #[serde(skip_serializing_if = "Option::is_none")]
-        size: Option<ItemSize>,
-    }
-
-    impl ItemPatch {
-        pub fn new(id: Id) -> Self {
-            Self {
-                id,
-                name: None,
-                size: None,
-            }
-        }
-
-        pub fn name(&self) -> &Option<Name> {
-            &self.name
-        }
-
-        pub fn name_mut(&mut self) -> &mut Option<Name> {
-            &mut self.name
-        }
-
-        pub fn size(&self) -> &Option<ItemSize> {
-            &self.size
-        }
-
-        pub fn size_mut(&mut self) -> &mut Option<ItemSize> {
-            &mut self.size
-        }
-    }
-
-    impl Patch for ItemPatch {
-        fn id(&self) -> &Id {
-            &self.id
-        }
+    pub fn location(&self) -> Option<&GeoPoint> {
+        self.location.as_ref()
     }
 }
 
-mod filter {
-    use super::*;
-    use crate::{
-        common::{id::Id, name::Name},
-        storage::repo::Filter,
-    };
-    use serde::Serialize;
-
-    #[derive(Default, Serialize)]
-    pub struct ItemFilter {
-        #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
-        id: Option<Id>,
-        #[serde(skip_serializing_if = "Option::is_none")]
-        name: Option<Name>,
-        #[serde(skip_serializing_if = "Option::is_none")]
-        size: Option<ItemSize>,
-    }
-
-    impl ItemFilter {
-        pub fn id(&self) -> &Option<Id> {
-            &self.id
-        }
-
-        pub fn name(&self) -> &Option<Name> {
-            &self.name
-        }
-
-        pub fn name_mut(&mut self) -> &mut Option<Name> {
-            &mut self.name
-        }
-
-        pub fn size(&self) -> &Option<ItemSize> {
-            &self.size
-        }
-
-        pub fn size_mut(&mut self) -> &mut Option<ItemSize> {
-            &mut self.size
-        }
-    }
-
-    impl Filter for ItemFilter {
-        fn id_mut(&mut self) -> &mut Option<Id> {
-            &mut self.id
-        }
+fn json_schema() -> Document {
+    doc! {
+        "bsonType": "object",
+        "required": ["name", "size"],
+        "properties": {
+            "name": { "bsonType": "string", "minLength": 1 },
+            "size": { "enum": ["Small", "Medium", "Large"] },
+            "description": { "bsonType": "string" },
+            "owner_id": { "bsonType": "objectId" },
+            "tags": { "bsonType": "array", "items": { "bsonType": "string" } },
+            "attributes": {
+                "bsonType": "object",
+                "maxProperties": MAX_ATTRIBUTES as i32,
+                "additionalProperties": {
+                    "bsonType": ["string", "double", "int", "long", "bool", "date"],
+                },
+            },
+            "location": {
+                "bsonType": "object",
+                "required": ["type", "coordinates"],
+                "properties": {
+                    "type": { "enum": ["Point"] },
+                    "coordinates": {
+                        "bsonType": "array",
+                        "minItems": 2,
+                        "maxItems": 2,
+                        "items": { "bsonType": ["double", "int", "long"] },
+                    },
+                },
+            },
+        },
     }
 }
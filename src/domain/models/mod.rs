@@ -1 +1,3 @@
 pub mod items;
+pub mod owners;
+pub mod stock;
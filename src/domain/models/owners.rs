@@ -0,0 +1,47 @@
+use mongo_repo_derive::Reposable;
+use mongodb::bson::{doc, Document};
+use serde::{Deserialize, Serialize};
+
+use crate::common::{id::Id, name::Name};
+
+/// A person who owns items.
+#[derive(Clone, Serialize, Deserialize, Reposable)]
+#[reposable(db = "repotest", collection = "owners", json_schema = "json_schema")]
+pub struct Owner {
+    #[serde(rename = "_id")]
+    id: Id<Owner>,
+    #[reposable(filter)]
+    name: Name,
+    #[reposable(filter)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    email: Option<String>,
+}
+
+impl Owner {
+    pub fn new(id: Id<Owner>, name: Name) -> Self {
+        Self {
+            id,
+            name,
+            email: None,
+        }
+    }
+
+    pub fn name(&self) -> &Name {
+        &self.name
+    }
+
+    pub fn email(&self) -> Option<&str> {
+        self.email.as_deref()
+    }
+}
+
+fn json_schema() -> Document {
+    doc! {
+        "bsonType": "object",
+        "required": ["name"],
+        "properties": {
+            "name": { "bsonType": "string", "minLength": 1 },
+            "email": { "bsonType": "string" },
+        },
+    }
+}
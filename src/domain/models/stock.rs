@@ -0,0 +1,257 @@
+use mongo_repo_derive::Reposable;
+use mongodb::bson::{doc, DateTime, Document};
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+
+use super::items::Item;
+use crate::common::{id::Id, name::Name};
+use crate::storage::{filter::RangeFilter, patch::Increment};
+
+/// The quantity of an item on hand at one location, e.g. a warehouse. There is at most one stock
+/// level per item and location, and its quantity is never negative.
+#[derive(Clone, Serialize, Deserialize, Reposable)]
+#[reposable(
+    db = "repotest",
+    collection = "stock_levels",
+    json_schema = "stock_level_json_schema"
+)]
+pub struct StockLevel {
+    #[serde(rename = "_id")]
+    id: Id<StockLevel>,
+    #[reposable(filter)]
+    item_id: Id<Item>,
+    #[reposable(filter)]
+    location: Name,
+    #[reposable(patch = "Increment<i64>", filter = "RangeFilter<i64>")]
+    quantity: i64,
+}
+
+impl StockLevel {
+    pub fn item_id(&self) -> &Id<Item> {
+        &self.item_id
+    }
+
+    pub fn location(&self) -> &Name {
+        &self.location
+    }
+
+    pub fn quantity(&self) -> i64 {
+        self.quantity
+    }
+}
+
+/// An entry in the ledger of stock movements: a quantity of an item added at a location, taken
+/// from one, or transferred from one location to another.
+#[derive(Clone, Serialize, Deserialize, Reposable)]
+#[reposable(
+    db = "repotest",
+    collection = "stock_movements",
+    json_schema = "stock_movement_json_schema"
+)]
+pub struct StockMovement {
+    #[serde(rename = "_id")]
+    id: Id<StockMovement>,
+    #[reposable(filter)]
+    item_id: Id<Item>,
+    #[reposable(filter)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    from_location: Option<Name>,
+    #[reposable(filter)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    to_location: Option<Name>,
+    quantity: i64,
+    recorded_at: DateTime,
+}
+
+impl StockMovement {
+    pub fn item_id(&self) -> &Id<Item> {
+        &self.item_id
+    }
+
+    /// Returns the location the stock was taken from, if any; stock added by an adjustment comes
+    /// from nowhere.
+    pub fn from_location(&self) -> Option<&Name> {
+        self.from_location.as_ref()
+    }
+
+    /// Returns the location the stock was added at, if any; stock taken by an adjustment goes
+    /// nowhere.
+    pub fn to_location(&self) -> Option<&Name> {
+        self.to_location.as_ref()
+    }
+
+    /// Returns the quantity of the item moved, which is always positive.
+    pub fn quantity(&self) -> i64 {
+        self.quantity
+    }
+
+    pub fn recorded_at(&self) -> DateTime {
+        self.recorded_at
+    }
+}
+
+impl StockMovementSpec {
+    /// Creates the movement recording an adjustment of the stock of an item at a location: `delta`
+    /// is added there, or taken from there if it is negative.
+    pub fn adjustment(
+        item_id: Id<Item>,
+        location: Name,
+        delta: i64,
+        recorded_at: DateTime,
+    ) -> Result<Self, InvalidStockChangeError> {
+        let quantity = delta
+            .checked_abs()
+            .filter(|quantity| *quantity > 0)
+            .ok_or(InvalidStockChangeError::ZeroAdjustment)?;
+        let mut movement = Self::new(item_id, quantity, recorded_at);
+        match delta < 0 {
+            true => *movement.from_location_mut() = Some(location),
+            false => *movement.to_location_mut() = Some(location),
+        }
+        Ok(movement)
+    }
+
+    /// Creates the movement recording a transfer of a positive quantity of an item from one
+    /// location to another.
+    pub fn transfer(
+        item_id: Id<Item>,
+        from: Name,
+        to: Name,
+        quantity: i64,
+        recorded_at: DateTime,
+    ) -> Result<Self, InvalidStockChangeError> {
+        if quantity <= 0 {
+            return Err(InvalidStockChangeError::NonPositiveTransfer);
+        }
+        if from == to {
+            return Err(InvalidStockChangeError::SameLocation);
+        }
+        let mut movement = Self::new(item_id, quantity, recorded_at);
+        *movement.from_location_mut() = Some(from);
+        *movement.to_location_mut() = Some(to);
+        Ok(movement)
+    }
+}
+
+/// The outcome of a change to the stock of an item.
+#[derive(Clone)]
+pub enum StockChange {
+    /// The change was made, and recorded in the ledger as this movement.
+    Made(StockMovement),
+    /// The item does not exist.
+    ItemNotFound,
+    /// The location the stock would be taken from does not have enough of it.
+    InsufficientStock,
+    /// The change itself is invalid, so nothing was changed.
+    Invalid(InvalidStockChangeError),
+}
+
+/// An error indicating a change to the stock of an item could not be made whatever the stock.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum InvalidStockChangeError {
+    ZeroAdjustment,
+    NonPositiveTransfer,
+    SameLocation,
+}
+
+impl Error for InvalidStockChangeError {}
+
+impl Display for InvalidStockChangeError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::ZeroAdjustment => write!(f, "the quantity to adjust by cannot be 0"),
+            Self::NonPositiveTransfer => write!(f, "the quantity to transfer must be positive"),
+            Self::SameLocation => write!(f, "stock cannot be transferred to the same location"),
+        }
+    }
+}
+
+fn stock_level_json_schema() -> Document {
+    doc! {
+        "bsonType": "object",
+        "required": ["item_id", "location", "quantity"],
+        "properties": {
+            "item_id": { "bsonType": "objectId" },
+            "location": { "bsonType": "string", "minLength": 1 },
+            "quantity": { "bsonType": ["int", "long"], "minimum": 0 },
+        },
+    }
+}
+
+fn stock_movement_json_schema() -> Document {
+    doc! {
+        "bsonType": "object",
+        "required": ["item_id", "quantity", "recorded_at"],
+        "properties": {
+            "item_id": { "bsonType": "objectId" },
+            "from_location": { "bsonType": "string", "minLength": 1 },
+            "to_location": { "bsonType": "string", "minLength": 1 },
+            "quantity": { "bsonType": ["int", "long"], "minimum": 1 },
+            "recorded_at": { "bsonType": "date" },
+        },
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use mongodb::bson::oid::ObjectId;
+
+    fn item_id() -> Id<Item> {
+        Id::new(ObjectId::new())
+    }
+
+    fn location(s: &str) -> Name {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn adjustment_by_zero_is_invalid() {
+        let movement = StockMovementSpec::adjustment(item_id(), location("a"), 0, DateTime::now());
+        assert_eq!(
+            movement.err(),
+            Some(InvalidStockChangeError::ZeroAdjustment)
+        );
+    }
+
+    #[test]
+    fn adjustment_records_a_positive_quantity() {
+        let taken = StockMovementSpec::adjustment(item_id(), location("a"), -3, DateTime::now());
+        let taken = taken.unwrap();
+        assert_eq!(*taken.quantity(), 3);
+        assert!(taken.from_location().is_some() && taken.to_location().is_none());
+        let overflowing =
+            StockMovementSpec::adjustment(item_id(), location("a"), i64::MIN, DateTime::now());
+        assert!(overflowing.is_err());
+    }
+
+    #[test]
+    fn transfer_of_no_or_negative_quantity_is_invalid() {
+        for quantity in [0, -1] {
+            let movement = StockMovementSpec::transfer(
+                item_id(),
+                location("a"),
+                location("b"),
+                quantity,
+                DateTime::now(),
+            );
+            assert_eq!(
+                movement.err(),
+                Some(InvalidStockChangeError::NonPositiveTransfer)
+            );
+        }
+    }
+
+    #[test]
+    fn transfer_to_the_same_location_is_invalid() {
+        let movement = StockMovementSpec::transfer(
+            item_id(),
+            location("a"),
+            location("a"),
+            1,
+            DateTime::now(),
+        );
+        assert_eq!(movement.err(), Some(InvalidStockChangeError::SameLocation));
+    }
+}
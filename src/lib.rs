@@ -1,4 +1,10 @@
+// lets code generated by `mongo_repo_derive` refer to this crate as `mongo_repo` within it too
+extern crate self as mongo_repo;
+
 pub mod api;
 pub mod common;
 pub mod domain;
+pub mod metrics;
+pub mod migrations;
 pub mod storage;
+pub mod telemetry;
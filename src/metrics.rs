@@ -0,0 +1,155 @@
+use mongodb::event::cmap::{
+    CmapEventHandler, ConnectionCheckedInEvent, ConnectionCheckedOutEvent,
+    ConnectionCheckoutFailedEvent, ConnectionClosedEvent, ConnectionCreatedEvent,
+};
+use prometheus::{
+    register_histogram_vec, register_int_counter_vec, register_int_gauge_vec, Encoder,
+    HistogramVec, IntCounterVec, IntGaugeVec, TextEncoder,
+};
+use std::sync::LazyLock;
+
+/// The number of HTTP requests handled, by method, route and response status.
+pub static HTTP_REQUESTS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "http_requests_total",
+        "Number of HTTP requests handled",
+        &["method", "route", "status"]
+    )
+    .unwrap()
+});
+
+/// The time taken to handle HTTP requests, by method and route.
+pub static HTTP_REQUEST_DURATION: LazyLock<HistogramVec> = LazyLock::new(|| {
+    register_histogram_vec!(
+        "http_request_duration_seconds",
+        "Time taken to handle HTTP requests",
+        &["method", "route"]
+    )
+    .unwrap()
+});
+
+/// The number of GraphQL operations executed, by operation name and outcome.
+pub static GRAPHQL_OPERATIONS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "graphql_operations_total",
+        "Number of GraphQL operations executed",
+        &["operation", "outcome"]
+    )
+    .unwrap()
+});
+
+/// The time taken to execute GraphQL operations, by operation name.
+pub static GRAPHQL_OPERATION_DURATION: LazyLock<HistogramVec> = LazyLock::new(|| {
+    register_histogram_vec!(
+        "graphql_operation_duration_seconds",
+        "Time taken to execute GraphQL operations",
+        &["operation"]
+    )
+    .unwrap()
+});
+
+/// The time taken by repository operations, by collection and repository method.
+pub static REPO_OPERATION_DURATION: LazyLock<HistogramVec> = LazyLock::new(|| {
+    register_histogram_vec!(
+        "repo_operation_duration_seconds",
+        "Time taken by repository operations",
+        &["collection", "method"]
+    )
+    .unwrap()
+});
+
+/// The number of failed repository operations, by collection and repository method.
+pub static REPO_OPERATION_ERRORS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "repo_operation_errors_total",
+        "Number of failed repository operations",
+        &["collection", "method"]
+    )
+    .unwrap()
+});
+
+/// The number of finished transactions, by outcome (`commit` or `abort`).
+pub static TRANSACTIONS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "transactions_total",
+        "Number of finished transactions",
+        &["outcome"]
+    )
+    .unwrap()
+});
+
+/// The number of open connections in each mongo connection pool, by server address.
+pub static MONGO_POOL_CONNECTIONS: LazyLock<IntGaugeVec> = LazyLock::new(|| {
+    register_int_gauge_vec!(
+        "mongo_pool_connections",
+        "Number of open connections in the mongo connection pool",
+        &["address"]
+    )
+    .unwrap()
+});
+
+/// The number of connections checked out of each mongo connection pool, by server address.
+pub static MONGO_POOL_CONNECTIONS_IN_USE: LazyLock<IntGaugeVec> = LazyLock::new(|| {
+    register_int_gauge_vec!(
+        "mongo_pool_connections_in_use",
+        "Number of connections checked out of the mongo connection pool",
+        &["address"]
+    )
+    .unwrap()
+});
+
+/// The number of failed attempts to check a connection out of each mongo connection pool, by
+/// server address.
+pub static MONGO_POOL_CHECKOUT_FAILURES: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "mongo_pool_checkout_failures_total",
+        "Number of failed attempts to check a connection out of the mongo connection pool",
+        &["address"]
+    )
+    .unwrap()
+});
+
+/// Renders all registered metrics in the Prometheus text exposition format.
+pub fn render() -> (String, Vec<u8>) {
+    let encoder = TextEncoder::new();
+    let mut buffer = vec![];
+    encoder
+        .encode(&prometheus::gather(), &mut buffer)
+        .expect("metrics could not be encoded");
+    (encoder.format_type().to_string(), buffer)
+}
+
+/// A mongo connection pool event handler that keeps the connection pool metrics up to date.
+pub struct PoolMetricsHandler;
+
+impl CmapEventHandler for PoolMetricsHandler {
+    fn handle_connection_created_event(&self, event: ConnectionCreatedEvent) {
+        MONGO_POOL_CONNECTIONS
+            .with_label_values(&[&event.address.to_string()])
+            .inc();
+    }
+
+    fn handle_connection_closed_event(&self, event: ConnectionClosedEvent) {
+        MONGO_POOL_CONNECTIONS
+            .with_label_values(&[&event.address.to_string()])
+            .dec();
+    }
+
+    fn handle_connection_checked_out_event(&self, event: ConnectionCheckedOutEvent) {
+        MONGO_POOL_CONNECTIONS_IN_USE
+            .with_label_values(&[&event.address.to_string()])
+            .inc();
+    }
+
+    fn handle_connection_checked_in_event(&self, event: ConnectionCheckedInEvent) {
+        MONGO_POOL_CONNECTIONS_IN_USE
+            .with_label_values(&[&event.address.to_string()])
+            .dec();
+    }
+
+    fn handle_connection_checkout_failed_event(&self, event: ConnectionCheckoutFailedEvent) {
+        MONGO_POOL_CHECKOUT_FAILURES
+            .with_label_values(&[&event.address.to_string()])
+            .inc();
+    }
+}
@@ -0,0 +1,363 @@
+use super::Migration;
+use crate::storage::namespace::NamespaceResolver;
+use mongodb::{
+    bson::{doc, DateTime, Document},
+    error::{ErrorKind, WriteFailure},
+    options::UpdateOptions,
+    Collection, Database,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    error::Error,
+    fmt::{Display, Formatter},
+    time::Duration,
+};
+use tracing::{info, warn};
+use uuid::Uuid;
+
+/// The collection applied migrations are recorded in.
+pub const MIGRATIONS_COLLECTION: &str = "_migrations";
+
+/// How long a lock is honoured for; a lock older than this is assumed to have been left behind by
+/// a runner that died, and can be taken over.
+pub const LOCK_TIMEOUT: Duration = Duration::from_secs(10 * 60);
+
+/// The ID of the document in the migrations collection that acts as the lock.
+const LOCK_ID: &str = "lock";
+
+/// The server error code indicating a write would have duplicated a unique key.
+const DUPLICATE_KEY_ERROR_CODE: i32 = 11000;
+
+/// The record of an applied migration.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct AppliedMigration {
+    #[serde(rename = "_id")]
+    version: u32,
+    description: String,
+    applied_at: DateTime,
+}
+
+/// The status of a migration within a database.
+#[derive(Debug, Clone)]
+pub struct MigrationStatus {
+    pub version: u32,
+    pub description: String,
+    /// When the migration was applied, or `None` if it is pending.
+    pub applied_at: Option<DateTime>,
+}
+
+/// Applies and reverts migrations to a database, recording which have been applied.
+///
+/// Only one migrator can change a database at a time; others fail with `MigrationError::Locked`
+/// until it is done.
+pub struct Migrator {
+    db: Database,
+    namespace: NamespaceResolver,
+    migrations: Vec<Box<dyn Migration>>,
+}
+
+impl Migrator {
+    /// Creates a migrator.
+    ///
+    /// # Arguments
+    /// * `db` - the database to migrate
+    /// * `namespace` - the namespace to resolve the names of collections with
+    /// * `migrations` - the migrations that can be applied, in any order
+    pub fn new(
+        db: Database,
+        namespace: NamespaceResolver,
+        mut migrations: Vec<Box<dyn Migration>>,
+    ) -> Result<Self, MigrationError> {
+        migrations.sort_by_key(|migration| migration.version());
+        for pair in migrations.windows(2) {
+            if pair[0].version() == pair[1].version() {
+                return Err(MigrationError::DuplicateVersion(pair[0].version()));
+            }
+        }
+        if let Some(migration) = migrations.first().filter(|m| m.version() == 0) {
+            return Err(MigrationError::InvalidVersion(migration.version()));
+        }
+        Ok(Self {
+            db,
+            namespace,
+            migrations,
+        })
+    }
+
+    /// Returns the status of every known migration, and of any applied migration that is no
+    /// longer known, in order of version.
+    pub async fn status(&self) -> Result<Vec<MigrationStatus>, MigrationError> {
+        let mut applied = self.applied().await?;
+        let mut statuses = self
+            .migrations
+            .iter()
+            .map(|migration| MigrationStatus {
+                version: migration.version(),
+                description: migration.description().to_string(),
+                applied_at: applied
+                    .remove(&migration.version())
+                    .map(|applied| applied.applied_at),
+            })
+            .collect::<Vec<_>>();
+        statuses.extend(applied.into_values().map(|applied| MigrationStatus {
+            version: applied.version,
+            description: applied.description,
+            applied_at: Some(applied.applied_at),
+        }));
+        statuses.sort_by_key(|status| status.version);
+        Ok(statuses)
+    }
+
+    /// Applies pending migrations in order.
+    ///
+    /// # Arguments
+    /// * `target` - if provided, the version to migrate up to, inclusive; otherwise all pending
+    ///   migrations are applied
+    ///
+    /// # Returns
+    /// the versions of the migrations that were applied
+    pub async fn up(&self, target: Option<u32>) -> Result<Vec<u32>, MigrationError> {
+        let lock = self.lock().await?;
+        let result = self.up_locked(target).await;
+        lock.release().await;
+        result
+    }
+
+    /// Reverts applied migrations in reverse order.
+    ///
+    /// # Arguments
+    /// * `target` - if provided, the version to migrate down to, which is left applied; otherwise
+    ///   only the latest applied migration is reverted
+    ///
+    /// # Returns
+    /// the versions of the migrations that were reverted
+    pub async fn down(&self, target: Option<u32>) -> Result<Vec<u32>, MigrationError> {
+        let lock = self.lock().await?;
+        let result = self.down_locked(target).await;
+        lock.release().await;
+        result
+    }
+
+    async fn up_locked(&self, target: Option<u32>) -> Result<Vec<u32>, MigrationError> {
+        let applied = self.applied().await?;
+        let known = self.known_versions();
+        let mut migrated = vec![];
+        for version in pending(&known, &applied.keys().copied().collect::<Vec<_>>(), target) {
+            let migration = self.migration(version)?;
+            info!(
+                "applying migration {}: {}",
+                version,
+                migration.description()
+            );
+            migration.up(&self.db, &self.namespace).await?;
+            self.collection::<AppliedMigration>()
+                .insert_one(
+                    AppliedMigration {
+                        version,
+                        description: migration.description().to_string(),
+                        applied_at: DateTime::now(),
+                    },
+                    None,
+                )
+                .await?;
+            migrated.push(version);
+        }
+        Ok(migrated)
+    }
+
+    async fn down_locked(&self, target: Option<u32>) -> Result<Vec<u32>, MigrationError> {
+        let applied = self.applied().await?;
+        let mut migrated = vec![];
+        for version in to_revert(&applied.keys().copied().collect::<Vec<_>>(), target) {
+            let migration = self.migration(version)?;
+            info!(
+                "reverting migration {}: {}",
+                version,
+                migration.description()
+            );
+            migration.down(&self.db, &self.namespace).await?;
+            self.collection::<Document>()
+                .delete_one(doc! { "_id": version }, None)
+                .await?;
+            migrated.push(version);
+        }
+        Ok(migrated)
+    }
+
+    /// Returns the records of applied migrations, keyed by version.
+    async fn applied(&self) -> Result<BTreeMap<u32, AppliedMigration>, MigrationError> {
+        use futures::TryStreamExt;
+
+        let applied = self
+            .collection::<AppliedMigration>()
+            .find(doc! { "_id": { "$type": "number" } }, None)
+            .await?
+            .try_collect::<Vec<_>>()
+            .await?;
+        Ok(applied
+            .into_iter()
+            .map(|applied| (applied.version, applied))
+            .collect())
+    }
+
+    fn known_versions(&self) -> Vec<u32> {
+        self.migrations
+            .iter()
+            .map(|migration| migration.version())
+            .collect()
+    }
+
+    fn migration(&self, version: u32) -> Result<&dyn Migration, MigrationError> {
+        self.migrations
+            .iter()
+            .find(|migration| migration.version() == version)
+            .map(|migration| migration.as_ref())
+            .ok_or(MigrationError::UnknownVersion(version))
+    }
+
+    /// Takes the lock on the database, or takes over a lock that has timed out.
+    async fn lock(&self) -> Result<MigrationLock, MigrationError> {
+        let owner = Uuid::new_v4().to_string();
+        let now = DateTime::now();
+        let stale_before =
+            DateTime::from_millis(now.timestamp_millis() - LOCK_TIMEOUT.as_millis() as i64);
+        // a lock that is held and not stale does not match, so the upsert tries to insert a second
+        // document with the lock's ID and fails
+        let result = self
+            .collection::<Document>()
+            .update_one(
+                doc! { "_id": LOCK_ID, "acquired_at": { "$lt": stale_before } },
+                doc! { "$set": { "owner": &owner, "acquired_at": now } },
+                UpdateOptions::builder().upsert(true).build(),
+            )
+            .await;
+        match result {
+            Ok(_) => Ok(MigrationLock {
+                collection: self.collection(),
+                owner,
+            }),
+            Err(e) if is_duplicate_key_error(&e) => Err(MigrationError::Locked),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn collection<T>(&self) -> Collection<T> {
+        self.db
+            .collection(&self.namespace.collection_name(MIGRATIONS_COLLECTION))
+    }
+}
+
+/// A held lock on a database's migrations.
+struct MigrationLock {
+    collection: Collection<Document>,
+    owner: String,
+}
+
+impl MigrationLock {
+    async fn release(self) {
+        let result = self
+            .collection
+            .delete_one(doc! { "_id": LOCK_ID, "owner": &self.owner }, None)
+            .await;
+        if let Err(e) = result {
+            warn!("error releasing migration lock: {}", e);
+        }
+    }
+}
+
+fn is_duplicate_key_error(e: &mongodb::error::Error) -> bool {
+    match e.kind.as_ref() {
+        ErrorKind::Write(WriteFailure::WriteError(write_error)) => {
+            write_error.code == DUPLICATE_KEY_ERROR_CODE
+        }
+        ErrorKind::Command(command_error) => command_error.code == DUPLICATE_KEY_ERROR_CODE,
+        _ => false,
+    }
+}
+
+/// Returns the versions that should be applied, in order, to migrate up to the target version.
+fn pending(known: &[u32], applied: &[u32], target: Option<u32>) -> Vec<u32> {
+    let mut pending = known
+        .iter()
+        .copied()
+        .filter(|version| !applied.contains(version))
+        .filter(|version| target.is_none_or(|target| *version <= target))
+        .collect::<Vec<_>>();
+    pending.sort_unstable();
+    pending
+}
+
+/// Returns the versions that should be reverted, in order, to migrate down to the target version.
+fn to_revert(applied: &[u32], target: Option<u32>) -> Vec<u32> {
+    let mut to_revert = applied.to_vec();
+    to_revert.sort_unstable_by(|a, b| b.cmp(a));
+    match target {
+        Some(target) => to_revert.retain(|version| *version > target),
+        None => to_revert.truncate(1),
+    }
+    to_revert
+}
+
+/// An error that occurred while migrating a database.
+#[derive(Debug)]
+pub enum MigrationError {
+    MongoError(mongodb::error::Error),
+    /// Another migrator holds the lock on the database.
+    Locked,
+    /// Two migrations have the same version.
+    DuplicateVersion(u32),
+    /// A migration has a version that is not allowed.
+    InvalidVersion(u32),
+    /// A migration that has been applied is not known, so cannot be reverted.
+    UnknownVersion(u32),
+    /// Migrations with these versions have not been applied, and were not allowed to be.
+    Pending(Vec<u32>),
+}
+
+impl Error for MigrationError {}
+
+impl Display for MigrationError {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        match self {
+            Self::MongoError(e) => write!(f, "MongoError({})", e),
+            Self::Locked => write!(f, "migrations are locked by another runner"),
+            Self::DuplicateVersion(version) => {
+                write!(f, "more than one migration has version {version}")
+            }
+            Self::InvalidVersion(version) => write!(f, "invalid migration version {version}"),
+            Self::UnknownVersion(version) => write!(f, "unknown migration version {version}"),
+            Self::Pending(versions) => write!(f, "migrations {versions:?} are pending"),
+        }
+    }
+}
+
+impl From<mongodb::error::Error> for MigrationError {
+    fn from(e: mongodb::error::Error) -> Self {
+        MigrationError::MongoError(e)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn pending_migrations_are_applied_in_order() {
+        assert_eq!(pending(&[1, 2, 3, 4], &[2], None), vec![1, 3, 4]);
+        assert_eq!(pending(&[1, 2, 3, 4], &[1], Some(3)), vec![2, 3]);
+        assert_eq!(pending(&[1, 2], &[1, 2], None), Vec::<u32>::new());
+    }
+
+    #[test]
+    fn latest_migration_is_reverted_by_default() {
+        assert_eq!(to_revert(&[1, 3, 2], None), vec![3]);
+        assert_eq!(to_revert(&[], None), Vec::<u32>::new());
+    }
+
+    #[test]
+    fn migrations_are_reverted_down_to_target() {
+        assert_eq!(to_revert(&[1, 2, 3, 4], Some(2)), vec![4, 3]);
+        assert_eq!(to_revert(&[1, 2], Some(0)), vec![2, 1]);
+    }
+}
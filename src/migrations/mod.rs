@@ -0,0 +1,243 @@
+mod migrator;
+mod tenants;
+mod v2_expire_idempotency_keys;
+mod v8_index_item_locations;
+
+pub use migrator::*;
+pub use tenants::*;
+
+use crate::{
+    domain::models::{
+        items::Item,
+        stock::{StockLevel, StockMovement},
+    },
+    storage::{mongo_repo::MongoReposable, namespace::NamespaceResolver},
+};
+use async_trait::async_trait;
+use mongodb::{
+    bson::{doc, Document},
+    error::ErrorKind,
+    options::IndexOptions,
+    Database, IndexModel,
+};
+
+/// The server error code indicating an index to drop does not exist.
+const INDEX_NOT_FOUND_ERROR_CODE: i32 = 27;
+
+/// A versioned change to the schema or data of a database, that can be applied and reverted.
+///
+/// Migrations are applied in order of version, and reverted in reverse order. A migration is not
+/// run within a transaction, so `up` and `down` should leave the database in a sensible state if
+/// they fail part way through, and be safe to run again.
+#[async_trait]
+pub trait Migration: Send + Sync {
+    /// The version of the migration; versions must be unique and greater than zero.
+    fn version(&self) -> u32;
+
+    /// A short, human readable description of the migration.
+    fn description(&self) -> &'static str;
+
+    /// Applies the migration.
+    ///
+    /// # Arguments
+    /// * `db` - the database to migrate
+    /// * `namespace` - the namespace to resolve the names of collections with
+    async fn up(
+        &self,
+        db: &Database,
+        namespace: &NamespaceResolver,
+    ) -> Result<(), mongodb::error::Error>;
+
+    /// Reverts the migration.
+    ///
+    /// # Arguments
+    /// * `db` - the database to migrate
+    /// * `namespace` - the namespace to resolve the names of collections with
+    async fn down(
+        &self,
+        db: &Database,
+        namespace: &NamespaceResolver,
+    ) -> Result<(), mongodb::error::Error>;
+}
+
+/// A migration that creates an index on a collection, and drops it when reverted.
+pub struct IndexMigration {
+    pub version: u32,
+    pub description: &'static str,
+    /// The default name of the collection to index.
+    pub collection: &'static str,
+    /// Builds the index to create, which must be named so that it can be dropped by name.
+    pub model: fn() -> IndexModel,
+}
+
+impl IndexMigration {
+    fn index_name(&self) -> String {
+        (self.model)()
+            .options
+            .and_then(|options| options.name)
+            .expect("indexes created by migrations are named")
+    }
+
+    fn collection(
+        &self,
+        db: &Database,
+        namespace: &NamespaceResolver,
+    ) -> mongodb::Collection<Document> {
+        db.collection(&namespace.collection_name(self.collection))
+    }
+}
+
+#[async_trait]
+impl Migration for IndexMigration {
+    fn version(&self) -> u32 {
+        self.version
+    }
+
+    fn description(&self) -> &'static str {
+        self.description
+    }
+
+    async fn up(
+        &self,
+        db: &Database,
+        namespace: &NamespaceResolver,
+    ) -> Result<(), mongodb::error::Error> {
+        self.collection(db, namespace)
+            .create_index((self.model)(), None)
+            .await?;
+        Ok(())
+    }
+
+    async fn down(
+        &self,
+        db: &Database,
+        namespace: &NamespaceResolver,
+    ) -> Result<(), mongodb::error::Error> {
+        let result = self
+            .collection(db, namespace)
+            .drop_index(self.index_name(), None)
+            .await;
+        match result {
+            Err(e) if is_index_not_found_error(&e) => Ok(()),
+            result => result,
+        }
+    }
+}
+
+/// Returns every migration, in order of version.
+pub fn all() -> Vec<Box<dyn Migration>> {
+    let mut migrations: Vec<Box<dyn Migration>> = vec![
+        Box::new(v2_expire_idempotency_keys::ExpireIdempotencyKeys),
+        Box::new(v8_index_item_locations::IndexItemLocations),
+    ];
+    migrations.extend(
+        index_migrations()
+            .into_iter()
+            .map(|migration| Box::new(migration) as Box<dyn Migration>),
+    );
+    migrations.sort_by_key(|migration| migration.version());
+    migrations
+}
+
+/// Returns the migrations that only create an index.
+fn index_migrations() -> Vec<IndexMigration> {
+    vec![
+        // so items can be found by name without scanning the whole collection
+        IndexMigration {
+            version: 1,
+            description: "index items by name",
+            collection: Item::collection_name(),
+            model: || index("name_1", doc! { "name": 1 }, IndexOptions::default()),
+        },
+        // so an owner's items can be found without scanning the whole collection; items without
+        // an owner are left out of the index
+        IndexMigration {
+            version: 3,
+            description: "index items by owner",
+            collection: Item::collection_name(),
+            model: || {
+                let options = IndexOptions::builder().sparse(true).build();
+                index("owner_id_1", doc! { "owner_id": 1 }, options)
+            },
+        },
+        // so items can be found by tag without scanning the whole collection; items without tags
+        // are left out of the index
+        IndexMigration {
+            version: 4,
+            description: "index items by tag",
+            collection: Item::collection_name(),
+            model: || {
+                let options = IndexOptions::builder().sparse(true).build();
+                index("tags_1", doc! { "tags": 1 }, options)
+            },
+        },
+        // so an item's stock can be found without scanning the whole collection; the index is
+        // unique, so an item has at most one stock level per location
+        IndexMigration {
+            version: 5,
+            description: "index stock levels by item and location",
+            collection: StockLevel::collection_name(),
+            model: || {
+                let options = IndexOptions::builder().unique(true).build();
+                let keys = doc! { "item_id": 1, "location": 1 };
+                index("item_id_1_location_1", keys, options)
+            },
+        },
+        // so an item's movements can be found without scanning the whole ledger
+        IndexMigration {
+            version: 6,
+            description: "index stock movements by item",
+            collection: StockMovement::collection_name(),
+            model: || index("item_id_1", doc! { "item_id": 1 }, IndexOptions::default()),
+        },
+        // a wildcard index, so items can be filtered by any attribute without scanning the whole
+        // collection
+        IndexMigration {
+            version: 7,
+            description: "index items by custom attribute",
+            collection: Item::collection_name(),
+            model: || {
+                let keys = doc! { "attributes.$**": 1 };
+                index("attributes.$**_1", keys, IndexOptions::default())
+            },
+        },
+    ]
+}
+
+/// Builds an index with the provided name.
+fn index(name: &str, keys: Document, mut options: IndexOptions) -> IndexModel {
+    options.name = Some(name.to_string());
+    IndexModel::builder().keys(keys).options(options).build()
+}
+
+/// Returns whether an error indicates an index to drop does not exist, so that dropping it can
+/// be treated as already done.
+fn is_index_not_found_error(e: &mongodb::error::Error) -> bool {
+    matches!(
+        e.kind.as_ref(),
+        ErrorKind::Command(command_error) if command_error.code == INDEX_NOT_FOUND_ERROR_CODE
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn migrations_are_numbered_consecutively() {
+        let versions = all()
+            .iter()
+            .map(|migration| migration.version())
+            .collect::<Vec<_>>();
+        assert_eq!(versions, (1..=versions.len() as u32).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn index_migrations_name_their_indexes() {
+        // the name an index is dropped by on reverting is read from its model
+        for migration in index_migrations() {
+            assert!(!migration.index_name().is_empty());
+        }
+        assert_eq!(index_migrations()[3].index_name(), "item_id_1_location_1");
+    }
+}
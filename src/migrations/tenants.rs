@@ -0,0 +1,92 @@
+use super::{MigrationError, Migrator};
+use crate::{
+    common::tenant::TenantId,
+    domain::models::items::Item,
+    storage::{mongo_repo::MongoReposable, namespace::NamespaceResolver},
+};
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+use tokio::sync::OnceCell;
+use tracing::info;
+
+/// Makes sure the database of each tenant is migrated to the latest version before the tenant is
+/// served, including tenants whose databases are created after startup.
+///
+/// Each tenant's database is checked the first time the tenant is served, and not again unless
+/// checking fails.
+#[derive(Clone)]
+pub struct TenantMigrator {
+    mongo_client: mongodb::Client,
+    namespace: NamespaceResolver,
+    apply: bool,
+    migrated: Arc<Mutex<HashMap<TenantId, Arc<OnceCell<()>>>>>,
+}
+
+impl TenantMigrator {
+    /// Creates a tenant migrator.
+    ///
+    /// # Arguments
+    /// * `mongo_client` - the client to reach tenants' databases with
+    /// * `namespace` - the namespace shared by all tenants, which each tenant's is derived from
+    /// * `apply` - whether to apply pending migrations; otherwise a tenant with pending migrations
+    ///   is refused
+    pub fn new(mongo_client: mongodb::Client, namespace: NamespaceResolver, apply: bool) -> Self {
+        Self {
+            mongo_client,
+            namespace,
+            apply,
+            migrated: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Makes sure the database of a tenant is at the latest version, applying pending migrations
+    /// if allowed; concurrent calls for the same tenant wait for the first to finish.
+    ///
+    /// # Arguments
+    /// * `tenant_id` - the tenant whose database to check
+    pub async fn ensure_migrated(&self, tenant_id: &TenantId) -> Result<(), MigrationError> {
+        let cell = self
+            .migrated
+            .lock()
+            .unwrap()
+            .entry(tenant_id.clone())
+            .or_default()
+            .clone();
+        cell.get_or_try_init(|| self.migrate(tenant_id))
+            .await
+            .map(|_| ())
+    }
+
+    async fn migrate(&self, tenant_id: &TenantId) -> Result<(), MigrationError> {
+        let namespace = self.namespace.for_tenant(tenant_id.clone());
+        let db = self
+            .mongo_client
+            .database(&namespace.db_name(Item::db_name()));
+        let migrator = Migrator::new(db, namespace, super::all())?;
+        if self.apply {
+            let applied = migrator.up(None).await?;
+            if !applied.is_empty() {
+                info!(
+                    "applied {} migration(s) for tenant {}",
+                    applied.len(),
+                    tenant_id
+                );
+            }
+            return Ok(());
+        }
+        let pending = migrator
+            .status()
+            .await?
+            .into_iter()
+            .filter(|status| status.applied_at.is_none())
+            .map(|status| status.version)
+            .collect::<Vec<_>>();
+        if pending.is_empty() {
+            Ok(())
+        } else {
+            Err(MigrationError::Pending(pending))
+        }
+    }
+}
@@ -0,0 +1,60 @@
+use super::{is_index_not_found_error, Migration};
+use crate::storage::{idempotency::IDEMPOTENCY_KEYS_COLLECTION, namespace::NamespaceResolver};
+use async_trait::async_trait;
+use mongodb::{
+    bson::{doc, Document},
+    options::IndexOptions,
+    Database, IndexModel,
+};
+use std::time::Duration;
+
+const INDEX_NAME: &str = "expires_at_1";
+
+/// Removes recorded responses to idempotent mutations once they expire.
+pub struct ExpireIdempotencyKeys;
+
+#[async_trait]
+impl Migration for ExpireIdempotencyKeys {
+    fn version(&self) -> u32 {
+        2
+    }
+
+    fn description(&self) -> &'static str {
+        "expire idempotency keys"
+    }
+
+    async fn up(
+        &self,
+        db: &Database,
+        namespace: &NamespaceResolver,
+    ) -> Result<(), mongodb::error::Error> {
+        let index = IndexModel::builder()
+            .keys(doc! { "expires_at": 1 })
+            .options(
+                IndexOptions::builder()
+                    .name(INDEX_NAME.to_string())
+                    .expire_after(Duration::ZERO)
+                    .build(),
+            )
+            .build();
+        collection(db, namespace).create_index(index, None).await?;
+        Ok(())
+    }
+
+    async fn down(
+        &self,
+        db: &Database,
+        namespace: &NamespaceResolver,
+    ) -> Result<(), mongodb::error::Error> {
+        // the recorded responses are kept; they are only no longer removed once they expire
+        let result = collection(db, namespace).drop_index(INDEX_NAME, None).await;
+        match result {
+            Err(e) if is_index_not_found_error(&e) => Ok(()),
+            result => result,
+        }
+    }
+}
+
+fn collection(db: &Database, namespace: &NamespaceResolver) -> mongodb::Collection<Document> {
+    db.collection(&namespace.collection_name(IDEMPOTENCY_KEYS_COLLECTION))
+}
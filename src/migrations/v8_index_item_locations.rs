@@ -0,0 +1,50 @@
+use super::{is_index_not_found_error, Migration};
+use crate::{
+    domain::models::items::Item,
+    storage::mongo_repo::{geo_index_name, geo_indexes, MongoReposable},
+    storage::namespace::NamespaceResolver,
+};
+use async_trait::async_trait;
+use mongodb::{bson::Document, Database};
+
+/// Indexes items by their location with a `2dsphere` index, which finding items near a point
+/// requires.
+pub struct IndexItemLocations;
+
+#[async_trait]
+impl Migration for IndexItemLocations {
+    fn version(&self) -> u32 {
+        8
+    }
+
+    fn description(&self) -> &'static str {
+        "index items by location"
+    }
+
+    async fn up(
+        &self,
+        db: &Database,
+        namespace: &NamespaceResolver,
+    ) -> Result<(), mongodb::error::Error> {
+        db.collection::<Document>(&namespace.collection_name(Item::collection_name()))
+            .create_indexes(geo_indexes(Item::geo_fields()), None)
+            .await?;
+        Ok(())
+    }
+
+    async fn down(
+        &self,
+        db: &Database,
+        namespace: &NamespaceResolver,
+    ) -> Result<(), mongodb::error::Error> {
+        let collection =
+            db.collection::<Document>(&namespace.collection_name(Item::collection_name()));
+        for field in Item::geo_fields() {
+            match collection.drop_index(geo_index_name(field), None).await {
+                Err(e) if is_index_not_found_error(&e) => {}
+                result => result?,
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,529 @@
+use crate::common::{entity::Entity, id::Id};
+use crate::storage::repo::{Facet, GeoQuery, Near, Patch, Repo, Reposable, ReturnDocument};
+use async_trait::async_trait;
+use futures::{Stream, StreamExt};
+use lru::LruCache;
+use serde::de::DeserializeOwned;
+use std::any::{Any, TypeId};
+use std::collections::{HashMap, HashSet};
+use std::fmt::Display;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tracing::warn;
+
+pub const DEFAULT_CAPACITY: usize = 0;
+pub const DEFAULT_TTL: Duration = Duration::from_secs(60);
+
+/// A bounded, in-process cache of entities, shared by every `CachedRepo` created from it.
+///
+/// Entries are evicted in least-recently-used order once the cache is full, and are ignored once
+/// they are older than the cache's time-to-live. Each handle to the cache has a scope (e.g. the
+/// tenant it serves); an entry is only ever returned to a handle with the scope it was stored
+/// under.
+pub struct RepoCache<R: Entity> {
+    entries: Arc<Mutex<LruCache<Id<R>, CacheEntry<R>>>>,
+    ttl: Duration,
+    scope: Arc<str>,
+}
+
+struct CacheEntry<R> {
+    scope: Arc<str>,
+    stored_at: Instant,
+    value: R,
+}
+
+impl<R: Entity + Clone> RepoCache<R> {
+    /// Creates a new cache.
+    ///
+    /// # Arguments
+    /// * `capacity` - the maximum number of entities to hold; a capacity of `0` disables caching
+    /// * `ttl` - how long a cached entity may be served for after it was stored
+    pub fn new(capacity: usize, ttl: Duration) -> Self {
+        Self {
+            entries: Arc::new(Mutex::new(LruCache::new(capacity))),
+            ttl,
+            scope: Arc::from(""),
+        }
+    }
+
+    /// Returns a handle to this cache that stores and serves entries under the provided scope.
+    pub fn scoped(&self, scope: &str) -> Self {
+        Self {
+            entries: Arc::clone(&self.entries),
+            ttl: self.ttl,
+            scope: Arc::from(scope),
+        }
+    }
+
+    /// Returns the cached entity with the provided ID, if it is present, fresh and in scope.
+    pub fn get(&self, id: &Id<R>) -> Option<R> {
+        let mut entries = self.entries.lock().unwrap();
+        match entries.get(id) {
+            Some(entry) if entry.scope != self.scope => None,
+            Some(entry) if entry.stored_at.elapsed() < self.ttl => Some(entry.value.clone()),
+            Some(_) => {
+                entries.pop(id);
+                None
+            }
+            None => None,
+        }
+    }
+
+    /// Stores an entity in the cache, replacing any entry with the same ID.
+    pub fn put(&self, id: Id<R>, value: R) {
+        let entry = CacheEntry {
+            scope: Arc::clone(&self.scope),
+            stored_at: Instant::now(),
+            value,
+        };
+        self.entries.lock().unwrap().put(id, entry);
+    }
+
+    /// Removes the entity with the provided ID from the cache, regardless of scope.
+    pub fn invalidate(&self, id: &Id<R>) {
+        self.entries.lock().unwrap().pop(id);
+    }
+
+    /// Removes all entities from the cache, regardless of scope.
+    pub fn clear(&self) {
+        self.entries.lock().unwrap().clear();
+    }
+
+    /// Invalidates entities as their IDs arrive on the provided stream, e.g. a stream of changes
+    /// made by other processes; an error on the stream means changes may have been missed, so the
+    /// whole cache is cleared, as it is once the stream ends.
+    pub async fn invalidate_from<E: Display>(&self, ids: impl Stream<Item = Result<Id<R>, E>>) {
+        futures::pin_mut!(ids);
+        while let Some(id) = ids.next().await {
+            match id {
+                Ok(id) => self.invalidate(&id),
+                Err(e) => {
+                    warn!("error watching for changes, clearing the cache: {}", e);
+                    self.clear();
+                }
+            }
+        }
+        self.clear();
+    }
+}
+
+impl<R: Entity> Clone for RepoCache<R> {
+    fn clone(&self) -> Self {
+        Self {
+            entries: Arc::clone(&self.entries),
+            ttl: self.ttl,
+            scope: Arc::clone(&self.scope),
+        }
+    }
+}
+
+/// A set of caches, at most one per type of entity, whose handles share a scope.
+#[derive(Clone, Default)]
+pub struct RepoCaches {
+    caches: HashMap<TypeId, Arc<dyn AnyRepoCache>>,
+}
+
+impl RepoCaches {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns this set with the provided cache as the cache of its type of entity, replacing any
+    /// other.
+    pub fn with<R>(mut self, cache: RepoCache<R>) -> Self
+    where
+        R: Entity + Clone + Send + 'static,
+    {
+        self.caches.insert(TypeId::of::<R>(), Arc::new(cache));
+        self
+    }
+
+    /// Returns the cache of a type of entity, if there is one.
+    pub fn get<R>(&self) -> Option<RepoCache<R>>
+    where
+        R: Entity + Clone + Send + 'static,
+    {
+        self.caches
+            .get(&TypeId::of::<R>())
+            .and_then(|cache| cache.as_any().downcast_ref::<RepoCache<R>>())
+            .cloned()
+    }
+
+    /// Returns handles to every cache in this set under the provided scope; see
+    /// `RepoCache::scoped`.
+    pub fn scoped(&self, scope: &str) -> Self {
+        Self {
+            caches: self
+                .caches
+                .iter()
+                .map(|(type_id, cache)| (*type_id, cache.scoped(scope)))
+                .collect(),
+        }
+    }
+}
+
+/// A `RepoCache` of any type of entity.
+trait AnyRepoCache: Send + Sync {
+    fn scoped(&self, scope: &str) -> Arc<dyn AnyRepoCache>;
+    fn as_any(&self) -> &dyn Any;
+}
+
+impl<R> AnyRepoCache for RepoCache<R>
+where
+    R: Entity + Clone + Send + 'static,
+{
+    fn scoped(&self, scope: &str) -> Arc<dyn AnyRepoCache> {
+        Arc::new(RepoCache::scoped(self, scope))
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+/// The IDs of the entities written through an invalidating `CachedRepo`.
+type TouchedIds<R> = Arc<Mutex<HashSet<Id<R>>>>;
+
+/// A repository decorator that serves `retrieve` from a `RepoCache`, falling back to the inner
+/// repository on a miss; updates and deletions made through it invalidate the affected entities.
+pub struct CachedRepo<R, Inner: Repo<R>>
+where
+    R: Reposable,
+{
+    inner: Inner,
+    cache: RepoCache<R>,
+    touched: Option<TouchedIds<R>>,
+}
+
+impl<R, Inner: Repo<R>> CachedRepo<R, Inner>
+where
+    R: Reposable + Clone,
+{
+    /// Creates a repository that reads through, and invalidates, the provided cache.
+    pub fn new(inner: Inner, cache: RepoCache<R>) -> Self {
+        Self {
+            inner,
+            cache,
+            touched: None,
+        }
+    }
+
+    /// Creates a repository for use within a transaction: reads bypass the cache, since they may
+    /// observe uncommitted changes, while writes still invalidate it. Because other readers may
+    /// re-cache an entity before the transaction commits, `invalidate_touched` should be called
+    /// once it has.
+    pub fn new_invalidating(inner: Inner, cache: RepoCache<R>) -> Self {
+        Self {
+            inner,
+            cache,
+            touched: Some(Arc::new(Mutex::new(HashSet::new()))),
+        }
+    }
+
+    /// Invalidates every entity written through this repository (or its clones) again.
+    pub fn invalidate_touched(&self) {
+        if let Some(ref touched) = self.touched {
+            for id in touched.lock().unwrap().drain() {
+                self.cache.invalidate(&id);
+            }
+        }
+    }
+
+    fn invalidate(&self, id: &Id<R>) {
+        self.cache.invalidate(id);
+        if let Some(ref touched) = self.touched {
+            touched.lock().unwrap().insert(id.clone());
+        }
+    }
+}
+
+#[async_trait]
+impl<R, Inner: Repo<R>> Repo<R> for CachedRepo<R, Inner>
+where
+    R: Reposable + Clone + Send + Sync,
+    R::Spec: Sync,
+    R::Patch: Sync,
+    R::Filter: Sync,
+    Inner: Send + Sync,
+{
+    type RepoError = Inner::RepoError;
+
+    async fn create(&self, spec: &R::Spec) -> Result<Id<R>, Self::RepoError> {
+        self.inner.create(spec).await
+    }
+
+    async fn create_and_get(&self, spec: &R::Spec) -> Result<R, Self::RepoError> {
+        self.inner.create_and_get(spec).await
+    }
+
+    async fn update(&self, patch: &R::Patch) -> Result<bool, Self::RepoError> {
+        let result = self.inner.update(patch).await;
+        self.invalidate(patch.id());
+        result
+    }
+
+    async fn delete(&self, id: &Id<R>) -> Result<bool, Self::RepoError> {
+        let result = self.inner.delete(id).await;
+        self.invalidate(id);
+        result
+    }
+
+    async fn update_and_get(
+        &self,
+        patch: &R::Patch,
+        return_document: ReturnDocument,
+    ) -> Result<Option<R>, Self::RepoError> {
+        let result = self.inner.update_and_get(patch, return_document).await;
+        self.invalidate(patch.id());
+        result
+    }
+
+    async fn update_and_get_if(
+        &self,
+        patch: &R::Patch,
+        condition: &R::Filter,
+        return_document: ReturnDocument,
+    ) -> Result<Option<R>, Self::RepoError> {
+        let result = self
+            .inner
+            .update_and_get_if(patch, condition, return_document)
+            .await;
+        self.invalidate(patch.id());
+        result
+    }
+
+    async fn delete_and_get(&self, id: &Id<R>) -> Result<Option<R>, Self::RepoError> {
+        let result = self.inner.delete_and_get(id).await;
+        self.invalidate(id);
+        result
+    }
+
+    async fn retrieve(&self, id: &Id<R>) -> Result<Option<R>, Self::RepoError> {
+        if self.touched.is_some() {
+            return self.inner.retrieve(id).await;
+        }
+        if let Some(entity) = self.cache.get(id) {
+            return Ok(Some(entity));
+        }
+        let entity = self.inner.retrieve(id).await?;
+        if let Some(ref entity) = entity {
+            self.cache.put(id.clone(), entity.clone());
+        }
+        Ok(entity)
+    }
+
+    async fn retrieve_many(&self, ids: &[Id<R>]) -> Result<Vec<R>, Self::RepoError> {
+        if self.touched.is_some() {
+            return self.inner.retrieve_many(ids).await;
+        }
+        let mut entities = vec![];
+        let mut missed = vec![];
+        for id in ids {
+            match self.cache.get(id) {
+                Some(entity) => entities.push(entity),
+                None => missed.push(id.clone()),
+            }
+        }
+        if !missed.is_empty() {
+            for entity in self.inner.retrieve_many(&missed).await? {
+                self.cache.put(entity.id().clone(), entity.clone());
+                entities.push(entity);
+            }
+        }
+        Ok(entities)
+    }
+
+    async fn retrieve_all(&self) -> Result<Vec<R>, Self::RepoError> {
+        self.inner.retrieve_all().await
+    }
+
+    async fn retrieve_page(&self, offset: usize, limit: usize) -> Result<Vec<R>, Self::RepoError> {
+        self.inner.retrieve_page(offset, limit).await
+    }
+
+    async fn find_all(&self, filter: &R::Filter) -> Result<Vec<R>, Self::RepoError> {
+        self.inner.find_all(filter).await
+    }
+
+    async fn find_page(
+        &self,
+        filter: &R::Filter,
+        offset: usize,
+        limit: usize,
+    ) -> Result<Vec<R>, Self::RepoError> {
+        self.inner.find_page(filter, offset, limit).await
+    }
+
+    async fn facet<V>(
+        &self,
+        field: &str,
+        filter: &R::Filter,
+    ) -> Result<Vec<Facet<V>>, Self::RepoError>
+    where
+        V: DeserializeOwned + Send,
+    {
+        self.inner.facet(field, filter).await
+    }
+
+    async fn find_near(
+        &self,
+        field: &str,
+        query: &GeoQuery,
+        filter: &R::Filter,
+        limit: usize,
+    ) -> Result<Vec<Near<R>>, Self::RepoError> {
+        self.inner.find_near(field, query, filter, limit).await
+    }
+}
+
+impl<R, Inner: Repo<R>> Clone for CachedRepo<R, Inner>
+where
+    R: Reposable,
+    Inner: Clone,
+{
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+            cache: self.cache.clone(),
+            touched: self.touched.clone(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use mongodb::bson::oid::ObjectId;
+
+    #[derive(Clone, Debug, PartialEq)]
+    struct Thing {
+        id: Id<Thing>,
+        value: &'static str,
+    }
+
+    impl Entity for Thing {
+        type Key = ObjectId;
+
+        fn id(&self) -> &Id<Thing> {
+            &self.id
+        }
+    }
+
+    fn new_id() -> Id<Thing> {
+        ObjectId::new().into()
+    }
+
+    fn thing(id: &Id<Thing>, value: &'static str) -> Thing {
+        Thing {
+            id: id.clone(),
+            value,
+        }
+    }
+
+    #[test]
+    fn cached_value_is_returned() {
+        let cache = RepoCache::new(10, Duration::from_secs(60));
+        let id = new_id();
+        cache.put(id.clone(), thing(&id, "value"));
+        assert_eq!(cache.get(&id), Some(thing(&id, "value")));
+    }
+
+    #[test]
+    fn invalidated_value_is_not_returned() {
+        let cache = RepoCache::new(10, Duration::from_secs(60));
+        let id = new_id();
+        cache.put(id.clone(), thing(&id, "value"));
+        cache.invalidate(&id);
+        assert_eq!(cache.get(&id), None);
+    }
+
+    #[test]
+    fn cache_is_cleared_when_watching_for_changes_is_interrupted() {
+        let cache = RepoCache::new(10, Duration::from_secs(60));
+        let (id1, id2) = (new_id(), new_id());
+        cache.put(id1.clone(), thing(&id1, "one"));
+        cache.put(id2.clone(), thing(&id2, "two"));
+        let changes = futures::stream::iter([Ok(id1.clone()), Err("interrupted")])
+            .chain(futures::stream::pending());
+
+        futures::executor::block_on(async {
+            let invalidating = cache.invalidate_from(changes);
+            futures::pin_mut!(invalidating);
+            assert!(futures::poll!(invalidating).is_pending());
+        });
+
+        assert_eq!(cache.get(&id1), None);
+        assert_eq!(cache.get(&id2), None);
+    }
+
+    #[test]
+    fn expired_value_is_not_returned() {
+        let cache = RepoCache::new(10, Duration::ZERO);
+        let id = new_id();
+        cache.put(id.clone(), thing(&id, "value"));
+        assert_eq!(cache.get(&id), None);
+    }
+
+    #[test]
+    fn least_recently_used_value_is_evicted() {
+        let cache = RepoCache::new(2, Duration::from_secs(60));
+        let (id1, id2, id3) = (new_id(), new_id(), new_id());
+        cache.put(id1.clone(), thing(&id1, "one"));
+        cache.put(id2.clone(), thing(&id2, "two"));
+        cache.get(&id1);
+        cache.put(id3.clone(), thing(&id3, "three"));
+
+        assert_eq!(cache.get(&id1), Some(thing(&id1, "one")));
+        assert_eq!(cache.get(&id2), None);
+        assert_eq!(cache.get(&id3), Some(thing(&id3, "three")));
+    }
+
+    #[test]
+    fn value_is_not_returned_outside_its_scope() {
+        let cache = RepoCache::new(10, Duration::from_secs(60));
+        let acme = cache.scoped("acme");
+        let globex = cache.scoped("globex");
+        let id = new_id();
+        acme.put(id.clone(), thing(&id, "value"));
+
+        assert_eq!(acme.get(&id), Some(thing(&id, "value")));
+        assert_eq!(globex.get(&id), None);
+    }
+
+    #[test]
+    fn zero_capacity_cache_stores_nothing() {
+        let cache = RepoCache::new(0, Duration::from_secs(60));
+        let id = new_id();
+        cache.put(id.clone(), thing(&id, "value"));
+        assert_eq!(cache.get(&id), None);
+    }
+
+    #[test]
+    fn caches_are_found_by_entity_type() {
+        let cache = RepoCache::new(10, Duration::from_secs(60));
+        let caches = RepoCaches::new().with(cache.clone());
+        let id = new_id();
+        caches
+            .get::<Thing>()
+            .unwrap()
+            .put(id.clone(), thing(&id, "value"));
+        assert_eq!(cache.get(&id), Some(thing(&id, "value")));
+        assert!(RepoCaches::new().get::<Thing>().is_none());
+    }
+
+    #[test]
+    fn scoped_caches_share_entries_within_scope() {
+        let caches = RepoCaches::new().with(RepoCache::<Thing>::new(10, Duration::from_secs(60)));
+        let (tenant1, tenant2) = (caches.scoped("tenant1"), caches.scoped("tenant2"));
+        let id = new_id();
+        tenant1
+            .get::<Thing>()
+            .unwrap()
+            .put(id.clone(), thing(&id, "value"));
+        assert_eq!(
+            caches.scoped("tenant1").get::<Thing>().unwrap().get(&id),
+            Some(thing(&id, "value"))
+        );
+        assert_eq!(tenant2.get::<Thing>().unwrap().get(&id), None);
+    }
+}
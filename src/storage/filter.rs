@@ -0,0 +1,253 @@
+use mongodb::bson::{Bson, Document};
+use serde::{ser::Error, Serialize, Serializer};
+use std::collections::BTreeMap;
+
+/// The key a map filter or patch is serialized under, so that its entries are applied to the
+/// fields of the subdocument they are in rather than to the subdocument as a whole; see
+/// `flatten_fields`.
+pub(crate) const FIELDS: &str = "$fields";
+
+/// A condition on the elements of an array field of an entity. At least one of its lists of
+/// elements must be non-empty.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct ArrayFilter<T> {
+    /// The array must contain at least one of these elements.
+    pub any: Vec<T>,
+    /// The array must contain every one of these elements.
+    pub all: Vec<T>,
+}
+
+impl<T> ArrayFilter<T> {
+    /// Returns a filter matching arrays that contain at least one of the elements.
+    pub fn any(any: Vec<T>) -> Self {
+        Self { any, all: vec![] }
+    }
+
+    /// Returns a filter matching arrays that contain every one of the elements.
+    pub fn all(all: Vec<T>) -> Self {
+        Self { any: vec![], all }
+    }
+
+    /// Returns whether this filter has no elements to match, and so cannot be serialized.
+    pub fn is_empty(&self) -> bool {
+        self.any.is_empty() && self.all.is_empty()
+    }
+}
+
+impl<T: Serialize> Serialize for ArrayFilter<T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::SerializeMap;
+
+        if self.is_empty() {
+            return Err(S::Error::custom(
+                "an array filter must have elements to match",
+            ));
+        }
+        let mut map = serializer.serialize_map(None)?;
+        if !self.any.is_empty() {
+            map.serialize_entry("$in", &self.any)?;
+        }
+        if !self.all.is_empty() {
+            map.serialize_entry("$all", &self.all)?;
+        }
+        map.end()
+    }
+}
+
+/// A condition on the value of an ordered field of an entity, e.g. a quantity. At least one of
+/// its bounds must be present.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct RangeFilter<T> {
+    /// The value must be greater than or equal to this.
+    pub min: Option<T>,
+    /// The value must be less than or equal to this.
+    pub max: Option<T>,
+}
+
+impl<T> RangeFilter<T> {
+    /// Returns a filter matching values greater than or equal to `min`.
+    pub fn at_least(min: T) -> Self {
+        Self {
+            min: Some(min),
+            max: None,
+        }
+    }
+
+    /// Returns a filter matching values less than or equal to `max`.
+    pub fn at_most(max: T) -> Self {
+        Self {
+            min: None,
+            max: Some(max),
+        }
+    }
+}
+
+impl<T: Serialize> Serialize for RangeFilter<T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::SerializeMap;
+
+        if self.min.is_none() && self.max.is_none() {
+            return Err(S::Error::custom("a range filter must have a bound"));
+        }
+        let mut map = serializer.serialize_map(None)?;
+        if let Some(ref min) = self.min {
+            map.serialize_entry("$gte", min)?;
+        }
+        if let Some(ref max) = self.max {
+            map.serialize_entry("$lte", max)?;
+        }
+        map.end()
+    }
+}
+
+/// A condition on the value of a field of an entity: equal to a value, or within a range.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum ValueFilter<T> {
+    Eq(T),
+    Range(RangeFilter<T>),
+}
+
+impl<T: Serialize> Serialize for ValueFilter<T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::SerializeMap;
+
+        match self {
+            Self::Eq(value) => {
+                let mut map = serializer.serialize_map(Some(1))?;
+                map.serialize_entry("$eq", value)?;
+                map.end()
+            }
+            Self::Range(range) => range.serialize(serializer),
+        }
+    }
+}
+
+/// Conditions on the entries of a map field of an entity, i.e. a subdocument whose keys are chosen
+/// by users; an entity matches if every one of the entries named matches its condition.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct MapFilter<K, F>(pub BTreeMap<K, F>);
+
+impl<K, F> Default for MapFilter<K, F> {
+    fn default() -> Self {
+        Self(BTreeMap::new())
+    }
+}
+
+impl<K: Serialize, F: Serialize> Serialize for MapFilter<K, F> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::SerializeMap;
+
+        let mut map = serializer.serialize_map(Some(1))?;
+        map.serialize_entry(FIELDS, &self.0)?;
+        map.end()
+    }
+}
+
+/// Compiles a serialized filter into a mongo query document, addressing the entries of map filters
+/// (see `MapFilter`) by their dotted paths.
+pub fn to_filter_document(filter: Document) -> Document {
+    flatten_fields(filter)
+}
+
+/// Replaces every field serialized as entries to apply to the fields of a subdocument with those
+/// entries, addressed by their dotted paths, e.g. `{ "a": { "$fields": { "b": 1 } } }` becomes
+/// `{ "a.b": 1 }`.
+pub(crate) fn flatten_fields(doc: Document) -> Document {
+    let mut flattened = Document::new();
+    for (field, value) in doc {
+        match value {
+            Bson::Document(mut entries) if entries.len() == 1 && entries.contains_key(FIELDS) => {
+                if let Some(Bson::Document(fields)) = entries.remove(FIELDS) {
+                    for (subfield, value) in flatten_fields(fields) {
+                        flattened.insert(format!("{field}.{subfield}"), value);
+                    }
+                }
+            }
+            value => {
+                flattened.insert(field, value);
+            }
+        }
+    }
+    flattened
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use mongodb::bson::{doc, to_bson, Bson};
+
+    #[test]
+    fn any_elements_are_matched_with_in() {
+        let filter = ArrayFilter::any(vec!["a", "b"]);
+        assert_eq!(
+            to_bson(&filter).unwrap(),
+            Bson::Document(doc! { "$in": ["a", "b"] })
+        );
+    }
+
+    #[test]
+    fn any_and_all_elements_are_combined() {
+        let filter = ArrayFilter {
+            any: vec!["a"],
+            all: vec!["b", "c"],
+        };
+        assert_eq!(
+            to_bson(&filter).unwrap(),
+            Bson::Document(doc! { "$in": ["a"], "$all": ["b", "c"] })
+        );
+    }
+
+    #[test]
+    fn empty_filter_cannot_be_serialized() {
+        assert!(to_bson(&ArrayFilter::<String>::default()).is_err());
+    }
+
+    #[test]
+    fn range_bounds_are_inclusive() {
+        assert_eq!(
+            to_bson(&RangeFilter::at_least(5)).unwrap(),
+            Bson::Document(doc! { "$gte": 5 })
+        );
+        let filter = RangeFilter {
+            min: Some(1),
+            max: Some(9),
+        };
+        assert_eq!(
+            to_bson(&filter).unwrap(),
+            Bson::Document(doc! { "$gte": 1, "$lte": 9 })
+        );
+    }
+
+    #[test]
+    fn unbounded_range_cannot_be_serialized() {
+        assert!(to_bson(&RangeFilter::<i64>::default()).is_err());
+    }
+
+    #[test]
+    fn map_filter_entries_are_addressed_by_path() {
+        let mut conditions = BTreeMap::new();
+        conditions.insert("colour", ValueFilter::Eq(Bson::from("red")));
+        conditions.insert(
+            "weight",
+            ValueFilter::Range(RangeFilter::at_most(Bson::from(2.5))),
+        );
+        let filter = doc! {
+            "name": "widget",
+            "attributes": to_bson(&MapFilter(conditions)).unwrap(),
+        };
+        assert_eq!(
+            to_filter_document(filter),
+            doc! {
+                "name": "widget",
+                "attributes.colour": { "$eq": "red" },
+                "attributes.weight": { "$lte": 2.5 },
+            }
+        );
+    }
+
+    #[test]
+    fn empty_map_filter_matches_everything() {
+        let filter = doc! { "attributes": to_bson(&MapFilter::<String, ValueFilter<i32>>::default()).unwrap() };
+        assert_eq!(to_filter_document(filter), doc! {});
+    }
+}
@@ -0,0 +1,219 @@
+use super::{mongo_repo::MongoRepoError, namespace::NamespaceResolver};
+use crate::common::idempotency::IdempotencyKey;
+use async_trait::async_trait;
+use mongodb::{
+    bson::{doc, DateTime},
+    error::{ErrorKind, WriteFailure},
+    options::ReplaceOptions,
+};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::{error::Error, ops::DerefMut, sync::Arc, time::Duration};
+use tokio::sync::Mutex;
+
+/// The collection the responses to idempotent mutations are recorded in.
+pub const IDEMPOTENCY_KEYS_COLLECTION: &str = "_idempotency_keys";
+
+/// How long a response is replayed for unless configured otherwise.
+pub const DEFAULT_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// The server error code indicating a write would have duplicated a unique key.
+const DUPLICATE_KEY_ERROR_CODE: i32 = 11000;
+
+/// Records the responses to mutations made with an idempotency key, so that a retried mutation can
+/// replay its original response.
+#[async_trait]
+pub trait IdempotencyStore {
+    type StoreError: Error;
+
+    /// Returns the response recorded for an operation made with the provided key, if it has not
+    /// expired.
+    ///
+    /// # Arguments
+    /// * `key` - the idempotency key supplied with the operation
+    /// * `operation` - the name of the operation; the same key may be used once for each operation
+    async fn recorded<T>(
+        &self,
+        key: &IdempotencyKey,
+        operation: &str,
+    ) -> Result<Option<T>, Self::StoreError>
+    where
+        T: DeserializeOwned + Send + Sync + Unpin;
+
+    /// Records the response to an operation made with the provided key, replacing any response
+    /// for the same key and operation that has expired. Recording a second response for the same
+    /// key and operation before the first expires fails.
+    ///
+    /// # Arguments
+    /// * `key` - the idempotency key supplied with the operation
+    /// * `operation` - the name of the operation
+    /// * `response` - the response to replay if the operation is retried
+    async fn record<T>(
+        &self,
+        key: &IdempotencyKey,
+        operation: &str,
+        response: &T,
+    ) -> Result<(), Self::StoreError>
+    where
+        T: Serialize + Send + Sync;
+}
+
+/// The ID of a recorded response; the key is scoped by operation.
+#[derive(Serialize)]
+struct RecordId<'a> {
+    operation: &'a str,
+    key: &'a str,
+}
+
+#[derive(Serialize)]
+struct Record<'a, T> {
+    #[serde(rename = "_id")]
+    id: RecordId<'a>,
+    response: T,
+    expires_at: DateTime,
+}
+
+/// An `IdempotencyStore` that records responses in a mongo collection. Expired responses are
+/// removed by a TTL index on `expires_at`, created by a migration.
+#[derive(Clone)]
+pub struct MongoIdempotencyStore {
+    client: mongodb::Client,
+    namespace: NamespaceResolver,
+    db_name: &'static str,
+    ttl: Duration,
+    session: Option<Arc<Mutex<mongodb::ClientSession>>>,
+}
+
+impl MongoIdempotencyStore {
+    /// Creates a store.
+    ///
+    /// # Arguments
+    /// * `client` - the mongo client to use
+    /// * `namespace` - the namespace to resolve the names of the database and collection with
+    /// * `db_name` - the default name of the database to record responses in
+    pub fn new(
+        client: mongodb::Client,
+        namespace: NamespaceResolver,
+        db_name: &'static str,
+    ) -> Self {
+        Self {
+            client,
+            namespace,
+            db_name,
+            ttl: DEFAULT_TTL,
+            session: None,
+        }
+    }
+
+    /// Creates a store whose operations take place within the provided session, e.g. so that a
+    /// response is recorded in the same transaction as the change it describes.
+    pub fn new_with_session(
+        client: mongodb::Client,
+        namespace: NamespaceResolver,
+        db_name: &'static str,
+        session: Arc<Mutex<mongodb::ClientSession>>,
+    ) -> Self {
+        Self {
+            session: Some(session),
+            ..Self::new(client, namespace, db_name)
+        }
+    }
+
+    /// Returns this store configured to replay responses for the provided time after they are
+    /// recorded.
+    pub fn with_ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = ttl;
+        self
+    }
+
+    fn collection<T>(&self) -> mongodb::Collection<T> {
+        self.client
+            .database(&self.namespace.db_name(self.db_name))
+            .collection(&self.namespace.collection_name(IDEMPOTENCY_KEYS_COLLECTION))
+    }
+}
+
+#[async_trait]
+impl IdempotencyStore for MongoIdempotencyStore {
+    type StoreError = MongoRepoError;
+
+    async fn recorded<T>(
+        &self,
+        key: &IdempotencyKey,
+        operation: &str,
+    ) -> Result<Option<T>, Self::StoreError>
+    where
+        T: DeserializeOwned + Send + Sync + Unpin,
+    {
+        #[derive(Deserialize)]
+        struct Response<T> {
+            response: T,
+        }
+
+        let filter = doc! {
+            "_id": { "operation": operation, "key": key.as_ref() },
+            "expires_at": { "$gt": DateTime::now() },
+        };
+        let coll = self.collection::<Response<T>>();
+        let recorded = match self.session {
+            Some(ref session) => {
+                let mut session_guard = session.lock().await;
+                let session = session_guard.deref_mut();
+                coll.find_one_with_session(filter, None, session).await?
+            }
+            None => coll.find_one(filter, None).await?,
+        };
+        Ok(recorded.map(|recorded| recorded.response))
+    }
+
+    async fn record<T>(
+        &self,
+        key: &IdempotencyKey,
+        operation: &str,
+        response: &T,
+    ) -> Result<(), Self::StoreError>
+    where
+        T: Serialize + Send + Sync,
+    {
+        let now = DateTime::now();
+        let record = Record {
+            id: RecordId {
+                operation,
+                key: key.as_ref(),
+            },
+            response,
+            expires_at: DateTime::from_millis(now.timestamp_millis() + self.ttl.as_millis() as i64),
+        };
+        // a record that has expired may not have been removed yet, and is replaced; one that has
+        // not is left alone, and the upsert fails on its ID
+        let filter = doc! {
+            "_id": { "operation": operation, "key": key.as_ref() },
+            "expires_at": { "$lte": now },
+        };
+        let options = ReplaceOptions::builder().upsert(true).build();
+        let coll = self.collection::<Record<&T>>();
+        let result = match self.session {
+            Some(ref session) => {
+                let mut session_guard = session.lock().await;
+                let session = session_guard.deref_mut();
+                coll.replace_one_with_session(filter, record, options, session)
+                    .await
+            }
+            None => coll.replace_one(filter, record, options).await,
+        };
+        match result {
+            Ok(_) => Ok(()),
+            Err(e) if is_duplicate_key_error(&e) => Err(MongoRepoError::IdempotencyConflict),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+fn is_duplicate_key_error(e: &mongodb::error::Error) -> bool {
+    match e.kind.as_ref() {
+        ErrorKind::Write(WriteFailure::WriteError(write_error)) => {
+            write_error.code == DUPLICATE_KEY_ERROR_CODE
+        }
+        ErrorKind::Command(command_error) => command_error.code == DUPLICATE_KEY_ERROR_CODE,
+        _ => false,
+    }
+}
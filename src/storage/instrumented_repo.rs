@@ -0,0 +1,182 @@
+use crate::common::id::Id;
+use crate::metrics::{REPO_OPERATION_DURATION, REPO_OPERATION_ERRORS};
+use crate::storage::repo::{Facet, GeoQuery, Near, Repo, Reposable, ReturnDocument};
+use async_trait::async_trait;
+use futures::Future;
+use serde::de::DeserializeOwned;
+use std::marker::PhantomData;
+
+/// A repository decorator that records the latency and errors of each operation of the inner
+/// repository, labelled with a collection name.
+pub struct InstrumentedRepo<R, Inner: Repo<R>>
+where
+    R: Reposable,
+{
+    inner: Inner,
+    collection: &'static str,
+    _reposable: PhantomData<R>,
+}
+
+impl<R, Inner: Repo<R>> InstrumentedRepo<R, Inner>
+where
+    R: Reposable,
+{
+    pub fn new(inner: Inner, collection: &'static str) -> Self {
+        Self {
+            inner,
+            collection,
+            _reposable: PhantomData,
+        }
+    }
+
+    async fn instrument<T>(
+        &self,
+        method: &str,
+        operation: impl Future<Output = Result<T, Inner::RepoError>>,
+    ) -> Result<T, Inner::RepoError> {
+        let labels = [self.collection, method];
+        let timer = REPO_OPERATION_DURATION
+            .with_label_values(&labels)
+            .start_timer();
+        let result = operation.await;
+        timer.observe_duration();
+        if result.is_err() {
+            REPO_OPERATION_ERRORS.with_label_values(&labels).inc();
+        }
+        result
+    }
+}
+
+#[async_trait]
+impl<R, Inner: Repo<R>> Repo<R> for InstrumentedRepo<R, Inner>
+where
+    R: Reposable + Send + Sync,
+    R::Spec: Sync,
+    R::Patch: Sync,
+    R::Filter: Sync,
+    Inner: Send + Sync,
+{
+    type RepoError = Inner::RepoError;
+
+    async fn create(&self, spec: &R::Spec) -> Result<Id<R>, Self::RepoError> {
+        self.instrument("create", self.inner.create(spec)).await
+    }
+
+    async fn create_and_get(&self, spec: &R::Spec) -> Result<R, Self::RepoError> {
+        self.instrument("create_and_get", self.inner.create_and_get(spec))
+            .await
+    }
+
+    async fn update(&self, patch: &R::Patch) -> Result<bool, Self::RepoError> {
+        self.instrument("update", self.inner.update(patch)).await
+    }
+
+    async fn delete(&self, id: &Id<R>) -> Result<bool, Self::RepoError> {
+        self.instrument("delete", self.inner.delete(id)).await
+    }
+
+    async fn update_and_get(
+        &self,
+        patch: &R::Patch,
+        return_document: ReturnDocument,
+    ) -> Result<Option<R>, Self::RepoError> {
+        self.instrument(
+            "update_and_get",
+            self.inner.update_and_get(patch, return_document),
+        )
+        .await
+    }
+
+    async fn update_and_get_if(
+        &self,
+        patch: &R::Patch,
+        condition: &R::Filter,
+        return_document: ReturnDocument,
+    ) -> Result<Option<R>, Self::RepoError> {
+        self.instrument(
+            "update_and_get_if",
+            self.inner
+                .update_and_get_if(patch, condition, return_document),
+        )
+        .await
+    }
+
+    async fn delete_and_get(&self, id: &Id<R>) -> Result<Option<R>, Self::RepoError> {
+        self.instrument("delete_and_get", self.inner.delete_and_get(id))
+            .await
+    }
+
+    async fn retrieve(&self, id: &Id<R>) -> Result<Option<R>, Self::RepoError> {
+        self.instrument("retrieve", self.inner.retrieve(id)).await
+    }
+
+    async fn retrieve_many(&self, ids: &[Id<R>]) -> Result<Vec<R>, Self::RepoError> {
+        self.instrument("retrieve_many", self.inner.retrieve_many(ids))
+            .await
+    }
+
+    async fn retrieve_all(&self) -> Result<Vec<R>, Self::RepoError> {
+        self.instrument("retrieve_all", self.inner.retrieve_all())
+            .await
+    }
+
+    async fn retrieve_page(&self, offset: usize, limit: usize) -> Result<Vec<R>, Self::RepoError> {
+        self.instrument("retrieve_page", self.inner.retrieve_page(offset, limit))
+            .await
+    }
+
+    async fn find_all(&self, filter: &R::Filter) -> Result<Vec<R>, Self::RepoError> {
+        self.instrument("find_all", self.inner.find_all(filter))
+            .await
+    }
+
+    async fn find_page(
+        &self,
+        filter: &R::Filter,
+        offset: usize,
+        limit: usize,
+    ) -> Result<Vec<R>, Self::RepoError> {
+        self.instrument("find_page", self.inner.find_page(filter, offset, limit))
+            .await
+    }
+
+    async fn facet<V>(
+        &self,
+        field: &str,
+        filter: &R::Filter,
+    ) -> Result<Vec<Facet<V>>, Self::RepoError>
+    where
+        V: DeserializeOwned + Send,
+    {
+        self.instrument("facet", self.inner.facet(field, filter))
+            .await
+    }
+
+    async fn find_near(
+        &self,
+        field: &str,
+        query: &GeoQuery,
+        filter: &R::Filter,
+        limit: usize,
+    ) -> Result<Vec<Near<R>>, Self::RepoError> {
+        self.instrument(
+            "find_near",
+            self.inner.find_near(field, query, filter, limit),
+        )
+        .await
+    }
+}
+
+impl<R, Inner: Repo<R>> Clone for InstrumentedRepo<R, Inner>
+where
+    R: Reposable,
+    Inner: Clone,
+{
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+            collection: self.collection,
+            _reposable: PhantomData,
+        }
+    }
+}
@@ -1,2 +1,11 @@
+pub mod cached_repo;
+pub mod filter;
+pub mod idempotency;
+pub mod instrumented_repo;
+pub mod mongo_options;
 pub mod mongo_repo;
+pub mod namespace;
+pub mod patch;
 pub mod repo;
+pub mod retrying_repo;
+pub mod transfer;
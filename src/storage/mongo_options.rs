@@ -0,0 +1,247 @@
+use mongodb::options::{
+    Acknowledgment, ReadConcern, ReadPreference, ReadPreferenceOptions, SelectionCriteria,
+    ValidationAction, ValidationLevel, WriteConcern,
+};
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+
+/// Options controlling the consistency and durability of the operations of a `MongoRepo`.
+///
+/// Any option that is not set falls back first to the options declared by the reposable type, and
+/// then to the defaults of the mongo client. Read preferences are ignored within transactions,
+/// which must always read from the primary.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct MongoRepoOptions {
+    write_concern: Option<WriteConcern>,
+    read_concern: Option<ReadConcern>,
+    read_preference: Option<ReadPreference>,
+    list_read_preference: Option<ReadPreference>,
+}
+
+impl MongoRepoOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns these options configured to use the provided write concern for all writes.
+    pub fn with_write_concern(mut self, write_concern: WriteConcern) -> Self {
+        self.write_concern = Some(write_concern);
+        self
+    }
+
+    /// Returns these options configured to use the provided read concern for all reads.
+    pub fn with_read_concern(mut self, read_concern: ReadConcern) -> Self {
+        self.read_concern = Some(read_concern);
+        self
+    }
+
+    /// Returns these options configured to use the provided read preference for all reads, unless
+    /// a separate read preference is configured for list queries.
+    pub fn with_read_preference(mut self, read_preference: ReadPreference) -> Self {
+        self.read_preference = Some(read_preference);
+        self
+    }
+
+    /// Returns these options configured to use the provided read preference for queries that can
+    /// return many entities.
+    pub fn with_list_read_preference(mut self, read_preference: ReadPreference) -> Self {
+        self.list_read_preference = Some(read_preference);
+        self
+    }
+
+    pub fn write_concern(&self) -> &Option<WriteConcern> {
+        &self.write_concern
+    }
+
+    pub fn read_concern(&self) -> &Option<ReadConcern> {
+        &self.read_concern
+    }
+
+    /// Returns the selection criteria to use for reads of a single entity.
+    pub fn selection_criteria(&self) -> Option<SelectionCriteria> {
+        self.read_preference
+            .clone()
+            .map(SelectionCriteria::ReadPreference)
+    }
+
+    /// Returns the selection criteria to use for queries that can return many entities.
+    pub fn list_selection_criteria(&self) -> Option<SelectionCriteria> {
+        self.list_read_preference
+            .clone()
+            .map(SelectionCriteria::ReadPreference)
+            .or_else(|| self.selection_criteria())
+    }
+
+    /// Returns these options with any unset option taken from `fallback`.
+    pub fn or(self, fallback: MongoRepoOptions) -> Self {
+        Self {
+            write_concern: self.write_concern.or(fallback.write_concern),
+            read_concern: self.read_concern.or(fallback.read_concern),
+            read_preference: self.read_preference.or(fallback.read_preference),
+            list_read_preference: self.list_read_preference.or(fallback.list_read_preference),
+        }
+    }
+}
+
+/// An error indicating a mongo option could not be parsed from a string.
+#[derive(Debug, Clone)]
+pub struct InvalidOptionError(String);
+
+impl Error for InvalidOptionError {}
+
+impl Display for InvalidOptionError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// Parses a write concern from the acknowledgment it requires (`majority`, a number of nodes or the
+/// name of a custom write concern) and whether it requires writes to be journaled.
+pub fn parse_write_concern(
+    w: &str,
+    journal: Option<bool>,
+) -> Result<WriteConcern, InvalidOptionError> {
+    if w.is_empty() {
+        return Err(InvalidOptionError(String::from(
+            "a write concern acknowledgment cannot be empty",
+        )));
+    }
+    let w = match w.parse::<u32>() {
+        Ok(nodes) => Acknowledgment::Nodes(nodes),
+        Err(_) => Acknowledgment::from(w.to_string()),
+    };
+    Ok(WriteConcern::builder().w(w).journal(journal).build())
+}
+
+/// Parses a read concern from its level, e.g. `majority` or `snapshot`.
+pub fn parse_read_concern(level: &str) -> Result<ReadConcern, InvalidOptionError> {
+    match level {
+        "local" => Ok(ReadConcern::local()),
+        "majority" => Ok(ReadConcern::majority()),
+        "linearizable" => Ok(ReadConcern::linearizable()),
+        "available" => Ok(ReadConcern::available()),
+        "snapshot" => Ok(ReadConcern::snapshot()),
+        _ => Err(InvalidOptionError(format!(
+            "unknown read concern level: {level}"
+        ))),
+    }
+}
+
+/// Parses a read preference from its mode, e.g. `primary` or `secondaryPreferred`.
+pub fn parse_read_preference(mode: &str) -> Result<ReadPreference, InvalidOptionError> {
+    let options = ReadPreferenceOptions::default();
+    match mode {
+        "primary" => Ok(ReadPreference::Primary),
+        "primaryPreferred" => Ok(ReadPreference::PrimaryPreferred { options }),
+        "secondary" => Ok(ReadPreference::Secondary { options }),
+        "secondaryPreferred" => Ok(ReadPreference::SecondaryPreferred { options }),
+        "nearest" => Ok(ReadPreference::Nearest { options }),
+        _ => Err(InvalidOptionError(format!(
+            "unknown read preference mode: {mode}"
+        ))),
+    }
+}
+
+/// Parses a schema validation level, i.e. `off`, `strict` or `moderate`.
+pub fn parse_validation_level(level: &str) -> Result<ValidationLevel, InvalidOptionError> {
+    match level {
+        "off" => Ok(ValidationLevel::Off),
+        "strict" => Ok(ValidationLevel::Strict),
+        "moderate" => Ok(ValidationLevel::Moderate),
+        _ => Err(InvalidOptionError(format!(
+            "unknown validation level: {level}"
+        ))),
+    }
+}
+
+/// Parses a schema validation action, i.e. `error` or `warn`.
+pub fn parse_validation_action(action: &str) -> Result<ValidationAction, InvalidOptionError> {
+    match action {
+        "error" => Ok(ValidationAction::Error),
+        "warn" => Ok(ValidationAction::Warn),
+        _ => Err(InvalidOptionError(format!(
+            "unknown validation action: {action}"
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn write_concern_can_be_parsed() {
+        let majority = parse_write_concern("majority", Some(true)).unwrap();
+        assert_eq!(majority.w, Some(Acknowledgment::Majority));
+        assert_eq!(majority.journal, Some(true));
+
+        let nodes = parse_write_concern("2", None).unwrap();
+        assert_eq!(nodes.w, Some(Acknowledgment::Nodes(2)));
+        assert_eq!(nodes.journal, None);
+
+        assert!(parse_write_concern("", None).is_err());
+    }
+
+    #[test]
+    fn read_concern_can_be_parsed() {
+        assert_eq!(
+            parse_read_concern("majority").unwrap(),
+            ReadConcern::majority()
+        );
+        assert_eq!(
+            parse_read_concern("snapshot").unwrap(),
+            ReadConcern::snapshot()
+        );
+        assert!(parse_read_concern("eventual").is_err());
+    }
+
+    #[test]
+    fn read_preference_can_be_parsed() {
+        assert_eq!(
+            parse_read_preference("primary").unwrap(),
+            ReadPreference::Primary
+        );
+        assert!(matches!(
+            parse_read_preference("secondaryPreferred").unwrap(),
+            ReadPreference::SecondaryPreferred { .. }
+        ));
+        assert!(parse_read_preference("fastest").is_err());
+    }
+
+    #[test]
+    fn validation_options_can_be_parsed() {
+        assert_eq!(
+            parse_validation_level("moderate").unwrap(),
+            ValidationLevel::Moderate
+        );
+        assert_eq!(
+            parse_validation_action("warn").unwrap(),
+            ValidationAction::Warn
+        );
+        assert!(parse_validation_level("lenient").is_err());
+        assert!(parse_validation_action("ignore").is_err());
+    }
+
+    #[test]
+    fn list_queries_fall_back_to_general_read_preference() {
+        let options = MongoRepoOptions::new().with_read_preference(ReadPreference::Primary);
+        assert_eq!(
+            options.list_selection_criteria(),
+            Some(SelectionCriteria::ReadPreference(ReadPreference::Primary))
+        );
+    }
+
+    #[test]
+    fn unset_options_are_taken_from_fallback() {
+        let options = MongoRepoOptions::new()
+            .with_read_concern(ReadConcern::majority())
+            .or(MongoRepoOptions::new()
+                .with_read_concern(ReadConcern::local())
+                .with_write_concern(parse_write_concern("majority", None).unwrap()));
+        assert_eq!(options.read_concern(), &Some(ReadConcern::majority()));
+        assert_eq!(
+            options.write_concern().as_ref().and_then(|wc| wc.w.clone()),
+            Some(Acknowledgment::Majority)
+        );
+    }
+}
@@ -1,23 +1,74 @@
-use crate::common::id::Id;
-use crate::storage::repo::{Patch, Repo};
+use crate::common::{
+    deadline::Deadline,
+    entity::InvalidEntityError,
+    id::{Id, Key},
+};
+use crate::storage::repo::{self, Facet, GeoArea, GeoQuery, Near, Patch, Repo};
 use async_trait::async_trait;
-use futures::StreamExt;
-use mongodb::bson::{doc, ser::to_document, Bson};
-use mongodb::options::FindOptions;
+use futures::{stream, Future, Stream, StreamExt};
+use mongodb::bson::{
+    doc, from_document, oid::ObjectId, ser::to_document, to_bson, Bson, Document, Uuid,
+};
+use mongodb::change_stream::{
+    event::{ChangeStreamEvent, ResumeToken},
+    ChangeStream,
+};
+use mongodb::options::{
+    AggregateOptions, ChangeStreamOptions, CollectionOptions, CreateCollectionOptions,
+    DeleteOptions, FindOneAndDeleteOptions, FindOneAndUpdateOptions, FindOneOptions, FindOptions,
+    IndexOptions, InsertOneOptions, ReturnDocument, SelectionCriteria, SessionOptions,
+    UpdateOptions, ValidationAction, ValidationLevel, WriteConcern,
+};
+use mongodb::IndexModel;
 use serde::de::DeserializeOwned;
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 use std::error::Error;
 use std::fmt::Display;
 use std::marker::PhantomData;
 use std::ops::DerefMut;
+use std::str::FromStr;
 use std::sync::Arc;
+use std::time::Duration;
 use tokio::sync::Mutex;
+use tracing::instrument;
 
+use super::filter::to_filter_document;
+use super::mongo_options::MongoRepoOptions;
+use super::namespace::NamespaceResolver;
+use super::patch::to_update_document;
 use super::repo::{Filter, Reposable};
+use super::retrying_repo::RetryableError;
 
 pub const DEFAULT_HOST: &str = "127.0.0.1";
 pub const DEFAULT_PORT: u16 = 27017;
 
+pub const DEFAULT_VALIDATION_LEVEL: ValidationLevel = ValidationLevel::Strict;
+pub const DEFAULT_VALIDATION_ACTION: ValidationAction = ValidationAction::Error;
+
+/// The collection holding the counters of `IdStrategy::Sequence`.
+pub const SEQUENCES_COLLECTION: &str = "_sequences";
+
+/// The server error code indicating a collection to create already exists.
+const NAMESPACE_EXISTS_ERROR_CODE: i32 = 48;
+
+/// How long to wait before watching for changes again after watching failed.
+const WATCH_RETRY_DELAY: Duration = Duration::from_secs(1);
+
+/// The field the distance of each entity found by a geospatial query is added to.
+const DISTANCE_FIELD: &str = "_distance";
+
+/// The server error code indicating an operation exceeded its `maxTimeMS`.
+const MAX_TIME_EXPIRED_ERROR_CODE: i32 = 50;
+
+/// The server error code indicating a write concern was not satisfied, e.g. within its `wtimeout`.
+const WRITE_CONCERN_FAILED_ERROR_CODE: i32 = 64;
+
+/// Server error codes indicating a condition that is expected to resolve itself, such as an
+/// election of a new primary or a node shutting down.
+const TRANSIENT_ERROR_CODES: [i32; 13] = [
+    6, 7, 89, 91, 134, 189, 262, 9001, 10107, 11600, 11602, 13435, 13436,
+];
+
 pub struct MongoRepo<R: MongoReposable>
 where
     R: DeserializeOwned,
@@ -26,6 +77,9 @@ where
     R::Filter: Serialize,
 {
     client: mongodb::Client,
+    namespace: NamespaceResolver,
+    options: MongoRepoOptions,
+    deadline: Option<Deadline>,
     session: Option<Arc<Mutex<mongodb::ClientSession>>>,
     _reposable: PhantomData<R>,
 }
@@ -39,8 +93,68 @@ where
 {
     fn db_name() -> &'static str;
     fn collection_name() -> &'static str;
+
+    /// The options to use for operations on this type's collection, unless overridden by the
+    /// options a repository is created with.
+    fn repo_options() -> MongoRepoOptions {
+        MongoRepoOptions::default()
+    }
+
+    /// A `$jsonSchema` that documents of this type must satisfy, so that documents written by
+    /// other processes can be rejected before they fail deserialization. See
+    /// `MongoRepo::apply_validator`.
+    fn json_schema() -> Option<Document> {
+        None
+    }
+
+    /// The fields of this type holding GeoJSON points, as they are stored, which need a
+    /// `2dsphere` index to be searched by location; see `geo_indexes`.
+    fn geo_fields() -> &'static [&'static str] {
+        &[]
+    }
+
+    /// How the IDs of new entities of this type are generated; this must produce keys of the
+    /// type's key type, which the `Reposable` derive checks at compile time and creation checks
+    /// before inserting.
+    fn id_strategy() -> IdStrategy {
+        IdStrategy::Server
+    }
+}
+
+/// A way of generating the IDs of new entities.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum IdStrategy {
+    /// IDs are left for mongo to generate on insertion, and so are `ObjectId`s.
+    Server,
+    /// IDs are generated as time-ordered UUIDs (version 7) before insertion.
+    UuidV7,
+    /// IDs are consecutive integers, counted per collection in a collection of sequences.
+    Sequence,
+}
+
+/// Types standing for each `IdStrategy`, so that the key types a strategy can generate are known
+/// at compile time; see `GeneratedKey`.
+pub mod id_strategies {
+    /// Stands for `IdStrategy::Server`.
+    pub struct Server;
+    /// Stands for `IdStrategy::UuidV7`.
+    pub struct UuidV7;
+    /// Stands for `IdStrategy::Sequence`.
+    pub struct Sequence;
 }
 
+/// A key type that the `IdStrategy` stood for by `S` generates keys of. The `Reposable` derive
+/// checks an entity's key type against its ID strategy with this, so that an entity whose
+/// strategy generates keys of another type fails to compile.
+pub trait GeneratedKey<S>: Key {}
+
+impl GeneratedKey<id_strategies::Server> for ObjectId {}
+impl GeneratedKey<id_strategies::UuidV7> for Uuid {}
+impl GeneratedKey<id_strategies::Sequence> for i64 {}
+
+/// Compiles only if the key type `K` is generated by the `IdStrategy` stood for by `S`.
+pub fn assert_generated_key<K: GeneratedKey<S>, S>() {}
+
 impl<R: MongoReposable> MongoRepo<R>
 where
     R: DeserializeOwned,
@@ -48,9 +162,12 @@ where
     R::Patch: Serialize,
     R::Filter: Serialize,
 {
-    pub fn new(client: mongodb::Client) -> Self {
+    pub fn new(client: mongodb::Client, namespace: NamespaceResolver) -> Self {
         Self {
             client,
+            namespace,
+            options: R::repo_options(),
+            deadline: None,
             session: None,
             _reposable: PhantomData,
         }
@@ -58,19 +175,450 @@ where
 
     pub fn new_with_session(
         client: mongodb::Client,
+        namespace: NamespaceResolver,
         session: Arc<Mutex<mongodb::ClientSession>>,
     ) -> Self {
         Self {
             client,
+            namespace,
+            options: R::repo_options(),
+            deadline: None,
             session: Some(session),
             _reposable: PhantomData,
         }
     }
 
+    /// Returns this repository configured to use the provided options; any option that is not set
+    /// falls back to the options of the reposable type.
+    pub fn with_options(mut self, options: MongoRepoOptions) -> Self {
+        self.options = options.or(R::repo_options());
+        self
+    }
+
+    /// Returns this repository configured to fail operations with `MongoRepoError::Timeout` once
+    /// the provided deadline has passed; reads are limited server-side to the remaining time, and
+    /// writes outside of a transaction wait no longer than that for their write concern.
+    pub fn with_deadline(mut self, deadline: Deadline) -> Self {
+        self.deadline = Some(deadline);
+        self
+    }
+
+    /// Installs the `$jsonSchema` validator of the reposable type on this repository's collection,
+    /// creating the collection if it does not yet exist.
+    ///
+    /// # Arguments
+    /// * `level` - which inserts and updates are validated
+    /// * `action` - whether invalid documents are rejected, or only logged by the server
+    ///
+    /// # Returns
+    /// `true` if a validator was installed, `false` if the reposable type has no schema
+    pub async fn apply_validator(
+        &self,
+        level: ValidationLevel,
+        action: ValidationAction,
+    ) -> Result<bool, MongoRepoError> {
+        let Some(schema) = R::json_schema() else {
+            return Ok(false);
+        };
+        let db = self.client.database(&self.namespace.db_name(R::db_name()));
+        let collection_name = self.namespace.collection_name(R::collection_name());
+        let validator = doc! { "$jsonSchema": schema };
+
+        let options = CreateCollectionOptions::builder()
+            .validator(validator.clone())
+            .validation_level(level.clone())
+            .validation_action(action.clone())
+            .build();
+        match db.create_collection(&collection_name, options).await {
+            Ok(()) => Ok(true),
+            Err(e) if is_namespace_exists_error(&e) => {
+                let command = doc! {
+                    "collMod": collection_name,
+                    "validator": validator,
+                    "validationLevel": to_bson(&level)?,
+                    "validationAction": to_bson(&action)?,
+                };
+                db.run_command(command, None).await?;
+                Ok(true)
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Creates a `2dsphere` index on each of the geo fields of the reposable type, so that
+    /// entities can be found by their location with `Repo::find_near`; indexes that already exist
+    /// are left as they are.
+    ///
+    /// # Returns
+    /// the number of indexes created or found to exist
+    pub async fn create_geo_indexes(&self) -> Result<usize, MongoRepoError> {
+        let indexes = geo_indexes(R::geo_fields());
+        if indexes.is_empty() {
+            return Ok(0);
+        }
+        let result = self
+            .collection::<Document>()
+            .create_indexes(indexes, None)
+            .await?;
+        Ok(result.index_names.len())
+    }
+
+    /// Visits every entity matching a filter, as of a single point in time, even if entities are
+    /// changed while they are being visited. Requires mongo to be running as a replica set.
+    ///
+    /// # Arguments
+    /// * `filter` - a mongo query document selecting the entities to visit
+    /// * `visit` - called with each entity in turn; visiting stops at the first error
+    ///
+    /// # Returns
+    /// the number of entities visited
+    pub async fn visit_snapshot<E>(
+        &self,
+        filter: Document,
+        mut visit: impl FnMut(R) -> Result<(), E>,
+    ) -> Result<usize, E>
+    where
+        E: From<MongoRepoError>,
+    {
+        let options = SessionOptions::builder().snapshot(true).build();
+        let mut session = self
+            .client
+            .start_session(options)
+            .await
+            .map_err(MongoRepoError::from)?;
+        let mut cursor = self
+            .collection::<R>()
+            .find_with_session(filter, None, &mut session)
+            .await
+            .map_err(MongoRepoError::from)?;
+        let mut count = 0;
+        while let Some(entity) = cursor.next(&mut session).await {
+            visit(entity.map_err(MongoRepoError::from)?)?;
+            count += 1;
+        }
+        Ok(count)
+    }
+
+    /// Writes many entities at once, preserving their IDs. A failure to write one entity does not
+    /// prevent the others from being written. If the write concern is not satisfied, the batch
+    /// fails as a whole, since it is then unknown whether its writes will persist.
+    ///
+    /// # Arguments
+    /// * `entities` - the entities to write
+    /// * `mode` - whether to insert the entities, or replace any existing entities with the same
+    ///   IDs
+    ///
+    /// # Returns
+    /// the index within `entities` of each entity that could not be written, and why
+    pub async fn write_batch(
+        &self,
+        entities: &[R],
+        mode: WriteMode,
+    ) -> Result<Vec<(usize, String)>, MongoRepoError>
+    where
+        R: Serialize,
+    {
+        if entities.is_empty() {
+            return Ok(vec![]);
+        }
+        let collection_name = self.namespace.collection_name(R::collection_name());
+        let docs = entities
+            .iter()
+            .map(to_document)
+            .collect::<Result<Vec<_>, _>>()?;
+        let mut command = match mode {
+            WriteMode::Insert => doc! {
+                "insert": collection_name,
+                "documents": docs,
+                "ordered": false,
+            },
+            WriteMode::Upsert => doc! {
+                "update": collection_name,
+                "updates": docs
+                    .into_iter()
+                    .map(|doc| doc! {
+                        "q": { "_id": doc.get("_id").cloned().unwrap_or(Bson::Null) },
+                        "u": doc,
+                        "upsert": true,
+                    })
+                    .collect::<Vec<_>>(),
+                "ordered": false,
+            },
+        };
+        if let Some(write_concern) = self.write_concern()? {
+            command.insert("writeConcern", to_bson(&write_concern)?);
+        }
+        let db = self.client.database(&self.namespace.db_name(R::db_name()));
+
+        let response = match self.session {
+            Some(ref session) => {
+                let mut session_guard = session.lock().await;
+                let session = session_guard.deref_mut();
+                db.run_command_with_session(command, None, session).await?
+            }
+            None => db.run_command(command, None).await?,
+        };
+
+        if let Ok(write_concern_error) = response.get_document("writeConcernError") {
+            let write_concern_error: mongodb::error::WriteConcernError =
+                from_document(write_concern_error.clone())?;
+            return Err(write_concern_error.into());
+        }
+        let write_errors = match response.get_array("writeErrors") {
+            Ok(write_errors) => write_errors
+                .iter()
+                .filter_map(Bson::as_document)
+                .filter_map(|write_error| {
+                    let index = write_error.get_i32("index").ok()?;
+                    let message = write_error.get_str("errmsg").unwrap_or("unknown error");
+                    Some((index as usize, message.to_string()))
+                })
+                .collect(),
+            Err(_) => vec![],
+        };
+        Ok(write_errors)
+    }
+
+    /// Watches for changes to entities in this repository's collection, in any database (and so for
+    /// any tenant), made by this or any other process. Requires mongo to be running as a replica set.
+    ///
+    /// The stream never ends: when watching is interrupted by an error, the error is yielded and
+    /// watching starts again after the last change seen, so no change is missed unless none had
+    /// been seen yet.
+    ///
+    /// # Returns
+    /// a stream of the IDs of entities as they are updated, replaced or deleted, and of the errors
+    /// that interrupt watching
+    pub fn watch_changed_ids(&self) -> impl Stream<Item = Result<Id<R>, MongoRepoError>> {
+        let watch = ChangeWatch {
+            client: self.client.clone(),
+            pipeline: vec![doc! {
+                "$match": {
+                    "ns.coll": self.namespace.collection_name(R::collection_name()),
+                    "operationType": { "$in": ["update", "replace", "delete"] },
+                }
+            }],
+            change_stream: None,
+            resume_token: None,
+            failed: false,
+        };
+
+        stream::unfold(watch, |mut watch| async move {
+            loop {
+                match watch.next_key().await {
+                    Ok(Some(key)) => {
+                        if let Some(id) = R::Key::from_bson(&key).map(Id::new) {
+                            return Some((Ok(id), watch));
+                        }
+                    }
+                    Ok(None) => {}
+                    Err(e) => return Some((Err(e), watch)),
+                }
+            }
+        })
+    }
+
+    /// Generates the ID of a new entity according to the reposable type's strategy, or returns
+    /// `None` if it should be generated on insertion.
+    async fn generate_id(&self) -> Result<Option<Bson>, MongoRepoError> {
+        match R::id_strategy() {
+            IdStrategy::Server => Ok(None),
+            IdStrategy::UuidV7 => Ok(Some(Bson::from(Uuid::from(uuid::Uuid::now_v7())))),
+            IdStrategy::Sequence => {
+                let sequences = self
+                    .client
+                    .database(&self.namespace.db_name(R::db_name()))
+                    .collection::<Document>(&self.namespace.collection_name(SEQUENCES_COLLECTION));
+                let query = doc! { "_id": self.namespace.collection_name(R::collection_name()) };
+                let update = doc! { "$inc": { "value": 1_i64 } };
+                let options = FindOneAndUpdateOptions::builder()
+                    .upsert(true)
+                    .return_document(ReturnDocument::After)
+                    .build();
+                let sequence = match self.session {
+                    Some(ref session) => {
+                        let mut session_guard = session.lock().await;
+                        let session = session_guard.deref_mut();
+                        sequences
+                            .find_one_and_update_with_session(query, update, options, session)
+                            .await?
+                    }
+                    None => {
+                        sequences
+                            .find_one_and_update(query, update, options)
+                            .await?
+                    }
+                };
+                Ok(sequence.and_then(|sequence| sequence.get("value").cloned()))
+            }
+        }
+    }
+
+    /// Inserts a new entity built from a specification.
+    ///
+    /// # Returns
+    /// the ID of the new entity, and the document that was inserted, including its ID
+    async fn insert(&self, spec: &R::Spec) -> Result<(Id<R>, Document), MongoRepoError>
+    where
+        R::Spec: Serialize,
+    {
+        let mut doc = to_document(spec)?;
+        let coll = self.collection::<Document>();
+
+        let options = InsertOneOptions::builder()
+            .write_concern(self.write_concern()?)
+            .build();
+        if let Some(id) = self.generate_id().await? {
+            // an ID that could not be read back as a key must not be inserted
+            if R::Key::from_bson(&id).is_none() {
+                return Err(MongoRepoError::UnexpectedId(id));
+            }
+            doc.insert("_id", id);
+        }
+        let result = match self.session {
+            Some(ref session) => {
+                let mut session_guard = session.lock().await;
+                let session = session_guard.deref_mut();
+                coll.insert_one_with_session(&doc, options, session).await?
+            }
+            None => coll.insert_one(&doc, options).await?,
+        };
+
+        let id = R::Key::from_bson(&result.inserted_id)
+            .map(Id::new)
+            .ok_or_else(|| MongoRepoError::UnexpectedId(result.inserted_id.clone()))?;
+        doc.insert("_id", result.inserted_id);
+        Ok((id, doc))
+    }
+
+    /// Updates the entity matching a mongo query document with a patch, and returns it.
+    async fn find_one_and_update(
+        &self,
+        query: Document,
+        patch: &R::Patch,
+        return_document: repo::ReturnDocument,
+    ) -> Result<Option<R>, MongoRepoError>
+    where
+        R: Send + Sync + Unpin,
+    {
+        let update = to_update_document(to_document(patch)?);
+        let options = FindOneAndUpdateOptions::builder()
+            .return_document(ReturnDocument::from(return_document))
+            .write_concern(self.write_concern()?)
+            .build();
+        let coll = self.collection::<R>();
+
+        match self.session {
+            Some(ref session) => {
+                let mut session_guard = session.lock().await;
+                let session = session_guard.deref_mut();
+                Ok(coll
+                    .find_one_and_update_with_session(query, update, options, session)
+                    .await?)
+            }
+            None => Ok(coll.find_one_and_update(query, update, options).await?),
+        }
+    }
+
+    /// Finds every entity matching a mongo query document.
+    async fn find_documents(&self, filter: Document) -> Result<Vec<R>, MongoRepoError>
+    where
+        R: Send + Sync + Unpin,
+    {
+        let options = FindOptions::builder()
+            .selection_criteria(self.selection_criteria(true))
+            .max_time(self.remaining_time()?)
+            .build();
+        let coll = self.collection::<R>();
+
+        self.before_deadline(async {
+            match self.session {
+                Some(ref session) => {
+                    let mut session_guard = session.lock().await;
+                    let session = session_guard.deref_mut();
+                    let mut cursor = coll.find_with_session(filter, options, session).await?;
+                    let mut docs = vec![];
+                    while let Some(doc) = cursor.next(session).await {
+                        docs.push(doc?);
+                    }
+                    Ok(docs)
+                }
+                None => {
+                    let mut cursor = coll.find(filter, options).await?;
+                    let mut docs = vec![];
+                    while let Some(doc) = cursor.next().await {
+                        docs.push(doc?);
+                    }
+                    Ok(docs)
+                }
+            }
+        })
+        .await
+    }
+
     fn collection<T>(&self) -> mongodb::Collection<T> {
+        let options = CollectionOptions::builder()
+            .write_concern(self.options.write_concern().clone())
+            .read_concern(self.options.read_concern().clone())
+            .build();
         self.client
-            .database(R::db_name())
-            .collection(R::collection_name())
+            .database(&self.namespace.db_name(R::db_name()))
+            .collection_with_options(
+                &self.namespace.collection_name(R::collection_name()),
+                options,
+            )
+    }
+
+    /// Returns the time left before this repository's deadline, if it has one.
+    fn remaining_time(&self) -> Result<Option<Duration>, MongoRepoError> {
+        match self.deadline {
+            Some(deadline) => deadline
+                .remaining()
+                .map(Some)
+                .ok_or(MongoRepoError::Timeout),
+            None => Ok(None),
+        }
+    }
+
+    /// Returns the write concern to use for a write, which outside of a transaction is bounded by
+    /// this repository's deadline with a `wtimeout`; within a transaction, writes take the
+    /// transaction's write concern, and its commit is bounded by its `maxCommitTimeMS` instead.
+    fn write_concern(&self) -> Result<Option<WriteConcern>, MongoRepoError> {
+        let remaining = self.remaining_time()?;
+        if self.session.is_some() {
+            return Ok(None);
+        }
+        Ok(match remaining {
+            Some(remaining) => Some(bounded_write_concern(
+                self.options.write_concern().clone(),
+                remaining,
+            )),
+            None => self.options.write_concern().clone(),
+        })
+    }
+
+    /// Runs a read, abandoning it if it is still running when this repository's deadline passes.
+    /// Writes are not run this way, since one that is abandoned may still be applied.
+    async fn before_deadline<T>(
+        &self,
+        operation: impl Future<Output = Result<T, MongoRepoError>>,
+    ) -> Result<T, MongoRepoError> {
+        match self.remaining_time()? {
+            Some(remaining) => tokio::time::timeout(remaining, operation)
+                .await
+                .map_err(|_| MongoRepoError::Timeout)?,
+            None => operation.await,
+        }
+    }
+
+    /// Returns the read preference to use for an operation, or none within a transaction, where
+    /// reads must be from the primary.
+    fn selection_criteria(&self, list: bool) -> Option<SelectionCriteria> {
+        match (&self.session, list) {
+            (Some(_), _) => None,
+            (None, false) => self.options.selection_criteria(),
+            (None, true) => self.options.list_selection_criteria(),
+        }
     }
 }
 
@@ -84,143 +632,401 @@ where
 {
     type RepoError = MongoRepoError;
 
-    async fn create(&self, spec: &R::Spec) -> Result<Id, Self::RepoError> {
-        let coll = self.collection::<R::Spec>();
-
-        let result = match self.session {
-            Some(ref session) => {
-                let mut session_guard = session.lock().await;
-                let session = session_guard.deref_mut();
-                coll.insert_one_with_session(spec, None, session).await?
-            }
-            None => coll.insert_one(spec, None).await?,
-        };
+    #[instrument(name = "MongoRepo::create", skip_all, fields(collection = R::collection_name()))]
+    async fn create(&self, spec: &R::Spec) -> Result<Id<R>, Self::RepoError> {
+        let (id, _) = self.insert(spec).await?;
+        Ok(id)
+    }
 
-        match result.inserted_id {
-            Bson::ObjectId(oid) => Ok(oid.into()),
-            _ => panic!("inserted ID was not an ObjectId"),
-        }
+    #[instrument(name = "MongoRepo::create_and_get", skip_all, fields(collection = R::collection_name()))]
+    async fn create_and_get(&self, spec: &R::Spec) -> Result<R, Self::RepoError> {
+        let (_, doc) = self.insert(spec).await?;
+        Ok(from_document(doc)?)
     }
 
+    #[instrument(name = "MongoRepo::update", skip_all, fields(collection = R::collection_name()))]
     async fn update(&self, patch: &R::Patch) -> Result<bool, Self::RepoError> {
         let mut query = R::Filter::default();
         *query.id_mut() = Some(patch.id().clone());
         let query = to_document(&query)?;
-        let update = doc! { "$set": to_document(patch)? };
+        let update = to_update_document(to_document(patch)?);
+        let options = UpdateOptions::builder()
+            .write_concern(self.write_concern()?)
+            .build();
         let coll = self.collection::<R>();
 
         let result = match self.session {
             Some(ref session) => {
                 let mut session_guard = session.lock().await;
                 let session = session_guard.deref_mut();
-                coll.update_one_with_session(query, update, None, session)
+                coll.update_one_with_session(query, update, options, session)
                     .await?
             }
-            None => coll.update_one(query, update, None).await?,
+            None => coll.update_one(query, update, options).await?,
         };
 
         Ok(result.modified_count > 0)
     }
 
-    async fn delete(&self, id: &Id) -> Result<bool, Self::RepoError> {
+    #[instrument(name = "MongoRepo::delete", skip_all, fields(collection = R::collection_name()))]
+    async fn delete(&self, id: &Id<R>) -> Result<bool, Self::RepoError> {
         let mut query = R::Filter::default();
         *query.id_mut() = Some(id.clone());
         let query = to_document(&query)?;
+        let options = DeleteOptions::builder()
+            .write_concern(self.write_concern()?)
+            .build();
         let coll = self.collection::<R>();
 
         let result = match self.session {
             Some(ref session) => {
                 let mut session_guard = session.lock().await;
                 let session = session_guard.deref_mut();
-                coll.delete_one_with_session(query, None, session).await?
+                coll.delete_one_with_session(query, options, session)
+                    .await?
             }
-            None => coll.delete_one(query, None).await?,
+            None => coll.delete_one(query, options).await?,
         };
 
         Ok(result.deleted_count > 0)
     }
 
-    async fn retrieve(&self, id: &Id) -> Result<Option<R>, Self::RepoError> {
-        let mut filter = R::Filter::default();
-        *filter.id_mut() = Some(id.clone());
-        let filter = to_document(&filter)?;
+    #[instrument(name = "MongoRepo::update_and_get", skip_all, fields(collection = R::collection_name()))]
+    async fn update_and_get(
+        &self,
+        patch: &R::Patch,
+        return_document: repo::ReturnDocument,
+    ) -> Result<Option<R>, Self::RepoError> {
+        let mut query = R::Filter::default();
+        *query.id_mut() = Some(patch.id().clone());
+        self.find_one_and_update(to_document(&query)?, patch, return_document)
+            .await
+    }
+
+    #[instrument(name = "MongoRepo::update_and_get_if", skip_all, fields(collection = R::collection_name()))]
+    async fn update_and_get_if(
+        &self,
+        patch: &R::Patch,
+        condition: &R::Filter,
+        return_document: repo::ReturnDocument,
+    ) -> Result<Option<R>, Self::RepoError> {
+        let mut query = to_filter_document(to_document(condition)?);
+        query.insert("_id", Bson::from(patch.id().clone()));
+        self.find_one_and_update(query, patch, return_document)
+            .await
+    }
+
+    #[instrument(name = "MongoRepo::delete_and_get", skip_all, fields(collection = R::collection_name()))]
+    async fn delete_and_get(&self, id: &Id<R>) -> Result<Option<R>, Self::RepoError> {
+        let mut query = R::Filter::default();
+        *query.id_mut() = Some(id.clone());
+        let query = to_document(&query)?;
+        let options = FindOneAndDeleteOptions::builder()
+            .write_concern(self.write_concern()?)
+            .build();
         let coll = self.collection::<R>();
 
         match self.session {
             Some(ref session) => {
                 let mut session_guard = session.lock().await;
                 let session = session_guard.deref_mut();
-                Ok(coll.find_one_with_session(filter, None, session).await?)
+                Ok(coll
+                    .find_one_and_delete_with_session(query, options, session)
+                    .await?)
+            }
+            None => Ok(coll.find_one_and_delete(query, options).await?),
+        }
+    }
+
+    #[instrument(name = "MongoRepo::retrieve", skip_all, fields(collection = R::collection_name()))]
+    async fn retrieve(&self, id: &Id<R>) -> Result<Option<R>, Self::RepoError> {
+        let mut filter = R::Filter::default();
+        *filter.id_mut() = Some(id.clone());
+        let filter = to_document(&filter)?;
+        let options = FindOneOptions::builder()
+            .selection_criteria(self.selection_criteria(false))
+            .max_time(self.remaining_time()?)
+            .build();
+        let coll = self.collection::<R>();
+
+        self.before_deadline(async {
+            match self.session {
+                Some(ref session) => {
+                    let mut session_guard = session.lock().await;
+                    let session = session_guard.deref_mut();
+                    Ok(coll.find_one_with_session(filter, options, session).await?)
+                }
+                None => Ok(coll.find_one(filter, options).await?),
             }
-            None => Ok(coll.find_one(filter, None).await?),
+        })
+        .await
+    }
+
+    #[instrument(name = "MongoRepo::retrieve_many", skip_all, fields(collection = R::collection_name()))]
+    async fn retrieve_many(&self, ids: &[Id<R>]) -> Result<Vec<R>, Self::RepoError> {
+        if ids.is_empty() {
+            return Ok(vec![]);
         }
+        let ids = ids.iter().cloned().map(Bson::from).collect::<Vec<_>>();
+        self.find_documents(doc! { "_id": { "$in": ids } }).await
     }
 
+    #[instrument(name = "MongoRepo::retrieve_all", skip_all, fields(collection = R::collection_name()))]
     async fn retrieve_all(&self) -> Result<Vec<R>, Self::RepoError> {
         self.find_all(&R::Filter::default()).await
     }
 
+    #[instrument(name = "MongoRepo::retrieve_page", skip_all, fields(collection = R::collection_name()))]
     async fn retrieve_page(&self, offset: usize, limit: usize) -> Result<Vec<R>, Self::RepoError> {
         self.find_page(&R::Filter::default(), offset, limit).await
     }
 
+    #[instrument(name = "MongoRepo::find_all", skip_all, fields(collection = R::collection_name()))]
     async fn find_all(&self, filter: &R::Filter) -> Result<Vec<R>, Self::RepoError> {
-        let filter = to_document(filter)?;
-        let coll = self.collection::<R>();
-
-        match self.session {
-            Some(ref session) => {
-                let mut session_guard = session.lock().await;
-                let session = session_guard.deref_mut();
-                let mut cursor = coll.find_with_session(filter, None, session).await?;
-                let mut docs = vec![];
-                while let Some(doc) = cursor.next(session).await {
-                    docs.push(doc?);
-                }
-                Ok(docs)
-            }
-            None => {
-                let mut cursor = coll.find(filter, None).await?;
-                let mut docs = vec![];
-                while let Some(doc) = cursor.next().await {
-                    docs.push(doc?);
-                }
-                Ok(docs)
-            }
-        }
+        self.find_documents(to_filter_document(to_document(filter)?))
+            .await
     }
 
+    #[instrument(name = "MongoRepo::find_page", skip_all, fields(collection = R::collection_name()))]
     async fn find_page(
         &self,
         filter: &R::Filter,
         offset: usize,
         limit: usize,
     ) -> Result<Vec<R>, Self::RepoError> {
-        let filter = to_document(filter)?;
+        let filter = to_filter_document(to_document(filter)?);
         let options = FindOptions::builder()
             .skip(offset as u64)
             .limit(limit as i64)
+            .selection_criteria(self.selection_criteria(true))
+            .max_time(self.remaining_time()?)
             .build();
         let coll = self.collection::<R>();
 
-        match self.session {
-            Some(ref session) => {
-                let mut session_guard = session.lock().await;
-                let session = session_guard.deref_mut();
-                let mut cursor = coll.find_with_session(filter, options, session).await?;
-                let mut docs = vec![];
-                while let Some(doc) = cursor.next(session).await {
-                    docs.push(doc?);
+        self.before_deadline(async {
+            match self.session {
+                Some(ref session) => {
+                    let mut session_guard = session.lock().await;
+                    let session = session_guard.deref_mut();
+                    let mut cursor = coll.find_with_session(filter, options, session).await?;
+                    let mut docs = vec![];
+                    while let Some(doc) = cursor.next(session).await {
+                        docs.push(doc?);
+                    }
+                    Ok(docs)
+                }
+                None => {
+                    let mut cursor = coll.find(filter, options).await?;
+                    let mut docs = vec![];
+                    while let Some(doc) = cursor.next().await {
+                        docs.push(doc?);
+                    }
+                    Ok(docs)
                 }
-                Ok(docs)
             }
+        })
+        .await
+    }
+
+    #[instrument(name = "MongoRepo::facet", skip(self, filter), fields(collection = R::collection_name()))]
+    async fn facet<V>(
+        &self,
+        field: &str,
+        filter: &R::Filter,
+    ) -> Result<Vec<Facet<V>>, Self::RepoError>
+    where
+        V: DeserializeOwned + Send,
+    {
+        let pipeline = [
+            doc! { "$match": to_filter_document(to_document(filter)?) },
+            doc! { "$unwind": format!("${field}") },
+            doc! { "$group": { "_id": format!("${field}"), "count": { "$sum": 1 } } },
+            doc! { "$sort": { "count": -1, "_id": 1 } },
+        ];
+        let options = AggregateOptions::builder()
+            .selection_criteria(self.selection_criteria(true))
+            .max_time(self.remaining_time()?)
+            .build();
+        let coll = self.collection::<Document>();
+
+        let groups = self
+            .before_deadline(async {
+                match self.session {
+                    Some(ref session) => {
+                        let mut session_guard = session.lock().await;
+                        let session = session_guard.deref_mut();
+                        let mut cursor = coll
+                            .aggregate_with_session(pipeline, options, session)
+                            .await?
+                            .with_type::<FacetGroup<V>>();
+                        let mut groups = vec![];
+                        while let Some(group) = cursor.next(session).await {
+                            groups.push(group?);
+                        }
+                        Ok(groups)
+                    }
+                    None => {
+                        let mut cursor = coll
+                            .aggregate(pipeline, options)
+                            .await?
+                            .with_type::<FacetGroup<V>>();
+                        let mut groups = vec![];
+                        while let Some(group) = cursor.next().await {
+                            groups.push(group?);
+                        }
+                        Ok(groups)
+                    }
+                }
+            })
+            .await?;
+        Ok(groups
+            .into_iter()
+            .map(|group| Facet {
+                value: group.value,
+                count: group.count,
+            })
+            .collect())
+    }
+
+    #[instrument(name = "MongoRepo::find_near", skip(self, query, filter), fields(collection = R::collection_name()))]
+    async fn find_near(
+        &self,
+        field: &str,
+        query: &GeoQuery,
+        filter: &R::Filter,
+        limit: usize,
+    ) -> Result<Vec<Near<R>>, Self::RepoError> {
+        let mut filter = to_filter_document(to_document(filter)?);
+        let mut geo_near = doc! {
+            "near": to_bson(&query.near)?,
+            "distanceField": DISTANCE_FIELD,
+            "key": field,
+            "spherical": true,
+        };
+        match query.within {
+            GeoArea::Radius(meters) => {
+                geo_near.insert("maxDistance", meters);
+            }
+            GeoArea::Polygon(ref polygon) => {
+                filter.insert(
+                    field,
+                    doc! { "$geoWithin": { "$geometry": to_bson(polygon)? } },
+                );
+            }
+        }
+        geo_near.insert("query", filter);
+        let pipeline = [
+            doc! { "$geoNear": geo_near },
+            doc! { "$limit": limit as i64 },
+        ];
+        let options = AggregateOptions::builder()
+            .selection_criteria(self.selection_criteria(true))
+            .max_time(self.remaining_time()?)
+            .build();
+        let coll = self.collection::<Document>();
+
+        let docs = self
+            .before_deadline(async {
+                match self.session {
+                    Some(ref session) => {
+                        let mut session_guard = session.lock().await;
+                        let session = session_guard.deref_mut();
+                        let mut cursor = coll
+                            .aggregate_with_session(pipeline, options, session)
+                            .await?;
+                        let mut docs = vec![];
+                        while let Some(doc) = cursor.next(session).await {
+                            docs.push(doc?);
+                        }
+                        Ok(docs)
+                    }
+                    None => {
+                        let mut cursor = coll.aggregate(pipeline, options).await?;
+                        let mut docs = vec![];
+                        while let Some(doc) = cursor.next().await {
+                            docs.push(doc?);
+                        }
+                        Ok(docs)
+                    }
+                }
+            })
+            .await?;
+        docs.into_iter()
+            .map(|mut doc| {
+                let distance_meters = doc
+                    .remove(DISTANCE_FIELD)
+                    .and_then(|distance| distance.as_f64())
+                    .unwrap_or_default();
+                Ok(Near {
+                    entity: from_document(doc)?,
+                    distance_meters,
+                })
+            })
+            .collect()
+    }
+}
+
+/// A group of documents produced by the `facet` aggregation.
+#[derive(Deserialize)]
+struct FacetGroup<V> {
+    #[serde(rename = "_id")]
+    value: V,
+    count: u64,
+}
+
+/// Watches a change stream, opening it again after the last change seen if it is interrupted.
+struct ChangeWatch {
+    client: mongodb::Client,
+    pipeline: Vec<Document>,
+    change_stream: Option<ChangeStream<ChangeStreamEvent<Document>>>,
+    /// The token to watch after, once a change has been seen.
+    resume_token: Option<ResumeToken>,
+    /// Whether the last attempt to watch failed, so the next should wait before it is made.
+    failed: bool,
+}
+
+impl ChangeWatch {
+    /// Waits for the next change, opening the change stream first if it is not open.
+    ///
+    /// # Returns
+    /// `Some()` of the `_id` of the changed document, or `None` if the change had none, or the
+    /// change stream was closed and will be opened again
+    async fn next_key(&mut self) -> Result<Option<Bson>, MongoRepoError> {
+        let change_stream = match self.change_stream {
+            Some(ref mut change_stream) => change_stream,
             None => {
-                let mut cursor = coll.find(filter, options).await?;
-                let mut docs = vec![];
-                while let Some(doc) = cursor.next().await {
-                    docs.push(doc?);
+                if self.failed {
+                    tokio::time::sleep(WATCH_RETRY_DELAY).await;
                 }
-                Ok(docs)
+                let options = ChangeStreamOptions::builder()
+                    .start_after(self.resume_token.clone())
+                    .build();
+                let change_stream = match self.client.watch(self.pipeline.clone(), options).await {
+                    Ok(change_stream) => change_stream,
+                    Err(e) => {
+                        self.failed = true;
+                        return Err(e.into());
+                    }
+                };
+                self.failed = false;
+                self.change_stream.insert(change_stream)
+            }
+        };
+
+        let event = change_stream.next().await;
+        if let Some(resume_token) = change_stream.resume_token() {
+            self.resume_token = Some(resume_token);
+        }
+        match event {
+            Some(Ok(event)) => Ok(event.document_key.and_then(|key| key.get("_id").cloned())),
+            Some(Err(e)) => {
+                self.change_stream = None;
+                self.failed = true;
+                Err(e.into())
+            }
+            None => {
+                self.change_stream = None;
+                Ok(None)
             }
         }
     }
@@ -236,16 +1042,102 @@ where
     fn clone(&self) -> Self {
         Self {
             client: self.client.clone(),
+            namespace: self.namespace.clone(),
+            options: self.options.clone(),
+            deadline: self.deadline,
             session: self.session.clone(),
             _reposable: PhantomData,
         }
     }
 }
 
+/// How entities that are written in a batch are treated if they already exist.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum WriteMode {
+    /// Entities are inserted, failing for any that already exist.
+    Insert,
+    /// Entities are inserted, replacing any that already exist.
+    Upsert,
+}
+
+/// An error indicating a write mode could not be parsed from a string.
+#[derive(Debug, Clone)]
+pub struct InvalidWriteModeError;
+
+impl FromStr for WriteMode {
+    type Err = InvalidWriteModeError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "insert" => Ok(WriteMode::Insert),
+            "upsert" => Ok(WriteMode::Upsert),
+            _ => Err(InvalidWriteModeError),
+        }
+    }
+}
+
+impl Error for InvalidWriteModeError {}
+
+impl Display for InvalidWriteModeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "a write mode must be one of 'insert' or 'upsert'")
+    }
+}
+
+impl From<repo::ReturnDocument> for ReturnDocument {
+    fn from(return_document: repo::ReturnDocument) -> Self {
+        match return_document {
+            repo::ReturnDocument::Before => ReturnDocument::Before,
+            repo::ReturnDocument::After => ReturnDocument::After,
+        }
+    }
+}
+
+/// Returns a `2dsphere` index on each of the geo fields of a reposable type, named with
+/// `geo_index_name`.
+pub fn geo_indexes(fields: &[&str]) -> Vec<IndexModel> {
+    fields
+        .iter()
+        .map(|field| {
+            IndexModel::builder()
+                .keys(doc! { *field: "2dsphere" })
+                .options(IndexOptions::builder().name(geo_index_name(field)).build())
+                .build()
+        })
+        .collect()
+}
+
+/// Returns the name of the `2dsphere` index on a geo field.
+pub fn geo_index_name(field: &str) -> String {
+    format!("{field}_2dsphere")
+}
+
+fn is_namespace_exists_error(e: &mongodb::error::Error) -> bool {
+    matches!(
+        e.kind.as_ref(),
+        mongodb::error::ErrorKind::Command(command_error)
+            if command_error.code == NAMESPACE_EXISTS_ERROR_CODE
+    )
+}
+
 #[derive(Debug)]
 pub enum MongoRepoError {
-    MongoError(mongodb::error::ErrorKind),
+    MongoError(mongodb::error::Error),
     BsonSerError(mongodb::bson::ser::Error),
+    BsonDeError(mongodb::bson::de::Error),
+    Timeout,
+    /// An ID was not of the key type of the reposable type.
+    UnexpectedId(Bson),
+    /// A change would leave an entity breaking one of its invariants.
+    InvalidEntity(InvalidEntityError),
+    /// A response was already recorded for an idempotency key, by a request made concurrently
+    /// with the same key; retrying replays that response.
+    IdempotencyConflict,
+    /// A write was made, but its write concern was not satisfied, for the reason given.
+    WriteConcernFailed(String),
+    /// An operation was abandoned before it completed, such as a batch of loads whose fetch was
+    /// dropped; it can be retried.
+    Cancelled,
 }
 
 impl Error for MongoRepoError {}
@@ -255,13 +1147,122 @@ impl Display for MongoRepoError {
         match self {
             Self::MongoError(e) => write!(f, "MongoError({})", e),
             Self::BsonSerError(e) => write!(f, "BsonSerError({})", e),
+            Self::BsonDeError(e) => write!(f, "BsonDeError({})", e),
+            Self::Timeout => write!(f, "Timeout"),
+            Self::UnexpectedId(id) => write!(f, "UnexpectedId({})", id),
+            Self::InvalidEntity(e) => write!(f, "InvalidEntity({})", e),
+            Self::IdempotencyConflict => write!(f, "IdempotencyConflict"),
+            Self::WriteConcernFailed(message) => write!(f, "WriteConcernFailed({})", message),
+            Self::Cancelled => write!(f, "Cancelled"),
         }
     }
 }
 
+impl RetryableError for MongoRepoError {
+    fn is_transient(&self) -> bool {
+        use mongodb::error::{ErrorKind, RETRYABLE_WRITE_ERROR, TRANSIENT_TRANSACTION_ERROR};
+
+        match self {
+            Self::MongoError(e) => {
+                e.contains_label(RETRYABLE_WRITE_ERROR)
+                    || e.contains_label(TRANSIENT_TRANSACTION_ERROR)
+                    || match e.kind.as_ref() {
+                        ErrorKind::Io(_)
+                        | ErrorKind::ConnectionPoolCleared { .. }
+                        | ErrorKind::ServerSelection { .. } => true,
+                        ErrorKind::Command(command_error) => {
+                            TRANSIENT_ERROR_CODES.contains(&command_error.code)
+                        }
+                        _ => false,
+                    }
+            }
+            Self::BsonSerError(_)
+            | Self::BsonDeError(_)
+            | Self::Timeout
+            | Self::UnexpectedId(_)
+            | Self::InvalidEntity(_)
+            | Self::IdempotencyConflict
+            | Self::WriteConcernFailed(_)
+            | Self::Cancelled => false,
+        }
+    }
+
+    fn is_transient_transaction_error(&self) -> bool {
+        use mongodb::error::TRANSIENT_TRANSACTION_ERROR;
+
+        matches!(self, Self::MongoError(e) if e.contains_label(TRANSIENT_TRANSACTION_ERROR))
+    }
+
+    fn is_unknown_commit_result(&self) -> bool {
+        use mongodb::error::UNKNOWN_TRANSACTION_COMMIT_RESULT;
+
+        // a commit that timed out is reported as a timeout instead, and not retried
+        matches!(self, Self::MongoError(e) if e.contains_label(UNKNOWN_TRANSACTION_COMMIT_RESULT))
+    }
+}
+
+/// Maps a server error to `MongoRepoError::Timeout` if it indicates the operation ran out of
+/// time; a commit can report this as a write concern error as well as a command error, and a
+/// write whose write concern was not satisfied within its `wtimeout` as a write concern error.
 impl From<mongodb::error::Error> for MongoRepoError {
     fn from(e: mongodb::error::Error) -> Self {
-        MongoRepoError::MongoError(*e.kind)
+        use mongodb::error::{ErrorKind, WriteFailure};
+
+        match e.kind.as_ref() {
+            ErrorKind::Command(command_error)
+                if command_error.code == MAX_TIME_EXPIRED_ERROR_CODE =>
+            {
+                MongoRepoError::Timeout
+            }
+            ErrorKind::Write(WriteFailure::WriteConcernError(write_concern_error))
+                if write_concern_error.code == MAX_TIME_EXPIRED_ERROR_CODE
+                    || is_wtimeout(write_concern_error) =>
+            {
+                MongoRepoError::Timeout
+            }
+            _ => MongoRepoError::MongoError(e),
+        }
+    }
+}
+
+/// Maps a write concern error reported in a command's response to `MongoRepoError::Timeout` if it
+/// indicates the write ran out of time, like a write concern error reported by the driver.
+impl From<mongodb::error::WriteConcernError> for MongoRepoError {
+    fn from(e: mongodb::error::WriteConcernError) -> Self {
+        if e.code == MAX_TIME_EXPIRED_ERROR_CODE || is_wtimeout(&e) {
+            MongoRepoError::Timeout
+        } else {
+            MongoRepoError::WriteConcernFailed(e.message)
+        }
+    }
+}
+
+/// Returns whether a write concern error indicates the write concern was not satisfied within its
+/// `wtimeout`.
+fn is_wtimeout(write_concern_error: &mongodb::error::WriteConcernError) -> bool {
+    write_concern_error.code == WRITE_CONCERN_FAILED_ERROR_CODE
+        && write_concern_error
+            .details
+            .as_ref()
+            .and_then(|details| details.get_bool("wtimeout").ok())
+            .unwrap_or(false)
+}
+
+/// Bounds the time a write waits for its write concern to be satisfied to the provided time, or
+/// less if the write concern already allows less.
+fn bounded_write_concern(write_concern: Option<WriteConcern>, remaining: Duration) -> WriteConcern {
+    let mut write_concern = write_concern.unwrap_or_default();
+    write_concern.w_timeout = Some(
+        write_concern
+            .w_timeout
+            .map_or(remaining, |w_timeout| w_timeout.min(remaining)),
+    );
+    write_concern
+}
+
+impl From<InvalidEntityError> for MongoRepoError {
+    fn from(e: InvalidEntityError) -> Self {
+        MongoRepoError::InvalidEntity(e)
     }
 }
 
@@ -270,3 +1271,50 @@ impl From<mongodb::bson::ser::Error> for MongoRepoError {
         MongoRepoError::BsonSerError(e)
     }
 }
+
+impl From<mongodb::bson::de::Error> for MongoRepoError {
+    fn from(e: mongodb::bson::de::Error) -> Self {
+        MongoRepoError::BsonDeError(e)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use mongodb::options::Acknowledgment;
+
+    #[test]
+    fn write_concern_is_bounded_by_the_time_remaining() {
+        let bounded = bounded_write_concern(None, Duration::from_secs(5));
+        assert_eq!(bounded.w_timeout, Some(Duration::from_secs(5)));
+        assert_eq!(bounded.w, None);
+
+        let majority = WriteConcern::builder()
+            .w(Acknowledgment::Majority)
+            .w_timeout(Duration::from_secs(1))
+            .build();
+        let bounded = bounded_write_concern(Some(majority), Duration::from_secs(5));
+        assert_eq!(bounded.w_timeout, Some(Duration::from_secs(1)));
+        assert_eq!(bounded.w, Some(Acknowledgment::Majority));
+    }
+    #[test]
+    fn write_concern_errors_in_a_response_are_timeouts_only_if_they_timed_out() {
+        let wtimeout = doc! {
+            "code": WRITE_CONCERN_FAILED_ERROR_CODE,
+            "errmsg": "waiting for replication timed out",
+            "errInfo": { "wtimeout": true },
+        };
+        let error: mongodb::error::WriteConcernError = from_document(wtimeout).unwrap();
+        assert!(matches!(error.into(), MongoRepoError::Timeout));
+
+        let unsatisfiable = doc! {
+            "code": 100,
+            "errmsg": "Not enough data-bearing nodes",
+        };
+        let error: mongodb::error::WriteConcernError = from_document(unsatisfiable).unwrap();
+        assert!(matches!(
+            error.into(),
+            MongoRepoError::WriteConcernFailed(_)
+        ));
+    }
+}
@@ -0,0 +1,175 @@
+use crate::common::tenant::{TenantId, MAX_TENANT_ID_LEN};
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+
+/// The longest database name mongo accepts, in bytes.
+pub const MAX_DB_NAME_LEN: usize = 63;
+
+/// Resolves the database and collection names used by mongo repositories.
+///
+/// Each `MongoReposable` type declares a default database and collection name; a resolver allows
+/// those defaults to be overridden at runtime so that several environments (or several test runs)
+/// can share one cluster without sharing data. A resolver may also be scoped to a tenant, in which
+/// case every tenant gets a database of its own.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct NamespaceResolver {
+    db_name: Option<String>,
+    collection_prefix: Option<String>,
+    tenant_id: Option<TenantId>,
+}
+
+impl NamespaceResolver {
+    /// Creates a resolver that uses the defaults declared by each reposable type.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns this resolver configured to place every collection in the named database.
+    pub fn with_db_name(mut self, db_name: impl Into<String>) -> Self {
+        self.db_name = Some(db_name.into());
+        self
+    }
+
+    /// Returns this resolver configured to prefix every collection name with `prefix`.
+    pub fn with_collection_prefix(mut self, prefix: impl Into<String>) -> Self {
+        self.collection_prefix = Some(prefix.into());
+        self
+    }
+
+    /// Returns a copy of this resolver scoped to the provided tenant.
+    pub fn for_tenant(&self, tenant_id: TenantId) -> Self {
+        Self {
+            tenant_id: Some(tenant_id),
+            ..self.clone()
+        }
+    }
+
+    /// Returns the tenant this resolver is scoped to, if any.
+    pub fn tenant_id(&self) -> Option<&TenantId> {
+        self.tenant_id.as_ref()
+    }
+
+    /// Resolves the name of the database to use.
+    ///
+    /// # Arguments
+    /// * `default` - the database name declared by the reposable type
+    pub fn db_name(&self, default: &str) -> String {
+        let db_name = self.db_name.as_deref().unwrap_or(default);
+        match self.tenant_id {
+            Some(ref tenant_id) => format!("{db_name}_{tenant_id}"),
+            None => db_name.to_string(),
+        }
+    }
+
+    /// Checks that the database of every tenant, whatever its ID, has a name short enough for
+    /// mongo to accept.
+    ///
+    /// # Arguments
+    /// * `default` - the database name declared by the reposable type
+    pub fn check_tenant_db_name(&self, default: &str) -> Result<(), DbNameTooLongError> {
+        let base = self.db_name.as_deref().unwrap_or(default);
+        match base.len() + 1 + MAX_TENANT_ID_LEN <= MAX_DB_NAME_LEN {
+            true => Ok(()),
+            false => Err(DbNameTooLongError(base.to_string())),
+        }
+    }
+
+    /// Determines which tenant a database belongs to, if it is a tenant's database in this
+    /// namespace.
+    ///
+    /// # Arguments
+    /// * `db_name` - the name of the database
+    /// * `default` - the database name declared by the reposable type
+    pub fn tenant_of_db(&self, db_name: &str, default: &str) -> Option<TenantId> {
+        let base = self.db_name.as_deref().unwrap_or(default);
+        db_name.strip_prefix(base)?.strip_prefix('_')?.parse().ok()
+    }
+
+    /// Resolves the name of the collection to use.
+    ///
+    /// # Arguments
+    /// * `default` - the collection name declared by the reposable type
+    pub fn collection_name(&self, default: &str) -> String {
+        match self.collection_prefix {
+            Some(ref prefix) => format!("{prefix}{default}"),
+            None => default.to_string(),
+        }
+    }
+}
+
+/// An error indicating the name of a database is too long to hold a tenant's data.
+#[derive(Debug, Clone)]
+pub struct DbNameTooLongError(String);
+
+impl Error for DbNameTooLongError {}
+
+impl Display for DbNameTooLongError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "the database name {} is too long to be scoped to tenants; it can be at most {} bytes",
+            self.0,
+            MAX_DB_NAME_LEN - 1 - MAX_TENANT_ID_LEN
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn default_resolver_uses_declared_names() {
+        let resolver = NamespaceResolver::new();
+        assert_eq!(resolver.db_name("repotest"), "repotest");
+        assert_eq!(resolver.collection_name("items"), "items");
+    }
+
+    #[test]
+    fn configured_db_name_overrides_declared_name() {
+        let resolver = NamespaceResolver::new().with_db_name("staging");
+        assert_eq!(resolver.db_name("repotest"), "staging");
+        assert_eq!(resolver.collection_name("items"), "items");
+    }
+
+    #[test]
+    fn collection_prefix_is_prepended() {
+        let resolver = NamespaceResolver::new().with_collection_prefix("ci_");
+        assert_eq!(resolver.db_name("repotest"), "repotest");
+        assert_eq!(resolver.collection_name("items"), "ci_items");
+    }
+
+    #[test]
+    fn tenant_scoped_resolver_uses_tenant_database() {
+        let resolver = NamespaceResolver::new()
+            .with_db_name("production")
+            .with_collection_prefix("app_");
+        let acme = resolver.for_tenant("acme".parse().unwrap());
+        let globex = resolver.for_tenant("globex".parse().unwrap());
+
+        assert_eq!(acme.db_name("repotest"), "production_acme");
+        assert_eq!(globex.db_name("repotest"), "production_globex");
+        assert_eq!(acme.collection_name("items"), "app_items");
+    }
+
+    #[test]
+    fn tenant_database_names_must_fit_within_limit() {
+        let resolver = NamespaceResolver::new();
+        assert!(resolver.check_tenant_db_name("repotest").is_ok());
+        let longest = "a".repeat(MAX_DB_NAME_LEN - 1 - MAX_TENANT_ID_LEN);
+        assert!(resolver.check_tenant_db_name(&longest).is_ok());
+        let resolver = resolver.with_db_name(format!("{longest}a"));
+        assert!(resolver.check_tenant_db_name("repotest").is_err());
+    }
+    #[test]
+    fn tenant_is_determined_from_database_name() {
+        let resolver = NamespaceResolver::new().with_db_name("production");
+        assert_eq!(
+            resolver.tenant_of_db("production_acme", "repotest"),
+            Some("acme".parse().unwrap())
+        );
+        assert_eq!(resolver.tenant_of_db("production", "repotest"), None);
+        assert_eq!(resolver.tenant_of_db("productionacme", "repotest"), None);
+        assert_eq!(resolver.tenant_of_db("staging_acme", "repotest"), None);
+    }
+}
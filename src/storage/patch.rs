@@ -0,0 +1,326 @@
+use super::filter::{flatten_fields, FIELDS};
+use mongodb::bson::{doc, Bson, Document};
+use serde::{ser::Error, Serialize, Serializer};
+use std::collections::BTreeMap;
+
+const SET: &str = "$set";
+const UNSET: &str = "$unset";
+const INC: &str = "$inc";
+const PUSH: &str = "$push";
+const PULL: &str = "$pull";
+const ADD_TO_SET: &str = "$addToSet";
+
+/// The update operators a field of a serialized patch can be wrapped in.
+const OPERATORS: [&str; 6] = [SET, UNSET, INC, PUSH, PULL, ADD_TO_SET];
+
+/// A change to an optional field of an entity.
+///
+/// Fields of this type must be skipped when unchanged, i.e. annotated with
+/// `#[serde(skip_serializing_if = "FieldPatch::is_unchanged")]`.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub enum FieldPatch<T> {
+    /// The field is left as it is.
+    #[default]
+    Unchanged,
+    /// The field is set to a value.
+    Set(T),
+    /// The field is removed.
+    Clear,
+}
+
+impl<T> FieldPatch<T> {
+    pub fn is_unchanged(&self) -> bool {
+        matches!(self, Self::Unchanged)
+    }
+}
+
+impl<T: Serialize> Serialize for FieldPatch<T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            Self::Unchanged => Err(S::Error::custom("unchanged fields must be skipped")),
+            Self::Set(value) => Operation {
+                operator: SET,
+                value,
+            }
+            .serialize(serializer),
+            Self::Clear => Operation {
+                operator: UNSET,
+                value: "",
+            }
+            .serialize(serializer),
+        }
+    }
+}
+
+/// A change to a field of an entity that is made with a patch type other than `FieldPatch`.
+pub trait FieldUpdate {
+    /// Returns `true` if making the change twice leaves the field as making it once does, so
+    /// that an update whose outcome is unknown can safely be made again.
+    fn is_idempotent(&self) -> bool;
+}
+
+/// An amount to add to a numeric field of an entity; a negative amount is subtracted.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Increment<T>(pub T);
+
+impl<T: Serialize> Serialize for Increment<T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        Operation {
+            operator: INC,
+            value: &self.0,
+        }
+        .serialize(serializer)
+    }
+}
+
+impl<T> FieldUpdate for Increment<T> {
+    fn is_idempotent(&self) -> bool {
+        false
+    }
+}
+
+/// A change to the elements of an array field of an entity.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum ArrayPatch<T> {
+    /// The elements are appended to the array.
+    Push(Vec<T>),
+    /// Every occurrence of the elements is removed from the array.
+    Pull(Vec<T>),
+    /// Those elements not already in the array are appended to it.
+    AddToSet(Vec<T>),
+}
+
+impl<T: Serialize> Serialize for ArrayPatch<T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let (operator, modifier, values) = match self {
+            Self::Push(values) => (PUSH, "$each", values),
+            Self::Pull(values) => (PULL, "$in", values),
+            Self::AddToSet(values) => (ADD_TO_SET, "$each", values),
+        };
+        let value = Operation {
+            operator: modifier,
+            value: values,
+        };
+        Operation { operator, value }.serialize(serializer)
+    }
+}
+
+impl<T> FieldUpdate for ArrayPatch<T> {
+    fn is_idempotent(&self) -> bool {
+        !matches!(self, Self::Push(_))
+    }
+}
+
+/// A change to some of the entries of a map field of an entity, i.e. a subdocument whose keys are
+/// chosen by users; entries not in the patch are left as they are.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct MapPatch<K, V>(pub BTreeMap<K, FieldPatch<V>>);
+
+impl<K, V> Default for MapPatch<K, V> {
+    fn default() -> Self {
+        Self(BTreeMap::new())
+    }
+}
+
+impl<K: Serialize, V: Serialize> Serialize for MapPatch<K, V> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let value = ChangedEntries(&self.0);
+        Operation {
+            operator: FIELDS,
+            value,
+        }
+        .serialize(serializer)
+    }
+}
+
+impl<K, V> FieldUpdate for MapPatch<K, V> {
+    fn is_idempotent(&self) -> bool {
+        true
+    }
+}
+
+/// The entries of a map patch that change something.
+struct ChangedEntries<'a, K, V>(&'a BTreeMap<K, FieldPatch<V>>);
+
+impl<K: Serialize, V: Serialize> Serialize for ChangedEntries<'_, K, V> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_map(self.0.iter().filter(|(_, patch)| !patch.is_unchanged()))
+    }
+}
+
+/// A field of a patch serialized as the update operator to apply, and its argument; also used for
+/// the modifiers of array operators.
+struct Operation<V> {
+    operator: &'static str,
+    value: V,
+}
+
+impl<V: Serialize> Serialize for Operation<V> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::SerializeMap;
+
+        let mut map = serializer.serialize_map(Some(1))?;
+        map.serialize_entry(self.operator, &self.value)?;
+        map.end()
+    }
+}
+
+/// Compiles a serialized patch into a mongo update document. Fields serialized as an update
+/// operator (see `FieldPatch`, `Increment` and `ArrayPatch`) are grouped under that operator; any
+/// other field is set to its value. The entries of map patches (see `MapPatch`) are addressed by
+/// their dotted paths.
+pub fn to_update_document(patch: Document) -> Document {
+    let mut update = Document::new();
+    for (field, value) in flatten_fields(patch) {
+        let (operator, value) = match value {
+            Bson::Document(operation) if is_operation(&operation) => {
+                operation.into_iter().next().unwrap()
+            }
+            value => (SET.to_string(), value),
+        };
+        match update.get_document_mut(&operator) {
+            Ok(fields) => {
+                fields.insert(field, value);
+            }
+            Err(_) => {
+                update.insert(operator, doc! { field: value });
+            }
+        }
+    }
+    if update.is_empty() {
+        update.insert(SET, Document::new());
+    }
+    update
+}
+
+fn is_operation(doc: &Document) -> bool {
+    doc.len() == 1
+        && doc
+            .keys()
+            .next()
+            .is_some_and(|key| OPERATORS.contains(&key.as_str()))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use mongodb::bson::{to_bson, to_document};
+
+    #[derive(Default, Serialize)]
+    struct Patch {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        name: Option<String>,
+        #[serde(skip_serializing_if = "FieldPatch::is_unchanged")]
+        description: FieldPatch<String>,
+        #[serde(skip_serializing_if = "FieldPatch::is_unchanged")]
+        colour: FieldPatch<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        count: Option<Increment<i64>>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        tags: Option<ArrayPatch<String>>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        aliases: Option<ArrayPatch<String>>,
+    }
+
+    fn compile(patch: &Patch) -> Document {
+        to_update_document(to_document(patch).unwrap())
+    }
+
+    #[test]
+    fn plain_and_set_fields_are_set() {
+        let patch = Patch {
+            name: Some("widget".into()),
+            description: FieldPatch::Set("a widget".into()),
+            ..Default::default()
+        };
+        assert_eq!(
+            compile(&patch),
+            doc! { "$set": { "name": "widget", "description": "a widget" } }
+        );
+    }
+
+    #[test]
+    fn cleared_fields_are_unset() {
+        let patch = Patch {
+            description: FieldPatch::Clear,
+            colour: FieldPatch::Clear,
+            ..Default::default()
+        };
+        assert_eq!(
+            compile(&patch),
+            doc! { "$unset": { "description": "", "colour": "" } }
+        );
+    }
+
+    #[test]
+    fn operators_are_combined_into_one_update() {
+        let patch = Patch {
+            name: Some("widget".into()),
+            description: FieldPatch::Clear,
+            count: Some(Increment(-2)),
+            tags: Some(ArrayPatch::AddToSet(vec!["new".into()])),
+            aliases: Some(ArrayPatch::Pull(vec!["old".into()])),
+            ..Default::default()
+        };
+        assert_eq!(
+            compile(&patch),
+            doc! {
+                "$set": { "name": "widget" },
+                "$unset": { "description": "" },
+                "$inc": { "count": -2_i64 },
+                "$addToSet": { "tags": { "$each": ["new"] } },
+                "$pull": { "aliases": { "$in": ["old"] } },
+            }
+        );
+    }
+
+    #[test]
+    fn pushed_elements_are_appended() {
+        let patch = Patch {
+            tags: Some(ArrayPatch::Push(vec!["a".into(), "b".into()])),
+            ..Default::default()
+        };
+        assert_eq!(
+            compile(&patch),
+            doc! { "$push": { "tags": { "$each": ["a", "b"] } } }
+        );
+    }
+
+    #[test]
+    fn map_entries_are_set_and_unset_by_path() {
+        let mut entries = BTreeMap::new();
+        entries.insert("colour", FieldPatch::Set("red"));
+        entries.insert("shape", FieldPatch::Clear);
+        entries.insert("size", FieldPatch::Unchanged);
+        let patch = doc! {
+            "name": "widget",
+            "attributes": to_bson(&MapPatch(entries)).unwrap(),
+        };
+        assert_eq!(
+            to_update_document(patch),
+            doc! {
+                "$set": { "name": "widget", "attributes.colour": "red" },
+                "$unset": { "attributes.shape": "" },
+            }
+        );
+    }
+
+    #[test]
+    fn only_increments_and_pushes_are_not_idempotent() {
+        assert!(!Increment(1).is_idempotent());
+        assert!(!ArrayPatch::Push(vec!["a"]).is_idempotent());
+        assert!(ArrayPatch::AddToSet(vec!["a"]).is_idempotent());
+        assert!(ArrayPatch::Pull(vec!["a"]).is_idempotent());
+        assert!(MapPatch::<String, String>::default().is_idempotent());
+    }
+
+    #[test]
+    fn empty_patch_changes_nothing() {
+        assert_eq!(compile(&Patch::default()), doc! { "$set": {} });
+    }
+
+    #[test]
+    fn unchanged_fields_cannot_be_serialized() {
+        assert!(to_document(&FieldPatch::<String>::Unchanged).is_err());
+    }
+}
@@ -1,5 +1,10 @@
-use crate::common::{entity::Entity, id::Id};
+use crate::common::{
+    entity::Entity,
+    geo::{GeoPoint, GeoPolygon},
+    id::Id,
+};
 use async_trait::async_trait;
+use serde::de::DeserializeOwned;
 use std::error::Error;
 
 /// Defines an interface for repositories, i.e. collections of entities that allows
@@ -16,7 +21,16 @@ pub trait Repo<R: Reposable> {
     ///
     /// # Returns
     /// the ID of the newly created entity
-    async fn create(&self, spec: &R::Spec) -> Result<Id, Self::RepoError>;
+    async fn create(&self, spec: &R::Spec) -> Result<Id<R>, Self::RepoError>;
+
+    /// Creates a new entity in the repository, and returns it, without reading it back.
+    ///
+    /// # Arguments
+    /// * `spec` - a specification for the entity to create
+    ///
+    /// # Returns
+    /// the newly created entity, as it was stored
+    async fn create_and_get(&self, spec: &R::Spec) -> Result<R, Self::RepoError>;
 
     /// Updates an entity in the repository if it exists.
     ///
@@ -34,7 +48,48 @@ pub trait Repo<R: Reposable> {
     ///
     /// # Returns
     /// `true` if the entity existed and was deleted, `false` otherwise
-    async fn delete(&self, id: &Id) -> Result<bool, Self::RepoError>;
+    async fn delete(&self, id: &Id<R>) -> Result<bool, Self::RepoError>;
+
+    /// Updates an entity in the repository if it exists, and returns it, in a single operation.
+    ///
+    /// # Arguments
+    /// * `patch` - the patch to use to update the entity
+    /// * `return_document` - whether to return the entity as it was before or after the update
+    ///
+    /// # Returns
+    /// `Some()` of the entity if it existed, `None` otherwise
+    async fn update_and_get(
+        &self,
+        patch: &R::Patch,
+        return_document: ReturnDocument,
+    ) -> Result<Option<R>, Self::RepoError>;
+
+    /// Updates an entity in the repository if it exists and matches a condition, and returns it,
+    /// in a single operation; this allows an update to depend on the entity's current state, such
+    /// as only decrementing a quantity that would not become negative.
+    ///
+    /// # Arguments
+    /// * `patch` - the patch to use to update the entity
+    /// * `condition` - a filter the entity must match to be updated
+    /// * `return_document` - whether to return the entity as it was before or after the update
+    ///
+    /// # Returns
+    /// `Some()` of the entity if it existed and matched the condition, `None` otherwise
+    async fn update_and_get_if(
+        &self,
+        patch: &R::Patch,
+        condition: &R::Filter,
+        return_document: ReturnDocument,
+    ) -> Result<Option<R>, Self::RepoError>;
+
+    /// Deletes an entity from the repository if it exists, and returns it, in a single operation.
+    ///
+    /// # Arguments
+    /// * `id` - the ID of the entity to delete
+    ///
+    /// # Returns
+    /// `Some()` of the entity as it was when deleted if it existed, `None` otherwise
+    async fn delete_and_get(&self, id: &Id<R>) -> Result<Option<R>, Self::RepoError>;
 
     /// Retrieves an entity from the repository.
     ///
@@ -43,7 +98,16 @@ pub trait Repo<R: Reposable> {
     ///
     /// # Returns
     /// `Some()` of the entity if it existed, `None` otherwise
-    async fn retrieve(&self, id: &Id) -> Result<Option<R>, Self::RepoError>;
+    async fn retrieve(&self, id: &Id<R>) -> Result<Option<R>, Self::RepoError>;
+
+    /// Retrieves several entities from the repository in a single operation.
+    ///
+    /// # Arguments
+    /// * `ids` - the IDs of the entities to retrieve
+    ///
+    /// # Returns
+    /// a `Vec` of those entities that existed, in no particular order
+    async fn retrieve_many(&self, ids: &[Id<R>]) -> Result<Vec<R>, Self::RepoError>;
 
     /// Retrieves all entities from the repository.
     ///
@@ -87,23 +151,110 @@ pub trait Repo<R: Reposable> {
         offset: usize,
         limit: usize,
     ) -> Result<Vec<R>, Self::RepoError>;
+
+    /// Counts the entities that match the given filter by each value of one of their fields; an
+    /// entity whose field is an array is counted once for each of its elements, and an entity
+    /// without the field is not counted.
+    ///
+    /// # Arguments
+    /// * `field` - the name of the field to count the values of, as it is stored
+    /// * `filter` - the filter to use to find the entities to count
+    ///
+    /// # Returns
+    /// a `Vec` of every value of the field, with the number of matching entities having it, from
+    /// the most to the least common
+    async fn facet<V>(
+        &self,
+        field: &str,
+        filter: &R::Filter,
+    ) -> Result<Vec<Facet<V>>, Self::RepoError>
+    where
+        V: DeserializeOwned + Send;
+
+    /// Retrieves the entities that match the given filter and whose location lies within an area,
+    /// nearest first.
+    ///
+    /// # Arguments
+    /// * `field` - the name of the field holding the entities' locations, as it is stored; the
+    ///   field must be indexed for geospatial queries
+    /// * `query` - the point to measure distances from, and the area to search
+    /// * `filter` - the filter to use to find matching entities
+    /// * `limit` - the maximum number of entities to return
+    ///
+    /// # Returns
+    /// a `Vec` of the nearest matching entities, with their distances from the point
+    async fn find_near(
+        &self,
+        field: &str,
+        query: &GeoQuery,
+        filter: &R::Filter,
+        limit: usize,
+    ) -> Result<Vec<Near<R>>, Self::RepoError>;
+}
+
+/// A value of a field of an entity, and the number of entities with it.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Facet<V> {
+    pub value: V,
+    pub count: u64,
+}
+
+/// A search for entities near a point.
+#[derive(Clone, Debug, PartialEq)]
+pub struct GeoQuery {
+    /// The point distances are measured from.
+    pub near: GeoPoint,
+    /// The area the entities must be located in.
+    pub within: GeoArea,
+}
+
+/// An area of the earth to search for entities in.
+#[derive(Clone, Debug, PartialEq)]
+pub enum GeoArea {
+    /// Within the given distance of the point searched near, in meters.
+    Radius(f64),
+    /// Within the polygon, which need not contain the point searched near.
+    Polygon(GeoPolygon),
+}
+
+/// An entity, and its distance from the point it was searched near.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Near<R> {
+    pub entity: R,
+    pub distance_meters: f64,
+}
+
+/// Which version of an entity an operation that changes it returns.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ReturnDocument {
+    /// The entity as it was before the change.
+    Before,
+    /// The entity as it is after the change.
+    After,
 }
 
 /// A thing that can be reposed in a repository.
 pub trait Reposable: Entity {
     type Spec;
-    type Patch: Patch;
-    type Filter: Filter;
+    type Patch: Patch<Self>;
+    type Filter: Filter<Self>;
 }
 
-/// A thing that can patch update a reposable thing stored in a repository.
-pub trait Patch {
+/// A thing that can patch update a reposable thing of type `E` stored in a repository.
+///
+/// Fields that are present are set; see `storage::patch` for fields that can be cleared,
+/// incremented, or have elements added to or removed from them.
+pub trait Patch<E: Entity> {
     /// Returns the ID of the thing for which this patch is an update.
-    fn id(&self) -> &Id;
+    fn id(&self) -> &Id<E>;
+
+    /// Returns `true` if applying this patch twice leaves the thing as applying it once does;
+    /// patches that increment fields or push elements onto arrays are not idempotent.
+    fn is_idempotent(&self) -> bool;
 }
 
-/// A thing that can filter reposable things stored in a repository.
-pub trait Filter: Default {
+/// A thing that can filter reposable things of type `E` stored in a repository.
+pub trait Filter<E: Entity>: Default {
     /// Modifies this filter to filter for the thing or things with the provided identity.
-    fn id_mut(&mut self) -> &mut Option<Id>;
+    fn id_mut(&mut self) -> &mut Option<Id<E>>;
 }
@@ -0,0 +1,603 @@
+use crate::common::id::Id;
+use crate::storage::repo::{Facet, GeoQuery, Near, Patch, Repo, Reposable, ReturnDocument};
+use async_trait::async_trait;
+use futures::Future;
+use rand::Rng;
+use serde::de::DeserializeOwned;
+use std::error::Error;
+use std::fmt::Display;
+use std::marker::PhantomData;
+use std::time::Duration;
+use tracing::warn;
+
+pub const DEFAULT_MAX_ATTEMPTS: u32 = 3;
+pub const DEFAULT_INITIAL_BACKOFF: Duration = Duration::from_millis(50);
+pub const DEFAULT_MAX_BACKOFF: Duration = Duration::from_secs(2);
+
+/// An error that can tell whether the operation that produced it might succeed if it were simply
+/// attempted again.
+pub trait RetryableError: Error {
+    /// Returns `true` if the error was caused by a transient condition, such as a network blip or
+    /// a change of primary.
+    fn is_transient(&self) -> bool;
+
+    /// Returns `true` if the error aborted a transaction, which might succeed if it were run again
+    /// from the start.
+    fn is_transient_transaction_error(&self) -> bool {
+        false
+    }
+
+    /// Returns `true` if it is not known whether a transaction committed, so committing it again
+    /// might succeed.
+    fn is_unknown_commit_result(&self) -> bool {
+        false
+    }
+}
+
+/// Determines how many times, and how quickly, failed operations are attempted again.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct RetryPolicy {
+    max_attempts: u32,
+    initial_backoff: Duration,
+    max_backoff: Duration,
+}
+
+impl RetryPolicy {
+    /// Creates a retry policy.
+    ///
+    /// # Arguments
+    /// * `max_attempts` - the maximum number of times to attempt an operation, including the first
+    /// * `initial_backoff` - the upper bound of the delay before the first retry; this doubles for
+    ///   each subsequent retry
+    /// * `max_backoff` - the upper bound of the delay before any retry
+    pub fn new(max_attempts: u32, initial_backoff: Duration, max_backoff: Duration) -> Self {
+        Self {
+            max_attempts: max_attempts.max(1),
+            initial_backoff,
+            max_backoff,
+        }
+    }
+
+    /// Creates a retry policy under which operations are only ever attempted once.
+    pub fn none() -> Self {
+        Self::new(1, Duration::ZERO, Duration::ZERO)
+    }
+
+    /// Returns the maximum number of times to attempt an operation, including the first.
+    pub fn max_attempts(&self) -> u32 {
+        self.max_attempts
+    }
+
+    /// Returns the upper bound of the delay before the provided retry, numbered from `1`.
+    fn backoff_ceiling(&self, retry: u32) -> Duration {
+        let factor = 2u32.saturating_pow(retry.saturating_sub(1));
+        self.initial_backoff
+            .saturating_mul(factor)
+            .min(self.max_backoff)
+    }
+
+    /// Returns a randomly "jittered" delay before the provided retry, numbered from `1`, so that
+    /// many clients retrying at once do not all hit the server at the same moments.
+    fn backoff(&self, retry: u32) -> Duration {
+        let ceiling = self.backoff_ceiling(retry);
+        ceiling.mul_f64(rand::thread_rng().gen_range(0.0..=1.0))
+    }
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self::new(
+            DEFAULT_MAX_ATTEMPTS,
+            DEFAULT_INITIAL_BACKOFF,
+            DEFAULT_MAX_BACKOFF,
+        )
+    }
+}
+
+/// Attempts an operation until it succeeds, fails with an error that should not be retried, or
+/// has been attempted as many times as a retry policy allows.
+///
+/// # Arguments
+/// * `policy` - how many times, and how quickly, to attempt the operation
+/// * `name` - the name of the operation, to log retries with
+/// * `should_retry` - whether an error should be retried
+/// * `operation` - called to start each attempt
+pub async fn retry<T, E, F>(
+    policy: &RetryPolicy,
+    name: &str,
+    should_retry: impl Fn(&E) -> bool,
+    mut operation: impl FnMut() -> F,
+) -> Result<T, E>
+where
+    E: Display,
+    F: Future<Output = Result<T, E>>,
+{
+    let mut attempt = 1;
+    loop {
+        match operation().await {
+            Err(e) if should_retry(&e) && attempt < policy.max_attempts => {
+                let backoff = policy.backoff(attempt);
+                warn!(
+                    "{} failed on attempt {} of {}; retrying in {:?}: {}",
+                    name, attempt, policy.max_attempts, backoff, e
+                );
+                tokio::time::sleep(backoff).await;
+                attempt += 1;
+            }
+            result => return result,
+        }
+    }
+}
+
+/// A repository decorator that retries reads and idempotent writes that fail with transient
+/// errors, according to a `RetryPolicy`.
+///
+/// Creations are never retried, since a creation whose outcome is unknown may already have
+/// succeeded; nor are updates with patches that are not idempotent (see `Patch::is_idempotent`),
+/// since an increment or push whose outcome is unknown may already have been applied. Operations
+/// within a transaction should not be retried individually either: a transient error aborts the
+/// whole transaction, so use `RetryPolicy::none()` there and run the transaction again instead.
+pub struct RetryingRepo<R, Inner: Repo<R>>
+where
+    R: Reposable,
+{
+    inner: Inner,
+    policy: RetryPolicy,
+    _reposable: PhantomData<R>,
+}
+
+impl<R, Inner: Repo<R>> RetryingRepo<R, Inner>
+where
+    R: Reposable,
+    Inner::RepoError: RetryableError,
+{
+    pub fn new(inner: Inner, policy: RetryPolicy) -> Self {
+        Self {
+            inner,
+            policy,
+            _reposable: PhantomData,
+        }
+    }
+
+    async fn retry<'a, T, F>(
+        &'a self,
+        method: &str,
+        operation: impl Fn(&'a Inner) -> F,
+    ) -> Result<T, Inner::RepoError>
+    where
+        F: Future<Output = Result<T, Inner::RepoError>>,
+    {
+        retry(&self.policy, method, RetryableError::is_transient, || {
+            operation(&self.inner)
+        })
+        .await
+    }
+}
+
+#[async_trait]
+impl<R, Inner: Repo<R>> Repo<R> for RetryingRepo<R, Inner>
+where
+    R: Reposable + Send + Sync,
+    R::Spec: Sync,
+    R::Patch: Sync,
+    R::Filter: Sync,
+    Inner: Send + Sync,
+    Inner::RepoError: RetryableError + Send,
+{
+    type RepoError = Inner::RepoError;
+
+    async fn create(&self, spec: &R::Spec) -> Result<Id<R>, Self::RepoError> {
+        self.inner.create(spec).await
+    }
+
+    async fn create_and_get(&self, spec: &R::Spec) -> Result<R, Self::RepoError> {
+        self.inner.create_and_get(spec).await
+    }
+
+    async fn update(&self, patch: &R::Patch) -> Result<bool, Self::RepoError> {
+        if !patch.is_idempotent() {
+            return self.inner.update(patch).await;
+        }
+        self.retry("update", |inner| inner.update(patch)).await
+    }
+
+    async fn delete(&self, id: &Id<R>) -> Result<bool, Self::RepoError> {
+        self.retry("delete", |inner| inner.delete(id)).await
+    }
+
+    async fn update_and_get(
+        &self,
+        patch: &R::Patch,
+        return_document: ReturnDocument,
+    ) -> Result<Option<R>, Self::RepoError> {
+        if !patch.is_idempotent() {
+            return self.inner.update_and_get(patch, return_document).await;
+        }
+        self.retry("update_and_get", |inner| {
+            inner.update_and_get(patch, return_document)
+        })
+        .await
+    }
+
+    async fn update_and_get_if(
+        &self,
+        patch: &R::Patch,
+        condition: &R::Filter,
+        return_document: ReturnDocument,
+    ) -> Result<Option<R>, Self::RepoError> {
+        if !patch.is_idempotent() {
+            return self
+                .inner
+                .update_and_get_if(patch, condition, return_document)
+                .await;
+        }
+        self.retry("update_and_get_if", |inner| {
+            inner.update_and_get_if(patch, condition, return_document)
+        })
+        .await
+    }
+
+    async fn delete_and_get(&self, id: &Id<R>) -> Result<Option<R>, Self::RepoError> {
+        self.retry("delete_and_get", |inner| inner.delete_and_get(id))
+            .await
+    }
+
+    async fn retrieve(&self, id: &Id<R>) -> Result<Option<R>, Self::RepoError> {
+        self.retry("retrieve", |inner| inner.retrieve(id)).await
+    }
+
+    async fn retrieve_many(&self, ids: &[Id<R>]) -> Result<Vec<R>, Self::RepoError> {
+        self.retry("retrieve_many", |inner| inner.retrieve_many(ids))
+            .await
+    }
+
+    async fn retrieve_all(&self) -> Result<Vec<R>, Self::RepoError> {
+        self.retry("retrieve_all", |inner| inner.retrieve_all())
+            .await
+    }
+
+    async fn retrieve_page(&self, offset: usize, limit: usize) -> Result<Vec<R>, Self::RepoError> {
+        self.retry("retrieve_page", |inner| inner.retrieve_page(offset, limit))
+            .await
+    }
+
+    async fn find_all(&self, filter: &R::Filter) -> Result<Vec<R>, Self::RepoError> {
+        self.retry("find_all", |inner| inner.find_all(filter)).await
+    }
+
+    async fn find_page(
+        &self,
+        filter: &R::Filter,
+        offset: usize,
+        limit: usize,
+    ) -> Result<Vec<R>, Self::RepoError> {
+        self.retry("find_page", |inner| inner.find_page(filter, offset, limit))
+            .await
+    }
+
+    async fn facet<V>(
+        &self,
+        field: &str,
+        filter: &R::Filter,
+    ) -> Result<Vec<Facet<V>>, Self::RepoError>
+    where
+        V: DeserializeOwned + Send,
+    {
+        self.retry("facet", |inner| inner.facet(field, filter))
+            .await
+    }
+
+    async fn find_near(
+        &self,
+        field: &str,
+        query: &GeoQuery,
+        filter: &R::Filter,
+        limit: usize,
+    ) -> Result<Vec<Near<R>>, Self::RepoError> {
+        self.retry("find_near", |inner| {
+            inner.find_near(field, query, filter, limit)
+        })
+        .await
+    }
+}
+
+impl<R, Inner: Repo<R>> Clone for RetryingRepo<R, Inner>
+where
+    R: Reposable,
+    Inner: Clone,
+{
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+            policy: self.policy,
+            _reposable: PhantomData,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::common::entity::Entity;
+    use crate::storage::repo::Filter;
+    use mongodb::bson::oid::ObjectId;
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    struct Thing {
+        id: Id<Thing>,
+    }
+
+    impl Entity for Thing {
+        type Key = ObjectId;
+
+        fn id(&self) -> &Id<Thing> {
+            &self.id
+        }
+    }
+
+    impl Reposable for Thing {
+        type Spec = ();
+        type Patch = ThingPatch;
+        type Filter = ThingFilter;
+    }
+
+    struct ThingPatch {
+        id: Id<Thing>,
+        idempotent: bool,
+    }
+
+    impl Patch<Thing> for ThingPatch {
+        fn id(&self) -> &Id<Thing> {
+            &self.id
+        }
+
+        fn is_idempotent(&self) -> bool {
+            self.idempotent
+        }
+    }
+
+    #[derive(Default)]
+    struct ThingFilter {
+        id: Option<Id<Thing>>,
+    }
+
+    impl Filter<Thing> for ThingFilter {
+        fn id_mut(&mut self) -> &mut Option<Id<Thing>> {
+            &mut self.id
+        }
+    }
+
+    #[derive(Debug)]
+    struct FakeError {
+        transient: bool,
+    }
+
+    impl Error for FakeError {}
+
+    impl Display for FakeError {
+        fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+            write!(f, "FakeError(transient: {})", self.transient)
+        }
+    }
+
+    impl RetryableError for FakeError {
+        fn is_transient(&self) -> bool {
+            self.transient
+        }
+    }
+
+    /// A repository whose operations fail a number of times before they succeed, counting the
+    /// attempts made.
+    struct FailingRepo {
+        failures: AtomicU32,
+        transient: bool,
+        attempts: AtomicU32,
+    }
+
+    impl FailingRepo {
+        fn new(failures: u32, transient: bool) -> Self {
+            Self {
+                failures: AtomicU32::new(failures),
+                transient,
+                attempts: AtomicU32::new(0),
+            }
+        }
+
+        fn attempt<T>(&self, result: T) -> Result<T, FakeError> {
+            self.attempts.fetch_add(1, Ordering::SeqCst);
+            let failures = self.failures.load(Ordering::SeqCst);
+            if failures > 0 {
+                self.failures.store(failures - 1, Ordering::SeqCst);
+                Err(FakeError {
+                    transient: self.transient,
+                })
+            } else {
+                Ok(result)
+            }
+        }
+    }
+
+    #[async_trait]
+    impl Repo<Thing> for FailingRepo {
+        type RepoError = FakeError;
+
+        async fn create(&self, _spec: &()) -> Result<Id<Thing>, Self::RepoError> {
+            self.attempt(ObjectId::new().into())
+        }
+
+        async fn create_and_get(&self, _spec: &()) -> Result<Thing, Self::RepoError> {
+            self.attempt(Thing {
+                id: ObjectId::new().into(),
+            })
+        }
+
+        async fn update(&self, _patch: &ThingPatch) -> Result<bool, Self::RepoError> {
+            self.attempt(true)
+        }
+
+        async fn delete(&self, _id: &Id<Thing>) -> Result<bool, Self::RepoError> {
+            self.attempt(true)
+        }
+
+        async fn update_and_get(
+            &self,
+            _patch: &ThingPatch,
+            _return_document: ReturnDocument,
+        ) -> Result<Option<Thing>, Self::RepoError> {
+            self.attempt(None)
+        }
+
+        async fn update_and_get_if(
+            &self,
+            _patch: &ThingPatch,
+            _condition: &ThingFilter,
+            _return_document: ReturnDocument,
+        ) -> Result<Option<Thing>, Self::RepoError> {
+            self.attempt(None)
+        }
+
+        async fn delete_and_get(&self, _id: &Id<Thing>) -> Result<Option<Thing>, Self::RepoError> {
+            self.attempt(None)
+        }
+
+        async fn retrieve(&self, _id: &Id<Thing>) -> Result<Option<Thing>, Self::RepoError> {
+            self.attempt(None)
+        }
+
+        async fn retrieve_many(&self, _ids: &[Id<Thing>]) -> Result<Vec<Thing>, Self::RepoError> {
+            self.attempt(vec![])
+        }
+
+        async fn retrieve_all(&self) -> Result<Vec<Thing>, Self::RepoError> {
+            self.attempt(vec![])
+        }
+
+        async fn retrieve_page(
+            &self,
+            _offset: usize,
+            _limit: usize,
+        ) -> Result<Vec<Thing>, Self::RepoError> {
+            self.attempt(vec![])
+        }
+
+        async fn find_all(&self, _filter: &ThingFilter) -> Result<Vec<Thing>, Self::RepoError> {
+            self.attempt(vec![])
+        }
+
+        async fn find_page(
+            &self,
+            _filter: &ThingFilter,
+            _offset: usize,
+            _limit: usize,
+        ) -> Result<Vec<Thing>, Self::RepoError> {
+            self.attempt(vec![])
+        }
+
+        async fn facet<V>(
+            &self,
+            _field: &str,
+            _filter: &ThingFilter,
+        ) -> Result<Vec<Facet<V>>, Self::RepoError>
+        where
+            V: DeserializeOwned + Send,
+        {
+            self.attempt(vec![])
+        }
+
+        async fn find_near(
+            &self,
+            _field: &str,
+            _query: &GeoQuery,
+            _filter: &ThingFilter,
+            _limit: usize,
+        ) -> Result<Vec<Near<Thing>>, Self::RepoError> {
+            self.attempt(vec![])
+        }
+    }
+
+    fn retrying(failures: u32, transient: bool) -> RetryingRepo<Thing, FailingRepo> {
+        RetryingRepo::new(
+            FailingRepo::new(failures, transient),
+            RetryPolicy::new(3, Duration::ZERO, Duration::ZERO),
+        )
+    }
+
+    fn attempts(repo: &RetryingRepo<Thing, FailingRepo>) -> u32 {
+        repo.inner.attempts.load(Ordering::SeqCst)
+    }
+
+    fn patch(idempotent: bool) -> ThingPatch {
+        ThingPatch {
+            id: ObjectId::new().into(),
+            idempotent,
+        }
+    }
+
+    #[tokio::test]
+    async fn transient_errors_are_retried_until_success() {
+        let repo = retrying(2, true);
+        assert!(repo.retrieve(&ObjectId::new().into()).await.is_ok());
+        assert_eq!(attempts(&repo), 3);
+    }
+
+    #[tokio::test]
+    async fn retrying_stops_after_max_attempts() {
+        let repo = retrying(5, true);
+        assert!(repo.delete(&ObjectId::new().into()).await.is_err());
+        assert_eq!(attempts(&repo), 3);
+    }
+
+    #[tokio::test]
+    async fn errors_that_are_not_transient_are_not_retried() {
+        let repo = retrying(1, false);
+        assert!(repo.find_all(&ThingFilter::default()).await.is_err());
+        assert_eq!(attempts(&repo), 1);
+    }
+
+    #[tokio::test]
+    async fn creations_are_never_retried() {
+        let repo = retrying(1, true);
+        assert!(repo.create(&()).await.is_err());
+        assert_eq!(attempts(&repo), 1);
+
+        let repo = retrying(1, true);
+        assert!(repo.create_and_get(&()).await.is_err());
+        assert_eq!(attempts(&repo), 1);
+    }
+
+    #[tokio::test]
+    async fn only_idempotent_updates_are_retried() {
+        let repo = retrying(1, true);
+        assert!(repo.update(&patch(false)).await.is_err());
+        assert_eq!(attempts(&repo), 1);
+
+        let repo = retrying(1, true);
+        assert!(repo.update(&patch(true)).await.is_ok());
+        assert_eq!(attempts(&repo), 2);
+    }
+
+    #[test]
+    fn backoff_doubles_until_capped() {
+        let policy = RetryPolicy::new(10, Duration::from_millis(100), Duration::from_millis(500));
+        assert_eq!(policy.backoff_ceiling(1), Duration::from_millis(100));
+        assert_eq!(policy.backoff_ceiling(2), Duration::from_millis(200));
+        assert_eq!(policy.backoff_ceiling(3), Duration::from_millis(400));
+        assert_eq!(policy.backoff_ceiling(4), Duration::from_millis(500));
+        assert_eq!(policy.backoff_ceiling(40), Duration::from_millis(500));
+    }
+
+    #[test]
+    fn jittered_backoff_does_not_exceed_ceiling() {
+        let policy = RetryPolicy::default();
+        for retry in 1..10 {
+            assert!(policy.backoff(retry) <= policy.backoff_ceiling(retry));
+        }
+    }
+
+    #[test]
+    fn policy_always_allows_one_attempt() {
+        let policy = RetryPolicy::new(0, Duration::ZERO, Duration::ZERO);
+        assert_eq!(policy.max_attempts, 1);
+        assert_eq!(RetryPolicy::none().max_attempts, 1);
+    }
+}
@@ -0,0 +1,261 @@
+use super::mongo_repo::{MongoRepo, MongoRepoError, MongoReposable, WriteMode};
+use mongodb::bson::{self, ser::to_document, Bson, Document};
+use serde::{de::DeserializeOwned, Serialize};
+use std::{
+    error::Error,
+    fmt::{Display, Formatter},
+    io::{BufRead, Write},
+    str::FromStr,
+};
+
+pub const DEFAULT_BATCH_SIZE: usize = 500;
+
+/// The format in which entities are exported, one entity per line.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ExportFormat {
+    /// Relaxed Extended JSON, i.e. plain JSON wherever a value can be represented by it.
+    Ndjson,
+    /// Canonical Extended JSON, which preserves the type of every value.
+    Ejson,
+}
+
+/// An error indicating an export format could not be parsed from a string.
+#[derive(Debug, Clone)]
+pub struct InvalidExportFormatError;
+
+impl FromStr for ExportFormat {
+    type Err = InvalidExportFormatError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "ndjson" => Ok(ExportFormat::Ndjson),
+            "ejson" => Ok(ExportFormat::Ejson),
+            _ => Err(InvalidExportFormatError),
+        }
+    }
+}
+
+impl Error for InvalidExportFormatError {}
+
+impl Display for InvalidExportFormatError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "an export format must be one of 'ndjson' or 'ejson'")
+    }
+}
+
+/// The progress of an import.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct ImportProgress {
+    /// The number of records read.
+    pub read: usize,
+    /// The number of records written.
+    pub written: usize,
+    /// The number of records that were invalid or could not be written.
+    pub failed: usize,
+}
+
+/// Exports every entity matching a filter, as of a single point in time.
+///
+/// # Arguments
+/// * `repo` - the repository to export from
+/// * `filter` - a mongo query document selecting the entities to export
+/// * `format` - the format to write entities in
+/// * `output` - where to write entities, one per line
+///
+/// # Returns
+/// the number of entities exported
+pub async fn export<R>(
+    repo: &MongoRepo<R>,
+    filter: Document,
+    format: ExportFormat,
+    output: &mut impl Write,
+) -> Result<usize, TransferError>
+where
+    R: MongoReposable + Serialize,
+    R::Spec: Serialize,
+    R::Patch: Serialize,
+    R::Filter: Serialize,
+{
+    let count = repo
+        .visit_snapshot(filter, |entity| {
+            writeln!(output, "{}", encode(&entity, format)?).map_err(TransferError::Io)
+        })
+        .await?;
+    output.flush()?;
+    Ok(count)
+}
+
+/// Imports entities, one per line in either export format, validating each as it is read.
+///
+/// # Arguments
+/// * `repo` - the repository to import into
+/// * `input` - where to read entities from
+/// * `mode` - how to treat entities that already exist
+/// * `batch_size` - the number of entities to write at once
+/// * `errors` - where to write a line for each record that could not be imported, with the
+///   record's line number, the reason and the record itself
+/// * `report` - called with the progress of the import after each batch
+///
+/// # Returns
+/// the final progress of the import
+pub async fn import<R>(
+    repo: &MongoRepo<R>,
+    input: impl BufRead,
+    mode: WriteMode,
+    batch_size: usize,
+    errors: &mut impl Write,
+    mut report: impl FnMut(&ImportProgress),
+) -> Result<ImportProgress, TransferError>
+where
+    R: MongoReposable + Serialize,
+    R::Spec: Serialize,
+    R::Patch: Serialize,
+    R::Filter: Serialize,
+{
+    let batch_size = batch_size.max(1);
+    let mut progress = ImportProgress::default();
+    let mut batch = Vec::with_capacity(batch_size);
+    let mut batch_records = Vec::with_capacity(batch_size);
+    let mut lines = input.lines().enumerate().peekable();
+
+    while let Some((index, line)) = lines.next() {
+        let line = line?;
+        if !line.trim().is_empty() {
+            progress.read += 1;
+            match decode::<R>(&line) {
+                Ok(entity) => {
+                    batch.push(entity);
+                    batch_records.push((index + 1, line));
+                }
+                Err(e) => {
+                    write_error(errors, index + 1, &e, &line)?;
+                    progress.failed += 1;
+                }
+            }
+        }
+
+        if batch.len() >= batch_size || (lines.peek().is_none() && !batch.is_empty()) {
+            let write_errors = repo.write_batch(&batch, mode).await?;
+            for (batch_index, message) in &write_errors {
+                if let Some((line_number, line)) = batch_records.get(*batch_index) {
+                    write_error(errors, *line_number, message, line)?;
+                }
+            }
+            progress.written += batch.len() - write_errors.len();
+            progress.failed += write_errors.len();
+            batch.clear();
+            batch_records.clear();
+            report(&progress);
+        }
+    }
+
+    errors.flush()?;
+    Ok(progress)
+}
+
+/// Encodes an entity as a single line of Extended JSON.
+fn encode<R: Serialize>(entity: &R, format: ExportFormat) -> Result<String, TransferError> {
+    let doc = Bson::Document(to_document(entity)?);
+    let json = match format {
+        ExportFormat::Ndjson => doc.into_relaxed_extjson(),
+        ExportFormat::Ejson => doc.into_canonical_extjson(),
+    };
+    Ok(json.to_string())
+}
+
+/// Decodes an entity from a single line of Extended JSON, in either relaxed or canonical form.
+fn decode<R: DeserializeOwned>(line: &str) -> Result<R, String> {
+    let json = serde_json::from_str::<serde_json::Value>(line).map_err(|e| e.to_string())?;
+    match Bson::try_from(json).map_err(|e| e.to_string())? {
+        Bson::Document(doc) => bson::from_document(doc).map_err(|e| e.to_string()),
+        _ => Err(String::from("a record must be a JSON object")),
+    }
+}
+
+fn write_error(
+    errors: &mut impl Write,
+    line_number: usize,
+    message: &str,
+    line: &str,
+) -> Result<(), TransferError> {
+    let error = serde_json::json!({ "line": line_number, "error": message, "record": line });
+    writeln!(errors, "{}", error)?;
+    Ok(())
+}
+
+#[derive(Debug)]
+pub enum TransferError {
+    RepoError(MongoRepoError),
+    Io(std::io::Error),
+}
+
+impl Error for TransferError {}
+
+impl Display for TransferError {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        match self {
+            Self::RepoError(e) => write!(f, "RepoError({})", e),
+            Self::Io(e) => write!(f, "Io({})", e),
+        }
+    }
+}
+
+impl From<MongoRepoError> for TransferError {
+    fn from(e: MongoRepoError) -> Self {
+        TransferError::RepoError(e)
+    }
+}
+
+impl From<mongodb::bson::ser::Error> for TransferError {
+    fn from(e: mongodb::bson::ser::Error) -> Self {
+        TransferError::RepoError(e.into())
+    }
+}
+
+impl From<std::io::Error> for TransferError {
+    fn from(e: std::io::Error) -> Self {
+        TransferError::Io(e)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        common::id::Id,
+        domain::models::items::{Item, ItemSize},
+    };
+    use mongodb::bson::oid::ObjectId;
+
+    fn item() -> Item {
+        let id: Id<Item> = ObjectId::new().into();
+        Item::new(id, "widget".parse().unwrap(), ItemSize::Small)
+    }
+
+    #[test]
+    fn entities_survive_both_formats() {
+        let item = item();
+        for format in [ExportFormat::Ndjson, ExportFormat::Ejson] {
+            let line = encode(&item, format).unwrap();
+            let decoded = decode::<Item>(&line).unwrap();
+            assert_eq!(to_document(&decoded).unwrap(), to_document(&item).unwrap());
+        }
+    }
+
+    #[test]
+    fn canonical_format_preserves_types() {
+        let line = encode(&item(), ExportFormat::Ejson).unwrap();
+        assert!(line.contains("\"$oid\""));
+    }
+
+    #[test]
+    fn invalid_records_are_rejected() {
+        let id = ObjectId::new().to_hex();
+        let empty_name = format!(r#"{{"_id":{{"$oid":"{id}"}},"name":"","size":"Small"}}"#);
+        let unknown_size = format!(r#"{{"_id":{{"$oid":"{id}"}},"name":"widget","size":"Huge"}}"#);
+        assert!(decode::<Item>(&empty_name).is_err());
+        assert!(decode::<Item>(&unknown_size).is_err());
+        assert!(decode::<Item>("[]").is_err());
+        assert!(decode::<Item>("not json").is_err());
+    }
+}
@@ -0,0 +1,123 @@
+use std::{
+    error::Error,
+    fmt::{Display, Formatter},
+    str::FromStr,
+};
+use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt, EnvFilter, Layer};
+
+/// The name services report themselves as when exporting traces.
+pub const SERVICE_NAME: &str = "mongo_repo";
+
+/// The format in which log events are written to stdout.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum LogFormat {
+    /// Human readable lines of text.
+    Text,
+    /// One JSON object per line, including the fields of every enclosing span.
+    Json,
+}
+
+/// An error indicating a log format could not be parsed from a string.
+#[derive(Debug, Clone)]
+pub struct InvalidLogFormatError;
+
+impl FromStr for LogFormat {
+    type Err = InvalidLogFormatError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(LogFormat::Text),
+            "json" => Ok(LogFormat::Json),
+            _ => Err(InvalidLogFormatError),
+        }
+    }
+}
+
+impl Error for InvalidLogFormatError {}
+
+impl Display for InvalidLogFormatError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "a log format must be one of 'text' or 'json'")
+    }
+}
+
+/// Keeps telemetry running; dropping it flushes any traces that have yet to be exported.
+pub struct TelemetryGuard {
+    #[cfg(feature = "otlp")]
+    tracer_provider: Option<opentelemetry_sdk::trace::SdkTracerProvider>,
+}
+
+impl Drop for TelemetryGuard {
+    fn drop(&mut self) {
+        #[cfg(feature = "otlp")]
+        if let Some(tracer_provider) = self.tracer_provider.take() {
+            if let Err(e) = tracer_provider.shutdown() {
+                eprintln!("error shutting down trace export: {e}");
+            }
+        }
+    }
+}
+
+/// Installs the global tracing subscriber. Events are filtered according to the `RUST_LOG`
+/// environment variable and written to stdout in the provided format.
+///
+/// # Arguments
+/// * `log_format` - the format to write log events in
+/// * `otlp_endpoint` - if provided, the address of an OTLP collector to export spans to via gRPC
+///
+/// # Returns
+/// a guard that must be kept alive for as long as telemetry should be recorded
+pub fn init(
+    log_format: LogFormat,
+    otlp_endpoint: Option<String>,
+) -> Result<TelemetryGuard, Box<dyn Error>> {
+    let fmt_layer = match log_format {
+        LogFormat::Text => tracing_subscriber::fmt::layer().boxed(),
+        LogFormat::Json => tracing_subscriber::fmt::layer()
+            .json()
+            .with_current_span(true)
+            .with_span_list(true)
+            .boxed(),
+    };
+    let registry = tracing_subscriber::registry()
+        .with(EnvFilter::from_default_env())
+        .with(fmt_layer);
+
+    #[cfg(feature = "otlp")]
+    {
+        use opentelemetry::trace::TracerProvider;
+        use opentelemetry_otlp::WithExportConfig;
+
+        let tracer_provider = otlp_endpoint
+            .map(|endpoint| {
+                let exporter = opentelemetry_otlp::SpanExporter::builder()
+                    .with_tonic()
+                    .with_endpoint(endpoint)
+                    .build()?;
+                let resource = opentelemetry_sdk::Resource::builder()
+                    .with_service_name(SERVICE_NAME)
+                    .build();
+                Ok::<_, Box<dyn Error>>(
+                    opentelemetry_sdk::trace::SdkTracerProvider::builder()
+                        .with_batch_exporter(exporter)
+                        .with_resource(resource)
+                        .build(),
+                )
+            })
+            .transpose()?;
+        let otlp_layer = tracer_provider.as_ref().map(|tracer_provider| {
+            tracing_opentelemetry::layer().with_tracer(tracer_provider.tracer(SERVICE_NAME))
+        });
+        registry.with(otlp_layer).try_init()?;
+        Ok(TelemetryGuard { tracer_provider })
+    }
+
+    #[cfg(not(feature = "otlp"))]
+    {
+        if otlp_endpoint.is_some() {
+            return Err("OTLP export requires the `otlp` feature".into());
+        }
+        registry.try_init()?;
+        Ok(TelemetryGuard {})
+    }
+}